@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, BenchmarkId};
 use rand::Rng;
-use spinout::Atom;
+use spinout::{Atom, RawLock, SeqAtom, SpinRwLock, SpinLock, SpinPark, BusySpinLock, TicketLock};
 use std::sync::{Arc, Mutex, RwLock};
 const UNSORTED_ARR: [i32; 20] = [9, 1, 8, 2, 7, 3, 6, 4, 5, 0, 9, 1, 42, 2, 7, 3, 6, 4, 5, 0];
 
@@ -189,6 +189,57 @@ macro_rules ! make_test_rand {
 	};
 }
 
+macro_rules ! make_test_locks {
+	($name:ident, $tcnt:expr, $modulo:expr, $multiplier:expr) => {
+		fn $name(c: &mut Criterion) {
+			let name = stringify!($name);
+			let mut group = c.benchmark_group(name);
+			for i in [1].iter() {
+				group.bench_with_input(BenchmarkId::new("SPINRWLOCK", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_rw_with($tcnt, *i * $multiplier, $modulo, SpinRwLock::new());
+						})
+					})
+				});
+
+				group.bench_with_input(BenchmarkId::new("SPINLOCK", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_rw_with($tcnt, *i * $multiplier, $modulo, SpinLock::new());
+						})
+					})
+				});
+
+				group.bench_with_input(BenchmarkId::new("SPINPARK", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_rw_with($tcnt, *i * $multiplier, $modulo, SpinPark::new());
+						})
+					})
+				});
+
+				group.bench_with_input(BenchmarkId::new("BUSYSPIN", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_rw_with($tcnt, *i * $multiplier, $modulo, BusySpinLock::new());
+						})
+					})
+				});
+
+				group.bench_with_input(BenchmarkId::new("TICKET", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_rw_with($tcnt, *i * $multiplier, $modulo, TicketLock::new());
+						})
+					})
+				});
+			}
+			group.finish();
+		}
+	};
+}
+
 macro_rules ! make_test_primes {
 	($name:ident, $tcnt:expr, $multiplier:expr) => {
 		fn $name(c: &mut Criterion) {
@@ -224,8 +275,81 @@ macro_rules ! make_test_primes {
 	};
 }
 
+macro_rules ! make_test_small_read {
+	($name:ident, $tcnt:expr, $modulo:expr, $multiplier:expr) => {
+		fn $name(c: &mut Criterion) {
+			let name = stringify!($name);
+			let mut group = c.benchmark_group(name);
+			for i in [1].iter() {
+				group.bench_with_input(BenchmarkId::new("SEQ_ATOM", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							seq_atom_test_small_read($tcnt, *i * $multiplier, $modulo);
+						})
+					})
+				});
+
+				group.bench_with_input(BenchmarkId::new("ATOM", $multiplier * i), &i, |b, _| {
+					b.iter(|| {
+						black_box({
+							atom_test_small_read($tcnt, *i * $multiplier, $modulo);
+						})
+					})
+				});
+			}
+			group.finish();
+		}
+	};
+}
+
+fn seq_atom_test_small_read(tcnt: usize, iters: usize, modulo: usize) {
+	let atom = SeqAtom::new((0u64, 0u64));
+
+	let mut threads = Vec::new();
+	for _ in 0..tcnt {
+		let tatom = atom.clone();
+		threads.push(std::thread::spawn(move || {
+			for i in 0..iters {
+				if i % modulo == 0 {
+					tatom.lock(|x| *x = (x.0 + 1, x.1 + 1));
+				}
+				let (a, b) = tatom.get();
+				assert_eq!(a, b);
+			}
+		}));
+	}
+	for thread in threads {
+		thread.join().unwrap();
+	}
+}
+
+fn atom_test_small_read(tcnt: usize, iters: usize, modulo: usize) {
+	let atom = Atom::new((0u64, 0u64));
+
+	let mut threads = Vec::new();
+	for _ in 0..tcnt {
+		let tatom = atom.clone();
+		threads.push(std::thread::spawn(move || {
+			for i in 0..iters {
+				if i % modulo == 0 {
+					tatom.lock(|x| *x = (x.0 + 1, x.1 + 1));
+				}
+				let (a, b) = tatom.get();
+				assert_eq!(a, b);
+			}
+		}));
+	}
+	for thread in threads {
+		thread.join().unwrap();
+	}
+}
+
 fn atom_test_rw(tcnt: usize, iters: usize, modulo: usize) {
-    let atom = Atom::new(UNSORTED_ARR.to_vec());
+	atom_test_rw_with(tcnt, iters, modulo, SpinRwLock::new());
+}
+
+fn atom_test_rw_with<L: RawLock + 'static>(tcnt: usize, iters: usize, modulo: usize, lock: L) {
+    let atom = Atom::with_lock(UNSORTED_ARR.to_vec(), lock);
 
     let mut threads = Vec::new();
     for _ in 0..tcnt {
@@ -238,12 +362,9 @@ fn atom_test_rw(tcnt: usize, iters: usize, modulo: usize) {
                         x.reverse();
                     });
                 }
-                tatom.lock(|x| {
-                    let y = x.get(0);
-                    match y {
-                        Some(fortytwo) => { assert_eq!(fortytwo, &42); },
-                        None => {},
-                    }
+                tatom.map(|x| {
+                    let y = x.first();
+                    if let Some(fortytwo) = y { assert_eq!(fortytwo, &42); }
                 });
             }
         }));
@@ -276,7 +397,8 @@ fn atom_test_random_lock(tcnt: usize, iters: usize) {
 }
 
 fn mutex_test_random_lock(tcnt: usize, iters: usize) {
-	let mutexes = vec![Arc::new(Mutex::new(0)); 3];
+	let mutex = Arc::new(Mutex::new(0));
+	let mutexes = vec![mutex; 3];
 
 	let mut threads = Vec::new();
 	for _ in 0..tcnt {
@@ -297,7 +419,8 @@ fn mutex_test_random_lock(tcnt: usize, iters: usize) {
 }
 
 fn rwlock_test_random_lock(tcnt: usize, iters: usize) {
-	let rwlocks = vec![Arc::new(RwLock::new(0)); 3];
+	let rwlock = Arc::new(RwLock::new(0));
+	let rwlocks = vec![rwlock; 3];
 
 	let mut threads = Vec::new();
 	for _ in 0..tcnt {
@@ -345,7 +468,7 @@ fn atom_test_r(tcnt: usize, iters: usize) {
 		let tatom = atom.clone();
 		threads.push(std::thread::spawn(move || {
 			for _ in 0..iters {
-				let y = tatom.map(|x| x.iter().find(|x| **x == 42).unwrap().clone());
+				let y = tatom.map(|x| *x.iter().find(|x| **x == 42).unwrap());
 				assert_eq!(y, 42);
 			}
 		}));
@@ -374,11 +497,8 @@ fn rwlock_test_rw(tcnt: usize, iters: usize, modulo: usize) {
 				}
 				match tarc.read() {
 					Ok(x) => {
-						let y = x.get(0);
-						match y {
-							Some(fortytwo) => { assert_eq!(fortytwo, &42); },
-							None => {},
-						}
+						let y = x.first();
+						if let Some(fortytwo) = y { assert_eq!(fortytwo, &42); }
 					},
 					Err(_) => panic!("lock failed"),
 				}
@@ -423,7 +543,7 @@ fn rwlock_test_r(tcnt: usize, iters: usize) {
 			for _ in 0..iters {
 				match tarc.read() {
 					Ok(x) => {
-						let y = x.iter().find(|x| **x == 42).unwrap().clone();
+						let y = *x.iter().find(|x| **x == 42).unwrap();
 						assert_eq!(y, 42);
 					},
 					Err(_) => panic!("lock failed"),
@@ -455,11 +575,8 @@ fn mutex_test_rw(tcnt: usize, iters: usize, modulo: usize) {
 				}
 				match tarc.lock() {
 					Ok(x) => {
-						let y = x.get(0);
-						match y {
-							Some(fortytwo) => { assert_eq!(fortytwo, &42); },
-							None => {},
-						}
+						let y = x.first();
+						if let Some(fortytwo) = y { assert_eq!(fortytwo, &42); }
 					},
 					Err(_) => panic!("lock failed"),
 				}
@@ -504,7 +621,7 @@ fn mutex_test_r(tcnt: usize, iters: usize) {
 			for _ in 0..iters {
 				match tarc.lock() {
 					Ok(x) => {
-						let y = x.iter().find(|x| **x == 42).unwrap().clone();
+						let y = *x.iter().find(|x| **x == 42).unwrap();
 						assert_eq!(y, 42);
 					},
 					Err(_) => panic!("lock failed"),
@@ -524,12 +641,12 @@ fn is_prime(n: u64) -> bool {
 	if n == 2 {
 		return true;
 	}
-	if n % 2 == 0 {
+	if n.is_multiple_of(2) {
 		return false;
 	}
 	let mut i = 3;
 	while i * i <= n {
-		if n % i == 0 {
+		if n.is_multiple_of(i) {
 			return false;
 		}
 		i += 2;
@@ -606,6 +723,8 @@ make_test_r!(t16_big_read_only, 16, 10_000);
 make_test_w!(t16_big_write_only, 16, 10_000);
 make_test_rand!(t16_big_rand, 32, 100);
 make_test_primes!(t8_primes, 8, 10000);
+make_test_locks!(t4_locks_read_heavy_rw, 4, 10, 10_000);
+make_test_small_read!(t8_small_read_heavy, 8, 100, 10_000);
 
 criterion_group!(benches,
 	t8_primes,
@@ -614,6 +733,8 @@ criterion_group!(benches,
 	t16_big_read_only,
 	t16_big_write_only,
 	t16_big_rand,
+	t4_locks_read_heavy_rw,
+	t8_small_read_heavy,
 );
 
 criterion_main!(benches);
\ No newline at end of file
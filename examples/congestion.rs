@@ -1,8 +1,8 @@
-use spinout::Atom;
+use spinout::{Atom, McsLock, RawLock, SpinLock, SpinPark, SpinRwLock, TicketLock};
 use rand::Rng;
 
-fn atom_test_random_lock(tcnt: usize, iters: usize) {
-    let counts = Atom::new(vec![0; tcnt]);
+fn atom_test_random_lock<L: RawLock + 'static>(name: &str, tcnt: usize, iters: usize, lock: L) {
+    let counts = Atom::with_lock(vec![0; tcnt], lock);
 	let pop_this = Atom::new(vec![0; iters]);
 
     let mut threads = Vec::new();
@@ -11,7 +11,7 @@ fn atom_test_random_lock(tcnt: usize, iters: usize) {
 		let tpop_this = pop_this.clone();
 		let tid = i;
         threads.push(std::thread::spawn(move || {
-            while let Some(_) = tpop_this.map_mut(|x| x.pop()) {
+            while tpop_this.map_mut(|x| x.pop()).is_some() {
 				tcounts.lock(|x| {
 					x[tid] += 1;
 					let nap_time = rand::thread_rng().gen_range(0..10);
@@ -25,12 +25,17 @@ fn atom_test_random_lock(tcnt: usize, iters: usize) {
     }
 
 	let counts = counts.get();
-	for i in 0..tcnt {
-		println!("Thread {} was incremented {} times", i, counts[i]);
+	println!("{}:", name);
+	for (i, count) in counts.iter().enumerate() {
+		println!("Thread {} was incremented {} times", i, count);
 	}
 	assert!(counts.iter().sum::<usize>() == iters);
 }
 
 fn main() {
-	atom_test_random_lock(4, 100_000);
-}
\ No newline at end of file
+	atom_test_random_lock("SpinRwLock", 4, 100_000, SpinRwLock::new());
+	atom_test_random_lock("SpinLock", 4, 100_000, SpinLock::new());
+	atom_test_random_lock("SpinPark", 4, 100_000, SpinPark::new());
+	atom_test_random_lock("TicketLock", 4, 100_000, TicketLock::new());
+	atom_test_random_lock("McsLock", 4, 100_000, McsLock::new());
+}
@@ -14,6 +14,7 @@ struct DoublyLinkedList {
 	tail: Option<Weak<DoublyLinkedNode>>,
 }
 
+#[allow(clippy::question_mark, clippy::wrong_self_convention)]
 impl DoublyLinkedList {
 	fn new() -> Self {
 		Self {
@@ -122,6 +123,7 @@ impl DoublyLinkedList {
 	}
 }
 
+#[allow(clippy::useless_vec)]
 fn main() {
 	let mut list = DoublyLinkedList::new();
 
@@ -7,12 +7,12 @@ fn is_prime(n: u64) -> bool {
 	if n == 2 {
 		return true;
 	}
-	if n % 2 == 0 {
+	if n.is_multiple_of(2) {
 		return false;
 	}
 	let mut i = 3;
 	while i * i <= n {
-		if n % i == 0 {
+		if n.is_multiple_of(i) {
 			return false;
 		}
 		i += 2;
@@ -1,9 +1,92 @@
 use super::*;
+use std::fmt;
 
-struct AtomInner<T: ?Sized> {
+/// The shared allocation of an `Atom` and its `Weak` references. The strong
+/// count is the number of `Atom`s, the weak count is the number of `Weak`s plus
+/// one that is collectively held by all the `Atom`s. The value is dropped when
+/// the strong count reaches zero, the allocation when the weak count does.
+struct AtomInner<T: ?Sized, L> {
 	count: (AtomicUsize, AtomicUsize),
-    lock: SpinLock,
-    data: UnsafeCell<T>,
+    lock: L,
+	poison: poison::Flag,
+	notify: notify::Notify,
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+/// Holds a shared lock of an `AtomInner` and releases it when dropped. Readers
+/// can't leave the value in an inconsistent state, so a panic while holding a
+/// shared lock doesn't poison the `AtomInner`.
+struct UnlockShared<'a, T: ?Sized, L: RawLock> {
+	inner: &'a AtomInner<T, L>,
+}
+
+impl<'a, T: ?Sized, L: RawLock> UnlockShared<'a, T, L> {
+	#[inline]
+	fn new(inner: &'a AtomInner<T, L>) -> Self {
+		lockdep::acquire(&inner.lock);
+		inner.lock.lock_shared();
+		Self::locked(inner)
+	}
+
+	/// Take over a shared lock of `inner` that is already held.
+	#[inline]
+	fn locked(inner: &'a AtomInner<T, L>) -> Self {
+		lockdep::acquired(&inner.lock);
+		UnlockShared { inner }
+	}
+
+	#[inline]
+	fn poisoned(&self) -> bool {
+		self.inner.poison.get()
+	}
+
+	#[inline]
+	fn data(&self) -> &T {
+		unsafe { &*self.inner.data.get() }
+	}
+}
+
+impl<T: ?Sized, L: RawLock> Drop for UnlockShared<'_, T, L> {
+	#[inline]
+	fn drop(&mut self) {
+		lockdep::released(&self.inner.lock);
+		self.inner.lock.unlock_shared();
+	}
+}
+
+/// Turn a timeout into a deadline. Returns `None` if the deadline would overflow
+/// `Instant`, which is treated as no deadline at all.
+#[inline]
+fn deadline(timeout: Duration) -> Option<Instant> {
+	Instant::now().checked_add(timeout)
+}
+
+#[inline]
+fn lock_timeout<L: RawLock>(lock: &L, timeout: Duration) -> bool {
+	match deadline(timeout) {
+		Some(deadline) => lock.lock_until(deadline),
+		None => {
+			lock.lock();
+			true
+		}
+	}
+}
+
+#[inline]
+fn lock_shared_timeout<L: RawLock>(lock: &L, timeout: Duration) -> bool {
+	match deadline(timeout) {
+		Some(deadline) => lock.lock_shared_until(deadline),
+		None => {
+			lock.lock_shared();
+			true
+		}
+	}
+}
+
+#[cold]
+#[inline(never)]
+fn panic_poisoned() -> ! {
+	panic!("Atom is poisoned: another thread panicked while holding the lock");
 }
 
 /// A thread-safe reference-counted mutabel pointer.
@@ -17,9 +100,9 @@ struct AtomInner<T: ?Sized> {
 /// let sum: i32 = atom.map(|v| v.iter().sum());
 /// assert_eq!(sum, 6);
 /// ```
-pub struct Atom<T: Send + ?Sized> {
-    inner: NonNull<AtomInner<T>>,
-	phantom: PhantomData<AtomInner<T>>,
+pub struct Atom<T: Send + ?Sized, L: RawLock = SpinRwLock> {
+    inner: NonNull<AtomInner<T, L>>,
+	phantom: PhantomData<AtomInner<T, L>>,
 }
 
 impl<T: Send> Atom<T> {
@@ -29,7 +112,8 @@ impl<T: Send> Atom<T> {
 	/// Unlike `Arc<Mutex<T>>`, `Atom<T>` does not use system futexes, but instead uses
 	/// a simple spin-lock. This can be advantageous in cases of low contention i.e.
 	/// when the lock is only held for a short time and there are few threads
-	/// competing for the lock.
+	/// competing for the lock. Reading operations like `map` and `get` share the
+	/// lock with each other, while `lock`, `map_mut` and `set` take it exclusively.
 	///
 	/// # Examples
 	///
@@ -40,10 +124,69 @@ impl<T: Send> Atom<T> {
 	/// ```
 	#[inline]
 	pub fn new(value: T) -> Self {
+		Self::with_lock(value, SpinRwLock::INIT)
+	}
+}
+
+#[cfg(feature = "registry")]
+impl<T: Send + Sync + 'static> Atom<T> {
+	/// Create a new `Atom<T>` registered under `name` in the `registry`, so that
+	/// `registry::dump` shows who holds its lock. The registry only keeps a `Weak`
+	/// reference to the `Atom`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{registry, Atom};
+	///
+	/// let orders = Atom::named("orders", Vec::<u32>::new());
+	/// assert!(registry::dump().locks.iter().any(|l| l.name == "orders"));
+	/// drop(orders);
+	/// assert!(!registry::dump().locks.iter().any(|l| l.name == "orders"));
+	/// ```
+	pub fn named(name: impl Into<String>, value: T) -> Self {
+		Self::named_with_lock(name, value, SpinRwLock::INIT)
+	}
+}
+
+#[cfg(feature = "registry")]
+impl<T: Send + Sync + 'static, L: RawLock + crate::registry::Inspect + 'static> Atom<T, L> {
+	/// Create a new named `Atom<T, L>` protected by the given lock, like
+	/// `Atom::named`.
+	pub fn named_with_lock(name: impl Into<String>, value: T, lock: L) -> Self {
+		let atom = Self::with_lock(value, lock);
+		let weak = atom.downgrade();
+		let lock = weak.raw_lock().unwrap();
+		lock.track();
+		crate::registry::register(name.into(), Box::new(move || weak.raw_lock().map(|lock| lock.inspect())));
+		atom
+	}
+}
+
+impl<T: Send, L: RawLock> Atom<T, L> {
+
+	/// Create a new `Atom<T, L>` with the given value, protected by the given lock
+	/// instead of the default `SpinRwLock`. The lock must be unlocked. Any type
+	/// implementing `RawLock` can be used, which allows picking the locking strategy
+	/// that suits each data structure best.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, SpinPark};
+	///
+	/// let atom = Atom::with_lock(5, SpinPark::new());
+	/// atom.lock(|x| *x += 5);
+	/// assert_eq!(atom.get(), 10);
+	/// ```
+	#[inline]
+	pub fn with_lock(value: T, lock: L) -> Self {
 		let inner = Box::new(AtomInner {
-			data: UnsafeCell::new(value),
+			data: UnsafeCell::new(ManuallyDrop::new(value)),
 			count: (AtomicUsize::new(1), AtomicUsize::new(1)),
-			lock: SpinLock::new(),
+			lock,
+			poison: poison::Flag::new(),
+			notify: notify::Notify::new(),
 		});
 		Atom {
 			inner: NonNull::new(Box::into_raw(inner)).unwrap(),
@@ -52,7 +195,7 @@ impl<T: Send> Atom<T> {
 	}
 
 	/// Get a copy of the value inside the `Atom<T>`. This is a blocking operation. If the
-	/// lock is held by another thread, this function will spin until the lock is released.
+	/// lock is held by a writer, this function will spin until the lock is released.
 	///
 	/// # Examples
 	///
@@ -89,6 +232,11 @@ impl<T: Send> Atom<T> {
 	/// operation. If the lock is held by another thread, this function will spin until the lock
 	/// is released.
 	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned, i.e. another thread panicked while holding
+	/// the lock. Use `checked_lock` to handle a poisoned `Atom<T>`.
+	///
 	/// # Examples
 	///
 	/// ```
@@ -100,15 +248,36 @@ impl<T: Send> Atom<T> {
 	/// ```
 	#[inline]
 	pub fn lock(&self, f: impl FnOnce(&mut T)) {
-		let inner = unsafe { self.inner.as_ref() };
-		inner.lock.lock();
-		f(unsafe { inner.data.get().as_mut().unwrap() });
-		inner.lock.unlock();
+		self.map_mut(f)
+	}
+
+	/// Lock the `Atom<T>` and apply the given function to the value inside, like `lock`.
+	/// If another thread panicked while holding the lock, the function is still applied
+	/// but a `PoisonError` is returned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// assert!(atom.checked_lock(|x| *x += 5).is_ok());
+	/// assert_eq!(atom.get(), 10);
+	/// ```
+	#[inline]
+	pub fn checked_lock(&self, f: impl FnOnce(&mut T)) -> LockResult<()> {
+		self.checked_map_mut(f)
 	}
 
 	/// Map a function over the value inside the `Atom<T>` and return the result.
-	/// This is a blocking operation. If the lock is held by another thread, this
-	/// function will spin until the lock is released.
+	/// The lock is shared with other readers. This is a blocking operation. If the
+	/// lock is held by a writer, or a writer is waiting for it, this function will
+	/// spin until the lock is released.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned. Use `checked_map` to handle a poisoned
+	/// `Atom<T>`.
 	///
 	/// # Examples
 	///
@@ -121,11 +290,37 @@ impl<T: Send> Atom<T> {
 	/// ```
 	#[inline]
 	pub fn map<U>(&self, f: impl FnOnce(&T) -> U) -> U {
-		let inner = unsafe { self.inner.as_ref() };
-		inner.lock.lock();
-		let data = f(unsafe { inner.data.get().as_mut().unwrap() });
-		inner.lock.unlock();
-		data
+		let guard = UnlockShared::new(unsafe { self.inner.as_ref() });
+		if guard.poisoned() {
+			panic_poisoned();
+		}
+		f(guard.data())
+	}
+
+	/// Map a function over the value inside the `Atom<T>` like `map`. If another
+	/// thread panicked while holding the lock, the result is wrapped in a
+	/// `PoisonError`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// let sum: i32 = atom.checked_map(|x| x.iter().sum()).unwrap();
+	/// assert_eq!(sum, 6);
+	/// ```
+	#[inline]
+	pub fn checked_map<U>(&self, f: impl FnOnce(&T) -> U) -> LockResult<U> {
+		let guard = UnlockShared::new(unsafe { self.inner.as_ref() });
+		let poisoned = guard.poisoned();
+		let data = f(guard.data());
+		drop(guard);
+		if poisoned {
+			Err(PoisonError::new(data))
+		} else {
+			Ok(data)
+		}
 	}
 
 	/// Map a function over the value inside the `Atom<T>` and return the result.
@@ -133,6 +328,11 @@ impl<T: Send> Atom<T> {
 	/// If the lock is held by another thread, this function will spin until the lock
 	/// is released.
 	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned. Use `checked_map_mut` to handle a poisoned
+	/// `Atom<T>`.
+	///
 	/// # Examples
 	///
 	/// ```
@@ -145,17 +345,77 @@ impl<T: Send> Atom<T> {
 	/// ```
 	#[inline]
 	pub fn map_mut<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
-		let inner = unsafe { self.inner.as_ref() };
-		inner.lock.lock();
-		let data = f(unsafe { inner.data.get().as_mut().unwrap() });
-		inner.lock.unlock();
-		data
+		f(&mut self.guard())
 	}
 
-	/// Downgrade the `Atom<T>` to a `Weak<T>`. This is a non-blocking operation.
-	/// The `Weak<T>` can be upgraded to an `Atom<T>` using the `upgrade` method.
-	/// If the `Atom<T>` is dropped, the `Weak<T>` will no longer be able to be upgraded and
-	/// will return `None`.
+	/// Map a function over the value inside the `Atom<T>` like `map_mut`. If another
+	/// thread panicked while holding the lock, the function is still applied and its
+	/// result is wrapped in a `PoisonError`, from which it can be recovered with
+	/// `PoisonError::into_inner`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// let t_atom = atom.clone();
+	/// let _ = std::thread::spawn(move || {
+	///     t_atom.lock(|x| {
+	///         x.clear();
+	///         panic!("crashed in the middle of an update");
+	///     });
+	/// }).join();
+	///
+	/// let len = atom.checked_map_mut(|x| x.len());
+	/// assert_eq!(len.unwrap_err().into_inner(), 0);
+	/// ```
+	#[inline]
+	pub fn checked_map_mut<U>(&self, f: impl FnOnce(&mut T) -> U) -> LockResult<U> {
+		let mut guard = AtomGuard::new(unsafe { self.inner.as_ref() });
+		let poisoned = guard.poisoned();
+		let data = f(&mut guard);
+		drop(guard);
+		if poisoned {
+			Err(PoisonError::new(data))
+		} else {
+			Ok(data)
+		}
+	}
+
+	/// Lock the `Atom<T>` and return a guard that gives access to the value inside.
+	/// The lock is held until the guard is dropped. This is a blocking operation. If
+	/// the lock is held by another thread, this function will spin until the lock is
+	/// released.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned. Use `checked_guard` to handle a poisoned
+	/// `Atom<T>`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// let mut guard = atom.guard();
+	/// guard.push(4);
+	/// assert_eq!(guard.len(), 4);
+	/// drop(guard);
+	/// assert_eq!(atom.get(), vec![1, 2, 3, 4]);
+	/// ```
+	#[inline]
+	pub fn guard(&self) -> AtomGuard<'_, T, L> {
+		let guard = AtomGuard::new(unsafe { self.inner.as_ref() });
+		if guard.poisoned() {
+			panic_poisoned();
+		}
+		guard
+	}
+
+	/// Lock the `Atom<T>` and return a guard like `guard`. If another thread panicked
+	/// while holding the lock, the guard is wrapped in a `PoisonError`.
 	///
 	/// # Examples
 	///
@@ -163,119 +423,979 @@ impl<T: Send> Atom<T> {
 	/// use spinout::Atom;
 	///
 	/// let atom = Atom::new(5);
-	/// let weak = atom.downgrade();
-	/// assert_eq!(atom.get(), weak.upgrade().unwrap().get());
+	/// let mut guard = atom.checked_guard().unwrap_or_else(|e| e.into_inner());
+	/// *guard += 1;
+	/// drop(guard);
+	/// assert_eq!(atom.get(), 6);
 	/// ```
 	#[inline]
-	pub fn downgrade(&self) -> Weak<T> {
-		let inner = unsafe { self.inner.as_ref() };
-		inner.count.1.fetch_add(1, Relaxed);
-		Weak {
-			inner: self.inner
+	pub fn checked_guard(&self) -> LockResult<AtomGuard<'_, T, L>> {
+		let guard = AtomGuard::new(unsafe { self.inner.as_ref() });
+		if guard.poisoned() {
+			Err(PoisonError::new(guard))
+		} else {
+			Ok(guard)
 		}
 	}
-}
 
-impl<T: Send> Clone for Atom<T> {
-	fn clone(&self) -> Self {
+	#[inline]
+	fn read_with(&self, blocking: bool, acquire: impl FnOnce(&L) -> bool) -> Option<UnlockShared<'_, T, L>> {
 		let inner = unsafe { self.inner.as_ref() };
-		if inner.count.0.fetch_add(1, SeqCst) == usize::MAX {
-			panic!("Atom count overflow");
+		if blocking {
+			lockdep::acquire(&inner.lock);
 		}
-		Atom {
-			inner: self.inner,
-			phantom: PhantomData,
+		if !acquire(&inner.lock) {
+			return None;
 		}
+		let guard = UnlockShared::locked(inner);
+		if guard.poisoned() {
+			panic_poisoned();
+		}
+		Some(guard)
 	}
-}
 
-impl<T: Send + ?Sized> Drop for Atom<T> {
-	fn drop(&mut self) {
+	#[inline]
+	fn write_with(&self, blocking: bool, acquire: impl FnOnce(&L) -> bool) -> Option<AtomGuard<'_, T, L>> {
 		let inner = unsafe { self.inner.as_ref() };
-		if inner.count.0.fetch_sub(1, SeqCst) == 1 {
-			unsafe { Box::from_raw(self.inner.as_ptr()) };
+		if blocking {
+			lockdep::acquire(&inner.lock);
+		}
+		if !acquire(&inner.lock) {
+			return None;
+		}
+		let guard = AtomGuard::locked(inner);
+		if guard.poisoned() {
+			panic_poisoned();
 		}
+		Some(guard)
 	}
-}
 
-unsafe impl<T: Send> Send for Atom<T> {}
-unsafe impl<T: Send> Sync for Atom<T> {}
+	/// Try to lock the `Atom<T>` without blocking and apply the given function to the
+	/// value inside. Returns `false` without calling the function if the lock is held
+	/// by another thread.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// assert!(atom.try_lock(|x| *x += 5));
+	/// let guard = atom.guard();
+	/// assert!(!atom.try_lock(|x| *x += 5));
+	/// drop(guard);
+	/// assert_eq!(atom.get(), 10);
+	/// ```
+	#[inline]
+	pub fn try_lock(&self, f: impl FnOnce(&mut T)) -> bool {
+		self.try_map_mut(f).is_some()
+	}
 
-/// A weak reference to an `Atom`. Weak references do not count towards the
-/// strong reference count, and will not prevent the value from being dropped.
-/// However, they may be upgraded to strong references. If the value has already
-/// been dropped, then an `Option::None` will be returned when attempting to
-/// upgrade.
-///
-/// # Examples
-///
-/// ```
-/// use spinout::{Atom, Weak};
-///
-/// let atom = Atom::new(3);
-/// let weak = atom.downgrade();
-/// let three = weak.upgrade().unwrap().get();
-/// assert_eq!(three, 3);
-/// ```
-pub struct Weak<T: Send + ?Sized> {
-	inner: NonNull<AtomInner<T>>
-}
+	/// Try to map a function over the value inside the `Atom<T>` without blocking.
+	/// Returns `None` without calling the function if the lock is held by a writer.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// assert_eq!(atom.try_map(|x| x.len()), Some(3));
+	/// let guard = atom.guard();
+	/// assert_eq!(atom.try_map(|x| x.len()), None);
+	/// drop(guard);
+	/// ```
+	#[inline]
+	pub fn try_map<U>(&self, f: impl FnOnce(&T) -> U) -> Option<U> {
+		self.read_with(false, L::try_lock_shared).map(|guard| f(guard.data()))
+	}
 
-impl<T: Send + ?Sized> Weak<T> {
-	/// Attempt to upgrade the `Weak` reference to a strong `Atom`. If the value
-	/// has already been dropped, then an `Option::None` will be returned.
-	/// Otherwise, an `Option::Some` will be returned containing the strong
-	/// reference.
+	/// Try to map a function over the value inside the `Atom<T>` without blocking,
+	/// allowing the value to be mutated. Returns `None` without calling the function
+	/// if the lock is held by another thread.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
 	///
 	/// # Examples
 	///
 	/// ```
-	/// use spinout::{Atom, Weak};
+	/// use spinout::Atom;
 	///
-	/// let atom = Atom::new(3);
-	/// let weak = atom.downgrade();
-	/// let three = weak.upgrade().unwrap().get();
-	/// assert_eq!(three, 3);
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// assert_eq!(atom.try_map_mut(|x| x.pop()), Some(Some(3)));
 	/// ```
-	pub fn upgrade(&self) -> Option<Atom<T>> {
-		let self_ref = unsafe { self.inner.as_ref() };
-		let count = self_ref.count.0.load(Acquire);
-		if count == 0 {
-			None
-		} else {
-			self_ref.count.0.fetch_add(1, AcqRel);
-			Some(Atom {
-				inner: self.inner,
-				phantom: PhantomData,
-			})
-		}
+	#[inline]
+	pub fn try_map_mut<U>(&self, f: impl FnOnce(&mut T) -> U) -> Option<U> {
+		self.write_with(false, L::try_lock).map(|mut guard| f(&mut guard))
 	}
-}
 
-impl<T: ?Sized + Send> Drop for Weak<T> {
-    fn drop(&mut self) {
-        // If we find out that we were the last weak pointer, then its time to
-        // deallocate the data entirely.
-        //
-        // It's not necessary to check for the locked state here, because the
-        // weak count can only be locked if there was precisely one weak ref,
-        // meaning that drop could only subsequently run ON that remaining weak
-        // ref, which can only happen after the lock is released.
-		let inner = unsafe { self.inner.as_ref() };
-        if inner.count.1.fetch_sub(1, Release) == 1 {
-			unsafe {
-				drop(Box::from_raw(self.inner.as_ptr()));
-			}
-        }
-    }
-}
+	/// Try to lock the `Atom<T>` without blocking and return a guard that gives
+	/// access to the value inside. Returns `None` if the lock is held by another
+	/// thread.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let guard = atom.try_guard().unwrap();
+	/// assert!(atom.try_guard().is_none());
+	/// drop(guard);
+	/// ```
+	#[inline]
+	pub fn try_guard(&self) -> Option<AtomGuard<'_, T, L>> {
+		self.write_with(false, L::try_lock)
+	}
 
-impl<T: ?Sized + Send> Clone for Weak<T> {
-	fn clone(&self) -> Weak<T> {
-		let inner = unsafe { self.inner.as_ref() };
-		inner.count.1.fetch_add(1, Relaxed);
-		Weak {
-			inner: self.inner
-		}
+	/// Lock the `Atom<T>` and apply the given function to the value inside, giving up
+	/// after the given timeout. Returns `false` without calling the function if the
+	/// lock couldn't be acquired in time.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	/// use std::time::Duration;
+	///
+	/// let atom = Atom::new(5);
+	/// let guard = atom.guard();
+	/// assert!(!atom.lock_timeout(Duration::from_millis(10), |x| *x += 5));
+	/// drop(guard);
+	/// assert!(atom.lock_timeout(Duration::from_millis(10), |x| *x += 5));
+	/// assert_eq!(atom.get(), 10);
+	/// ```
+	#[inline]
+	pub fn lock_timeout(&self, timeout: Duration, f: impl FnOnce(&mut T)) -> bool {
+		self.map_mut_timeout(timeout, f).is_some()
+	}
+
+	/// Map a function over the value inside the `Atom<T>`, giving up after the given
+	/// timeout. Returns `None` without calling the function if the lock couldn't be
+	/// acquired in time.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	/// use std::time::Duration;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// let len = atom.map_timeout(Duration::from_millis(10), |x| x.len());
+	/// assert_eq!(len, Some(3));
+	/// ```
+	#[inline]
+	pub fn map_timeout<U>(&self, timeout: Duration, f: impl FnOnce(&T) -> U) -> Option<U> {
+		self.read_with(true, |lock| lock_shared_timeout(lock, timeout)).map(|guard| f(guard.data()))
+	}
+
+	/// Map a function over the value inside the `Atom<T>`, allowing the value to be
+	/// mutated, giving up after the given timeout. Returns `None` without calling the
+	/// function if the lock couldn't be acquired in time.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	/// use std::time::Duration;
+	///
+	/// let atom = Atom::new(vec![1, 2, 3]);
+	/// let three = atom.map_mut_timeout(Duration::from_millis(10), |x| x.pop());
+	/// assert_eq!(three, Some(Some(3)));
+	/// ```
+	#[inline]
+	pub fn map_mut_timeout<U>(&self, timeout: Duration, f: impl FnOnce(&mut T) -> U) -> Option<U> {
+		self.write_with(true, |lock| lock_timeout(lock, timeout)).map(|mut guard| f(&mut guard))
+	}
+
+	/// Lock the `Atom<T>` and return a guard that gives access to the value inside,
+	/// giving up after the given timeout. Returns `None` if the lock couldn't be
+	/// acquired in time.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	/// use std::time::Duration;
+	///
+	/// let atom = Atom::new(5);
+	/// let guard = atom.guard_timeout(Duration::from_millis(10)).unwrap();
+	/// assert!(atom.guard_timeout(Duration::from_millis(10)).is_none());
+	/// drop(guard);
+	/// ```
+	#[inline]
+	pub fn guard_timeout(&self, timeout: Duration) -> Option<AtomGuard<'_, T, L>> {
+		self.write_with(true, |lock| lock_timeout(lock, timeout))
+	}
+
+	/// Wait until the given predicate holds for the value inside the `Atom<T>`, then
+	/// apply the given function to it while still holding the lock, and return the
+	/// result. While the predicate doesn't hold, the lock is released and the thread
+	/// sleeps until another thread calls `notify_one` or `notify_all`, after which the
+	/// predicate is checked again.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let queue = Atom::new(vec![]);
+	/// let t_queue = queue.clone();
+	/// let t = std::thread::spawn(move || {
+	///     t_queue.wait_until(|q| !q.is_empty(), |q| q.pop().unwrap())
+	/// });
+	///
+	/// queue.lock(|q| q.push(42));
+	/// queue.notify_one();
+	/// assert_eq!(t.join().unwrap(), 42);
+	/// ```
+	#[inline]
+	pub fn wait_until<U>(&self, pred: impl FnMut(&T) -> bool, f: impl FnOnce(&mut T) -> U) -> U {
+		self.wait_until_deadline(None, pred, f).unwrap()
+	}
+
+	/// Wait until the given predicate holds for the value inside the `Atom<T>` like
+	/// `wait_until`, giving up after the given timeout. Returns `None` without calling
+	/// the function if the predicate didn't hold in time.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Atom<T>` is poisoned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	/// use std::time::Duration;
+	///
+	/// let queue: Atom<Vec<i32>> = Atom::new(vec![]);
+	/// let x = queue.wait_until_timeout(Duration::from_millis(10), |q| !q.is_empty(), |q| q.pop());
+	/// assert_eq!(x, None);
+	/// ```
+	#[inline]
+	pub fn wait_until_timeout<U>(
+		&self,
+		timeout: Duration,
+		pred: impl FnMut(&T) -> bool,
+		f: impl FnOnce(&mut T) -> U,
+	) -> Option<U> {
+		self.wait_until_deadline(deadline(timeout), pred, f)
+	}
+
+	fn wait_until_deadline<U>(
+		&self,
+		deadline: Option<Instant>,
+		mut pred: impl FnMut(&T) -> bool,
+		f: impl FnOnce(&mut T) -> U,
+	) -> Option<U> {
+		let inner = unsafe { self.inner.as_ref() };
+		let acquire = |lock: &L| match deadline {
+			Some(deadline) => lock.lock_until(deadline),
+			None => {
+				lock.lock();
+				true
+			}
+		};
+		let mut guard = self.write_with(true, acquire)?;
+		loop {
+			if pred(&guard) {
+				return Some(f(&mut guard));
+			}
+			if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+				return None;
+			}
+			// Read the sequence number before releasing the lock, so that any change
+			// made after we checked the predicate also changes the sequence number.
+			let seq = inner.notify.prepare_wait();
+			guard.unlock_for_wait();
+			inner.notify.wait(seq, deadline);
+			guard = self.write_with(true, acquire)?;
+		}
+	}
+
+	/// Wake up one of the threads waiting in `wait_until` on this `Atom<T>`, so that it
+	/// checks its predicate again. Should be called after changing the value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(0);
+	/// atom.set(1);
+	/// atom.notify_one();
+	/// ```
+	#[inline]
+	pub fn notify_one(&self) {
+		unsafe { self.inner.as_ref() }.notify.notify_one();
+	}
+
+	/// Wake up all the threads waiting in `wait_until` on this `Atom<T>`, so that they
+	/// check their predicates again. Should be called after changing the value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let done = Atom::new(false);
+	/// let threads: Vec<_> = (0..4).map(|_| {
+	///     let t_done = done.clone();
+	///     std::thread::spawn(move || t_done.wait_until(|x| *x, |_| ()))
+	/// }).collect();
+	///
+	/// done.set(true);
+	/// done.notify_all();
+	/// for t in threads {
+	///     t.join().unwrap();
+	/// }
+	/// ```
+	#[inline]
+	pub fn notify_all(&self) {
+		unsafe { self.inner.as_ref() }.notify.notify_all();
+	}
+
+	/// Enable or disable automatic notification. When enabled, every operation that
+	/// takes the lock exclusively, like `lock`, `map_mut`, `set` or dropping an
+	/// `AtomGuard`, calls `notify_all` after releasing the lock. Automatic notification
+	/// is disabled by default.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let queue = Atom::new(vec![]);
+	/// queue.set_auto_notify(true);
+	/// let t_queue = queue.clone();
+	/// let t = std::thread::spawn(move || {
+	///     t_queue.wait_until(|q| q.len() == 2, |q| q.iter().sum::<i32>())
+	/// });
+	///
+	/// queue.lock(|q| q.push(1));
+	/// queue.lock(|q| q.push(2));
+	/// assert_eq!(t.join().unwrap(), 3);
+	/// ```
+	#[inline]
+	pub fn set_auto_notify(&self, enabled: bool) {
+		unsafe { self.inner.as_ref() }.notify.set_auto(enabled);
+	}
+
+	/// Returns `true` if the `Atom<T>` is poisoned, i.e. a thread panicked while
+	/// holding the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let t_atom = atom.clone();
+	/// let _ = std::thread::spawn(move || {
+	///     t_atom.lock(|_| panic!());
+	/// }).join();
+	/// assert!(atom.is_poisoned());
+	/// ```
+	#[inline]
+	pub fn is_poisoned(&self) -> bool {
+		unsafe { self.inner.as_ref() }.poison.get()
+	}
+
+	/// Clear the poisoned state of the `Atom<T>`. This should be called once the
+	/// value has been checked or repaired after a thread panicked while holding
+	/// the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let t_atom = atom.clone();
+	/// let _ = std::thread::spawn(move || {
+	///     t_atom.lock(|_| panic!());
+	/// }).join();
+	///
+	/// let _ = atom.checked_lock(|x| *x = 0);
+	/// atom.clear_poison();
+	/// assert!(!atom.is_poisoned());
+	/// assert_eq!(atom.get(), 0);
+	/// ```
+	#[inline]
+	pub fn clear_poison(&self) {
+		unsafe { self.inner.as_ref() }.poison.clear();
+	}
+
+	/// Consume the `Atom<T>` and return its value, if this is the last `Atom<T>`
+	/// pointing to it. Returns `None` otherwise, and the value is left to the other
+	/// `Atom`s, like `Arc::into_inner`. If the `Atom<T>` is poisoned, the value is
+	/// wrapped in a `PoisonError`, from which it can be recovered with
+	/// `PoisonError::into_inner`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(vec![1, 2]);
+	/// let t_atom = atom.clone();
+	/// let _ = std::thread::spawn(move || {
+	///     t_atom.lock(|x| {
+	///         x.push(3);
+	///         panic!();
+	///     });
+	/// }).join();
+	///
+	/// let value = atom.into_inner().unwrap().unwrap_or_else(|e| e.into_inner());
+	/// assert_eq!(value, vec![1, 2, 3]);
+	/// ```
+	pub fn into_inner(self) -> Option<LockResult<T>> {
+		let this = ManuallyDrop::new(self);
+		let inner = unsafe { this.inner.as_ref() };
+		if inner.count.0.fetch_sub(1, Release) != 1 {
+			return None;
+		}
+
+		// Like `drop`, the uses of the value by the other strong references happen
+		// before it's taken.
+		fence(Acquire);
+
+		let data = unsafe { ManuallyDrop::take(&mut *inner.data.get()) };
+		let poisoned = inner.poison.get();
+		drop(Weak { inner: this.inner });
+		if poisoned {
+			Some(Err(PoisonError::new(data)))
+		} else {
+			Some(Ok(data))
+		}
+	}
+
+	/// Downgrade the `Atom<T>` to a `Weak<T>`. This is a non-blocking operation.
+	/// The `Weak<T>` can be upgraded to an `Atom<T>` using the `upgrade` method.
+	/// If the `Atom<T>` is dropped, the `Weak<T>` will no longer be able to be upgraded and
+	/// will return `None`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let weak = atom.downgrade();
+	/// assert_eq!(atom.get(), weak.upgrade().unwrap().get());
+	/// ```
+	#[inline]
+	pub fn downgrade(&self) -> Weak<T, L> {
+		let inner = unsafe { self.inner.as_ref() };
+		if inner.count.1.fetch_add(1, Relaxed) > MAX_REFCOUNT {
+			panic!("Weak count overflow");
+		}
+		Weak {
+			inner: self.inner
+		}
+	}
+}
+
+impl<T: Send + ?Sized, L: RawLock> Atom<T, L> {
+	/// Get the number of strong references, i.e. `Atom`s, pointing to this value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let atom2 = atom.clone();
+	/// assert_eq!(atom.strong_count(), 2);
+	/// drop(atom2);
+	/// assert_eq!(atom.strong_count(), 1);
+	/// ```
+	#[inline]
+	pub fn strong_count(&self) -> usize {
+		unsafe { self.inner.as_ref() }.count.0.load(Acquire)
+	}
+
+	/// Get the number of `Weak` references pointing to this value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// let weak = atom.downgrade();
+	/// assert_eq!(atom.weak_count(), 1);
+	/// drop(weak);
+	/// assert_eq!(atom.weak_count(), 0);
+	/// ```
+	#[inline]
+	pub fn weak_count(&self) -> usize {
+		// Subtract the weak reference held by the strong references. As long as
+		// `self` is alive that reference exists too.
+		unsafe { self.inner.as_ref() }.count.1.load(Acquire) - 1
+	}
+
+	/// The address of the shared allocation, which orders the locks taken by
+	/// `lock_all` and `lock_slice`.
+	#[inline]
+	pub(crate) fn addr(&self) -> usize {
+		self.inner.as_ptr() as *const u8 as usize
+	}
+
+	/// The lock of the `Atom`, for `stm` to lock the `TVar`s of a commit together.
+	#[inline]
+	pub(crate) fn raw(&self) -> &L {
+		&unsafe { self.inner.as_ref() }.lock
+	}
+
+	/// A pointer to the value, for `stm` to read and write while holding `raw`.
+	#[inline]
+	pub(crate) fn data_ptr(&self) -> *mut T {
+		// `ManuallyDrop<T>` has the same layout as `T`.
+		unsafe { self.inner.as_ref() }.data.get() as *mut T
+	}
+
+	/// A snapshot of the contention statistics of the lock of the `Atom`, with the
+	/// `stats` feature. Returns `None` if the lock doesn't record any.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(5);
+	/// atom.set(6);
+	/// assert_eq!(atom.get(), 6);
+	/// assert_eq!(atom.stats().unwrap().acquisitions, 2);
+	/// ```
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn stats(&self) -> Option<LockStats> {
+		unsafe { self.inner.as_ref() }.lock.stats()
+	}
+
+	/// Reset the contention statistics of the lock of the `Atom` to zero.
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn reset_stats(&self) {
+		unsafe { self.inner.as_ref() }.lock.reset_stats();
+	}
+}
+
+/// Maximum reference count before we consider the count to be overflowing. Like
+/// `Arc`, we leave plenty of headroom so that racing increments can't wrap the
+/// counter before one of them notices.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+impl<T: Send, L: RawLock> Clone for Atom<T, L> {
+	fn clone(&self) -> Self {
+		let inner = unsafe { self.inner.as_ref() };
+		// A new reference can only be made from an existing one, so relaxed is
+		// enough here, see `Arc::clone`.
+		if inner.count.0.fetch_add(1, Relaxed) > MAX_REFCOUNT {
+			panic!("Atom count overflow");
+		}
+		Atom {
+			inner: self.inner,
+			phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Send + ?Sized, L: RawLock> Drop for Atom<T, L> {
+	fn drop(&mut self) {
+		let inner = unsafe { self.inner.as_ref() };
+		if inner.count.0.fetch_sub(1, Release) != 1 {
+			return;
+		}
+
+		// Synchronize with the `Release` decrements of the other strong references
+		// so that all their uses of the value happen before it's dropped.
+		fence(Acquire);
+
+		// Drop the value but keep the allocation alive for any remaining `Weak`s.
+		unsafe { ManuallyDrop::drop(&mut *inner.data.get()) };
+
+		// Release the weak reference collectively held by the strong references,
+		// which deallocates `AtomInner` if there are no `Weak`s left.
+		drop(Weak { inner: self.inner });
+	}
+}
+
+// Like `Arc<RwLock<T>>`, readers on different threads share a `&T`, so `T` must
+// also be `Sync`.
+unsafe impl<T: Send + Sync, L: RawLock> Send for Atom<T, L> {}
+unsafe impl<T: Send + Sync, L: RawLock> Sync for Atom<T, L> {}
+
+impl<T: Send + ?Sized, L: RawLock> UnwindSafe for Atom<T, L> {}
+impl<T: Send + ?Sized, L: RawLock> RefUnwindSafe for Atom<T, L> {}
+
+/// A weak reference to an `Atom`. Weak references do not count towards the
+/// strong reference count, and will not prevent the value from being dropped.
+/// However, they may be upgraded to strong references. If the value has already
+/// been dropped, then an `Option::None` will be returned when attempting to
+/// upgrade.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{Atom, Weak};
+///
+/// let atom = Atom::new(3);
+/// let weak = atom.downgrade();
+/// let three = weak.upgrade().unwrap().get();
+/// assert_eq!(three, 3);
+/// ```
+pub struct Weak<T: Send + ?Sized, L: RawLock = SpinRwLock> {
+	inner: NonNull<AtomInner<T, L>>
+}
+
+impl<T: Send + ?Sized, L: RawLock> Weak<T, L> {
+	/// Attempt to upgrade the `Weak` reference to a strong `Atom`. If the value
+	/// has already been dropped, then an `Option::None` will be returned.
+	/// Otherwise, an `Option::Some` will be returned containing the strong
+	/// reference.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, Weak};
+	///
+	/// let atom = Atom::new(3);
+	/// let weak = atom.downgrade();
+	/// let three = weak.upgrade().unwrap().get();
+	/// assert_eq!(three, 3);
+	/// ```
+	pub fn upgrade(&self) -> Option<Atom<T, L>> {
+		let inner = unsafe { self.inner.as_ref() };
+		// Never increment the strong count from zero: once it has reached zero
+		// the value is being, or has been, dropped.
+		let mut count = inner.count.0.load(Relaxed);
+		loop {
+			if count == 0 {
+				return None;
+			}
+			if count > MAX_REFCOUNT {
+				panic!("Atom count overflow");
+			}
+			match inner.count.0.compare_exchange_weak(count, count + 1, Acquire, Relaxed) {
+				Ok(_) => return Some(Atom {
+					inner: self.inner,
+					phantom: PhantomData,
+				}),
+				Err(old) => count = old,
+			}
+		}
+	}
+
+	/// Get the number of strong references, i.e. `Atom`s, pointing to the value.
+	/// Returns zero if the value has already been dropped.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Atom;
+	///
+	/// let atom = Atom::new(3);
+	/// let weak = atom.downgrade();
+	/// assert_eq!(weak.strong_count(), 1);
+	/// drop(atom);
+	/// assert_eq!(weak.strong_count(), 0);
+	/// ```
+	#[inline]
+	pub fn strong_count(&self) -> usize {
+		unsafe { self.inner.as_ref() }.count.0.load(Acquire)
+	}
+
+	/// The lock of the `Atom`, if the value hasn't been dropped yet. The allocation,
+	/// and so the lock, lives as long as the `Weak`.
+	#[cfg(feature = "registry")]
+	fn raw_lock(&self) -> Option<&L> {
+		let inner = unsafe { self.inner.as_ref() };
+		(inner.count.0.load(Acquire) > 0).then_some(&inner.lock)
+	}
+}
+
+impl<T: ?Sized + Send, L: RawLock> Drop for Weak<T, L> {
+	fn drop(&mut self) {
+		// If we were the last weak reference, including the one held by the
+		// strong references, the value has already been dropped and it's time
+		// to deallocate `AtomInner`.
+		let inner = unsafe { self.inner.as_ref() };
+		if inner.count.1.fetch_sub(1, Release) == 1 {
+			fence(Acquire);
+			lockdep::forget(&inner.lock);
+			unsafe {
+				drop(Box::from_raw(self.inner.as_ptr()));
+			}
+		}
+	}
+}
+
+impl<T: ?Sized + Send, L: RawLock> Clone for Weak<T, L> {
+	fn clone(&self) -> Weak<T, L> {
+		let inner = unsafe { self.inner.as_ref() };
+		if inner.count.1.fetch_add(1, Relaxed) > MAX_REFCOUNT {
+			panic!("Weak count overflow");
+		}
+		Weak {
+			inner: self.inner
+		}
+	}
+}
+
+unsafe impl<T: Send + Sync + ?Sized, L: RawLock> Send for Weak<T, L> {}
+unsafe impl<T: Send + Sync + ?Sized, L: RawLock> Sync for Weak<T, L> {}
+
+/// A guard holding the exclusive lock of an `Atom`, returned by `Atom::guard`. The
+/// value inside can be accessed through `Deref` and `DerefMut`, and the lock is
+/// released when the guard is dropped, also when the stack is unwinding. A panic
+/// while the guard is held poisons the `Atom`.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::Atom;
+///
+/// fn first_even(atom: &Atom<Vec<i32>>) -> Option<i32> {
+///     let guard = atom.guard();
+///     let x = *guard.iter().find(|x| *x % 2 == 0)?;
+///     Some(x)
+/// }
+///
+/// let atom = Atom::new(vec![1, 2, 3]);
+/// assert_eq!(first_even(&atom), Some(2));
+/// ```
+pub struct AtomGuard<'a, T: ?Sized, L: RawLock = SpinRwLock> {
+	inner: &'a AtomInner<T, L>,
+	poison: poison::Guard,
+}
+
+impl<'a, T: ?Sized, L: RawLock> AtomGuard<'a, T, L> {
+	#[inline]
+	fn new(inner: &'a AtomInner<T, L>) -> Self {
+		lockdep::acquire(&inner.lock);
+		inner.lock.lock();
+		Self::locked(inner)
+	}
+
+	/// Take over the exclusive lock of `inner` that is already held.
+	#[inline]
+	fn locked(inner: &'a AtomInner<T, L>) -> Self {
+		lockdep::acquired(&inner.lock);
+		AtomGuard {
+			inner,
+			poison: inner.poison.guard(),
+		}
+	}
+
+	#[inline]
+	fn poisoned(&self) -> bool {
+		self.inner.poison.get()
+	}
+
+	/// Release the lock without automatic notification. The waiter only looked at
+	/// the value, so waking up the other waiters would only make them spin.
+	#[inline]
+	fn unlock_for_wait(self) {
+		let this = ManuallyDrop::new(self);
+		this.inner.poison.done(&this.poison);
+		lockdep::released(&this.inner.lock);
+		this.inner.lock.unlock();
+	}
+
+	/// Project the guard to a part of the locked value, e.g. a field of a struct.
+	/// The returned `MappedAtomGuard` keeps the same lock held.
+	///
+	/// This is an associated function that needs to be used as `AtomGuard::map(...)`,
+	/// so that it doesn't conflict with a method on the value inside.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, AtomGuard};
+	///
+	/// let atom = Atom::new((1, String::from("one")));
+	/// let mut name = AtomGuard::map(atom.guard(), |x| &mut x.1);
+	/// name.push_str(" and two");
+	/// drop(name);
+	/// assert_eq!(atom.get().1, "one and two");
+	/// ```
+	#[inline]
+	pub fn map<U: ?Sized>(orig: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedAtomGuard<'a, U, L> {
+		// If `f` panics, `orig` is dropped normally, which releases the lock.
+		let data = NonNull::from(f(unsafe { &mut *orig.inner.data.get() }));
+		let orig = ManuallyDrop::new(orig);
+		MappedAtomGuard {
+			lock: &orig.inner.lock,
+			poison_flag: &orig.inner.poison,
+			notify: &orig.inner.notify,
+			poison: orig.poison,
+			data,
+			phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: ?Sized, L: RawLock> Deref for AtomGuard<'_, T, L> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		unsafe { &*self.inner.data.get() }
+	}
+}
+
+impl<T: ?Sized, L: RawLock> DerefMut for AtomGuard<'_, T, L> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.inner.data.get() }
+	}
+}
+
+impl<T: ?Sized, L: RawLock> Drop for AtomGuard<'_, T, L> {
+	#[inline]
+	fn drop(&mut self) {
+		self.inner.poison.done(&self.poison);
+		lockdep::released(&self.inner.lock);
+		self.inner.lock.unlock();
+		self.inner.notify.after_unlock();
+	}
+}
+
+impl<T: ?Sized + fmt::Debug, L: RawLock> fmt::Debug for AtomGuard<'_, T, L> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Debug::fmt(&**self, f)
+	}
+}
+
+unsafe impl<T: ?Sized + Sync, L: RawLock> Sync for AtomGuard<'_, T, L> {}
+
+unsafe impl<T: ?Sized, L: RawLock> CondvarLock for AtomGuard<'_, T, L> {
+	type Lock = L;
+
+	#[inline]
+	unsafe fn raw_lock(&self) -> &L {
+		&self.inner.lock
+	}
+}
+
+/// A guard holding the exclusive lock of an `Atom` that gives access to a part of
+/// the locked value, returned by `AtomGuard::map`. The lock is released when the
+/// guard is dropped.
+pub struct MappedAtomGuard<'a, T: ?Sized, L: RawLock = SpinRwLock> {
+	lock: &'a L,
+	poison_flag: &'a poison::Flag,
+	notify: &'a notify::Notify,
+	poison: poison::Guard,
+	data: NonNull<T>,
+	phantom: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: ?Sized, L: RawLock> MappedAtomGuard<'a, T, L> {
+	/// Project the guard further to a part of the value it gives access to. The
+	/// returned `MappedAtomGuard` keeps the same lock held.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, AtomGuard, MappedAtomGuard};
+	///
+	/// let atom = Atom::new((1, (2, 3)));
+	/// let pair = AtomGuard::map(atom.guard(), |x| &mut x.1);
+	/// let mut three = MappedAtomGuard::map(pair, |x| &mut x.1);
+	/// *three += 1;
+	/// drop(three);
+	/// assert_eq!(atom.get(), (1, (2, 4)));
+	/// ```
+	#[inline]
+	pub fn map<U: ?Sized>(mut orig: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedAtomGuard<'a, U, L> {
+		let data = NonNull::from(f(unsafe { orig.data.as_mut() }));
+		let orig = ManuallyDrop::new(orig);
+		MappedAtomGuard {
+			lock: orig.lock,
+			poison_flag: orig.poison_flag,
+			notify: orig.notify,
+			poison: orig.poison,
+			data,
+			phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: ?Sized, L: RawLock> Deref for MappedAtomGuard<'_, T, L> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		unsafe { self.data.as_ref() }
+	}
+}
+
+impl<T: ?Sized, L: RawLock> DerefMut for MappedAtomGuard<'_, T, L> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { self.data.as_mut() }
+	}
+}
+
+impl<T: ?Sized, L: RawLock> Drop for MappedAtomGuard<'_, T, L> {
+	#[inline]
+	fn drop(&mut self) {
+		self.poison_flag.done(&self.poison);
+		lockdep::released(self.lock);
+		self.lock.unlock();
+		self.notify.after_unlock();
+	}
+}
+
+impl<T: ?Sized + fmt::Debug, L: RawLock> fmt::Debug for MappedAtomGuard<'_, T, L> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Debug::fmt(&**self, f)
+	}
+}
+
+unsafe impl<T: ?Sized + Sync, L: RawLock> Sync for MappedAtomGuard<'_, T, L> {}
+
+unsafe impl<T: ?Sized, L: RawLock> CondvarLock for MappedAtomGuard<'_, T, L> {
+	type Lock = L;
+
+	#[inline]
+	unsafe fn raw_lock(&self) -> &L {
+		self.lock
 	}
 }
@@ -0,0 +1,174 @@
+use super::*;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::AtomicPtr;
+use crate::epoch;
+use crate::raw_lock::Unlock;
+
+struct SnapshotInner<T> {
+	current: AtomicPtr<T>,
+	/// Taken by writers, so that `rcu` sees the value it replaces.
+	lock: SpinLock,
+}
+
+impl<T> Drop for SnapshotInner<T> {
+	fn drop(&mut self) {
+		// Snapshots borrow an `AtomSnapshot`, so there are none left.
+		drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+	}
+}
+
+/// A thread-safe reference-counted pointer to a value that is read all the time and
+/// replaced now and then, like a routing table.
+///
+/// `load` returns a `Snapshot` of the current value without taking any lock or
+/// writing to the value: readers never wait, not even for each other. Writers swap
+/// in a new value with `store` or `rcu`, and the old value is dropped once no reader
+/// holds a snapshot of it.
+///
+/// Old values are reclaimed by epochs: a reader marks its thread as reading while it
+/// holds snapshots, and writers drop the old values that were replaced before every
+/// thread that is reading now started. A thread that holds on to a snapshot for long
+/// keeps every value replaced meanwhile alive, in all `AtomSnapshot`s.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::AtomSnapshot;
+/// use std::collections::HashMap;
+///
+/// let routes = AtomSnapshot::new(HashMap::from([("/", 1)]));
+/// let snapshot = routes.load();
+/// routes.rcu(|old| {
+///     let mut new = old.clone();
+///     new.insert("/about", 2);
+///     new
+/// });
+/// // The snapshot still shows the value it was taken of.
+/// assert_eq!(snapshot.len(), 1);
+/// assert_eq!(routes.load().len(), 2);
+/// ```
+pub struct AtomSnapshot<T> {
+	inner: Arc<SnapshotInner<T>>,
+}
+
+impl<T: Send + Sync + 'static> AtomSnapshot<T> {
+	/// Create a new `AtomSnapshot` with the given value.
+	#[inline]
+	pub fn new(value: T) -> Self {
+		AtomSnapshot {
+			inner: Arc::new(SnapshotInner {
+				current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+				lock: SpinLock::new(),
+			}),
+		}
+	}
+
+	/// Take a snapshot of the current value. The value isn't dropped while the
+	/// snapshot is alive, even if it is replaced.
+	#[inline]
+	pub fn load(&self) -> Snapshot<'_, T> {
+		let guard = epoch::pin();
+		let value = self.inner.current.load(Acquire);
+		Snapshot {
+			value: unsafe { &*value },
+			_guard: guard,
+		}
+	}
+
+	/// Return a clone of the current value.
+	#[inline]
+	pub fn get(&self) -> T where T: Clone {
+		T::clone(&self.load())
+	}
+
+	/// Replace the value. The old one is dropped once no snapshot of it is left.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::AtomSnapshot;
+	///
+	/// let config = AtomSnapshot::new(String::from("v1"));
+	/// config.store(String::from("v2"));
+	/// assert_eq!(*config.load(), "v2");
+	/// ```
+	pub fn store(&self, value: T) {
+		self.rcu(|_| value);
+	}
+
+	/// Replace the value with the result of the given function, called with the
+	/// current value. Writers are serialized, so no other write happens in between.
+	pub fn rcu(&self, f: impl FnOnce(&T) -> T) {
+		let inner = &*self.inner;
+		inner.lock.lock();
+		let unlock = Unlock(&inner.lock);
+		// Only writers retire values, so the current one lives while the lock is held.
+		let old = inner.current.load(Acquire);
+		let new = Box::into_raw(Box::new(f(unsafe { &*old })));
+		inner.current.store(new, Release);
+		drop(unlock);
+		let old = SendPtr(old);
+		epoch::retire(Box::new(move || drop(unsafe { Box::from_raw(old.into_inner()) })));
+		epoch::collect();
+	}
+}
+
+/// A pointer to a retired value, which is dropped on whichever thread reclaims it.
+struct SendPtr<T>(*mut T);
+
+impl<T> SendPtr<T> {
+	fn into_inner(self) -> *mut T {
+		self.0
+	}
+}
+
+unsafe impl<T: Send> Send for SendPtr<T> {}
+
+impl<T> Clone for AtomSnapshot<T> {
+	fn clone(&self) -> Self {
+		AtomSnapshot {
+			inner: self.inner.clone(),
+		}
+	}
+}
+
+impl<T: Send + Sync + 'static + Default> Default for AtomSnapshot<T> {
+	fn default() -> Self {
+		Self::new(T::default())
+	}
+}
+
+impl<T: Send + Sync + 'static + fmt::Debug> fmt::Debug for AtomSnapshot<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("AtomSnapshot").field(&&*self.load()).finish()
+	}
+}
+
+// Readers on different threads share a `&T`, and the value is dropped on whichever
+// thread reclaims it.
+unsafe impl<T: Send + Sync> Send for AtomSnapshot<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomSnapshot<T> {}
+
+/// A snapshot of the value of an `AtomSnapshot`, returned by `AtomSnapshot::load`.
+/// It derefs to the value as it was when the snapshot was taken. The snapshot can't
+/// be sent to another thread, as it keeps its own thread marked as reading.
+pub struct Snapshot<'a, T> {
+	value: &'a T,
+	_guard: epoch::Guard,
+}
+
+impl<T> Deref for Snapshot<'_, T> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		self.value
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for Snapshot<'_, T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Debug::fmt(self.value, f)
+	}
+}
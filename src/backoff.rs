@@ -0,0 +1,381 @@
+//! Strategies for waiting for a lock that is held by another thread, before going
+//! to sleep on it. `SpinLock` and `SpinPark` can be built with any of them, so the
+//! waiting can be tuned to how long the lock is usually held.
+//!
+//! # Examples
+//!
+//! ```
+//! use spinout::{Atom, SpinPark};
+//! use spinout::backoff::SpinThenPark;
+//!
+//! let atom = Atom::with_lock(vec![1, 2, 3], SpinPark::with_backoff(SpinThenPark::new(1000)));
+//! atom.lock(|x| x.push(4));
+//! assert_eq!(atom.get(), vec![1, 2, 3, 4]);
+//! ```
+
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering::Relaxed};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use crate::fairness::WaitTimer;
+
+/// A strategy for waiting for a lock that is held by another thread.
+///
+/// While the lock is held, and no other thread is asleep waiting for it, the lock
+/// calls `backoff` before every new look at it. `step` counts the calls since the
+/// thread started waiting, or was last woken up, starting from 0. The strategy
+/// waits for a moment and returns `true` to look at the lock again, or returns
+/// `false` to go to sleep until the lock is released.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::SpinLock;
+/// use spinout::backoff::Backoff;
+///
+/// /// Go to sleep right away.
+/// struct Park;
+///
+/// impl Backoff for Park {
+///     const DEFAULT: Self = Park;
+///
+///     fn backoff(&self, _step: u32) -> bool {
+///         false
+///     }
+/// }
+///
+/// let lock = SpinLock::with_backoff(Park);
+/// lock.lock();
+/// lock.unlock();
+/// ```
+pub trait Backoff: Send + Sync {
+	/// The strategy of a lock created with `RawLock::INIT` or `Default`.
+	const DEFAULT: Self;
+
+	/// Wait before looking at the lock again. Returns `false` if the thread should
+	/// go to sleep instead.
+	fn backoff(&self, step: u32) -> bool;
+
+	/// Called when a thread that found the lock held is done with its first round
+	/// of waiting with `backoff`. `acquired` tells if it got the lock, or has to go
+	/// to sleep.
+	#[inline]
+	fn spun(&self, acquired: bool) {
+		let _ = acquired;
+	}
+
+	/// Called after the lock has been acquired, by the thread holding it.
+	#[inline]
+	fn locked(&self) {}
+
+	/// Called before the lock is released, by the thread holding it.
+	#[inline]
+	fn unlocking(&self) {}
+}
+
+/// Spin with `std::hint::spin_loop`, without ever going to sleep while the holder
+/// of the lock is running. Suits locks that are only held for a handful of
+/// instructions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spin;
+
+impl Backoff for Spin {
+	const DEFAULT: Self = Spin;
+
+	#[inline]
+	fn backoff(&self, _step: u32) -> bool {
+		spin_loop();
+		true
+	}
+}
+
+/// Spin with `std::hint::spin_loop` for twice as long on every step, up to `cap`
+/// spins per step, without ever going to sleep while the holder of the lock is
+/// running. Spinning for longer takes the waiters off the cache line of the lock
+/// when there are many of them.
+#[derive(Clone, Copy, Debug)]
+pub struct Exponential {
+	cap: u32,
+}
+
+impl Exponential {
+	/// Create an `Exponential` backoff that spins at most `cap` times per step.
+	#[inline]
+	pub const fn new(cap: u32) -> Self {
+		Exponential { cap }
+	}
+}
+
+impl Default for Exponential {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl Backoff for Exponential {
+	const DEFAULT: Self = Exponential::new(1 << 10);
+
+	#[inline]
+	fn backoff(&self, step: u32) -> bool {
+		for _ in 0..1u32.checked_shl(step).unwrap_or(u32::MAX).min(self.cap) {
+			spin_loop();
+		}
+		true
+	}
+}
+
+/// Give up the time slice with `std::thread::yield_now` up to `limit` times, and
+/// then go to sleep. This is the strategy of `SpinPark`, with a limit of 100.
+#[derive(Clone, Copy, Debug)]
+pub struct Yield {
+	limit: u32,
+}
+
+impl Yield {
+	/// Create a `Yield` backoff that yields `limit` times before going to sleep.
+	#[inline]
+	pub const fn new(limit: u32) -> Self {
+		Yield { limit }
+	}
+}
+
+impl Default for Yield {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl Backoff for Yield {
+	const DEFAULT: Self = Yield::new(100);
+
+	#[inline]
+	fn backoff(&self, step: u32) -> bool {
+		if step >= self.limit {
+			return false;
+		}
+		std::thread::yield_now();
+		true
+	}
+}
+
+/// Sleep for `duration` up to `limit` times with `std::thread::sleep`, and then go
+/// to sleep on the lock. This is the strategy of `SpinLock`, with a single sleep
+/// of 1ns, which is mostly the cost of the system call.
+#[derive(Clone, Copy, Debug)]
+pub struct Sleep {
+	duration: Duration,
+	limit: u32,
+}
+
+impl Sleep {
+	/// Create a `Sleep` backoff that sleeps `limit` times for `duration`.
+	#[inline]
+	pub const fn new(duration: Duration, limit: u32) -> Self {
+		Sleep { duration, limit }
+	}
+}
+
+impl Default for Sleep {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl Backoff for Sleep {
+	const DEFAULT: Self = Sleep::new(Duration::from_nanos(1), 1);
+
+	#[inline]
+	fn backoff(&self, step: u32) -> bool {
+		if step >= self.limit {
+			return false;
+		}
+		std::thread::sleep(self.duration);
+		true
+	}
+}
+
+/// Spin with `std::hint::spin_loop` up to `spins` times, and then go to sleep.
+#[derive(Clone, Copy, Debug)]
+pub struct SpinThenPark {
+	spins: u32,
+}
+
+impl SpinThenPark {
+	/// Create a `SpinThenPark` backoff that spins `spins` times before going to
+	/// sleep.
+	#[inline]
+	pub const fn new(spins: u32) -> Self {
+		SpinThenPark { spins }
+	}
+}
+
+impl Default for SpinThenPark {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl Backoff for SpinThenPark {
+	const DEFAULT: Self = SpinThenPark::new(100);
+
+	#[inline]
+	fn backoff(&self, step: u32) -> bool {
+		if step >= self.spins {
+			return false;
+		}
+		spin_loop();
+		true
+	}
+}
+
+/// The fewest and most spins `Adaptive` sets its budget to, unless it skips
+/// spinning.
+const MIN_SPINS: u32 = 16;
+const MAX_SPINS: u32 = 1 << 14;
+
+/// After this many waits in a row that went to sleep despite spinning, `Adaptive`
+/// stops spinning, except for one wait in every `PROBE_EVERY`.
+const SKIP_AFTER: u32 = 4;
+const PROBE_EVERY: u32 = 16;
+
+/// One in this many acquisitions is timed.
+const SAMPLE_EVERY: u32 = 8;
+
+/// Spin with `std::hint::spin_loop` for a budget that is learned from the lock.
+///
+/// The strategy keeps a moving average of how long the lock is held, timing one in
+/// every 8 acquisitions, and never spins for much longer than twice that. The
+/// budget doubles every time spinning gets the lock, and halves every time the
+/// thread has to go to sleep anyway. When spinning fails several times in a row,
+/// because the holder keeps the lock for long, waiters skip spinning and go to
+/// sleep right away, trying to spin again only now and then.
+///
+/// An `Adaptive` keeps its state in atomics, so each lock needs one of its own.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::SpinPark;
+///
+/// let lock = SpinPark::adaptive();
+/// lock.lock();
+/// lock.unlock();
+/// println!("spinning for {} rounds", lock.backoff().budget());
+/// ```
+#[derive(Debug)]
+pub struct Adaptive {
+	budget: AtomicU32,
+	misses: AtomicU32,
+	/// The average hold time in nanoseconds.
+	hold: AtomicU32,
+	/// Only accessed by the thread holding the lock.
+	acquisitions: AtomicU32,
+	locked_at: AtomicU64,
+}
+
+impl Adaptive {
+	/// Create an `Adaptive` backoff with no hold times measured yet.
+	#[inline]
+	pub const fn new() -> Self {
+		Adaptive {
+			budget: AtomicU32::new(MIN_SPINS * 4),
+			misses: AtomicU32::new(0),
+			hold: AtomicU32::new(0),
+			acquisitions: AtomicU32::new(0),
+			locked_at: AtomicU64::new(0),
+		}
+	}
+
+	/// The number of spins a waiter currently does before going to sleep. Zero when
+	/// spinning is skipped.
+	#[inline]
+	pub fn budget(&self) -> u32 {
+		let misses = self.misses.load(Relaxed);
+		if misses >= SKIP_AFTER && !misses.is_multiple_of(PROBE_EVERY) {
+			return 0;
+		}
+		self.budget.load(Relaxed)
+	}
+
+	/// The moving average of how long the lock is held.
+	#[inline]
+	pub fn hold_time(&self) -> Duration {
+		Duration::from_nanos(self.hold.load(Relaxed) as u64)
+	}
+
+	/// The most spins worth doing: twice the average hold time. Before any hold has
+	/// been timed, the budget is only limited by `MAX_SPINS`.
+	fn limit(&self) -> u32 {
+		let hold = self.hold.load(Relaxed);
+		if hold == 0 {
+			return MAX_SPINS;
+		}
+		let spins = 2 * hold as u64 / spin_nanos();
+		spins.clamp(MIN_SPINS as u64, MAX_SPINS as u64) as u32
+	}
+}
+
+impl Default for Adaptive {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Backoff for Adaptive {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const DEFAULT: Self = Adaptive::new();
+
+	#[inline]
+	fn backoff(&self, step: u32) -> bool {
+		if step >= self.budget() {
+			return false;
+		}
+		spin_loop();
+		true
+	}
+
+	fn spun(&self, acquired: bool) {
+		let budget = self.budget.load(Relaxed);
+		if acquired {
+			self.misses.store(0, Relaxed);
+			self.budget.store(budget.saturating_mul(2).min(self.limit()), Relaxed);
+		} else {
+			self.misses.store(self.misses.load(Relaxed).wrapping_add(1), Relaxed);
+			self.budget.store((budget / 2).max(MIN_SPINS), Relaxed);
+		}
+	}
+
+	#[inline]
+	fn locked(&self) {
+		let n = self.acquisitions.load(Relaxed);
+		self.acquisitions.store(n.wrapping_add(1), Relaxed);
+		if n.is_multiple_of(SAMPLE_EVERY) {
+			self.locked_at.store(WaitTimer::now() + 1, Relaxed);
+		}
+	}
+
+	#[inline]
+	fn unlocking(&self) {
+		let locked_at = self.locked_at.load(Relaxed);
+		if locked_at != 0 {
+			self.locked_at.store(0, Relaxed);
+			let held = (WaitTimer::now() + 1).saturating_sub(locked_at).min(u32::MAX as u64) as u32;
+			let hold = self.hold.load(Relaxed);
+			let hold = if hold == 0 { held } else { hold - hold / 8 + held / 8 };
+			self.hold.store(hold.max(1), Relaxed);
+		}
+	}
+}
+
+/// The time a `spin_loop` takes in nanoseconds, measured once per process.
+fn spin_nanos() -> u64 {
+	static SPIN_NANOS: OnceLock<u64> = OnceLock::new();
+	*SPIN_NANOS.get_or_init(|| {
+		const SPINS: u32 = 1000;
+		let start = Instant::now();
+		for _ in 0..SPINS {
+			spin_loop();
+		}
+		(start.elapsed().as_nanos() as u64 / SPINS as u64).max(1)
+	})
+}
@@ -0,0 +1,123 @@
+use super::*;
+use std::sync::atomic::AtomicBool;
+use std::hint::spin_loop;
+use crate::owner::Owner;
+
+pub struct BusySpinLock {
+	locked: AtomicBool,
+	owner: Owner,
+}
+
+impl BusySpinLock {
+
+	/// Create a new `BusySpinLock`. Unlike `SpinLock` and `SpinPark`, a `BusySpinLock`
+	/// never parks the waiting thread but spins on the lock until it's released. This
+	/// gives the lowest latency when critical sections are very short and every
+	/// thread has its own core, but wastes CPU time otherwise.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::BusySpinLock;
+	///
+	/// let lock = BusySpinLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		BusySpinLock {
+			locked: AtomicBool::new(false),
+			owner: Owner::new(),
+		}
+	}
+
+	/// Lock the `BusySpinLock`. This is a blocking operation. If the lock is held by
+	/// another thread, this function will spin until the lock is released and then
+	/// acquire it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::BusySpinLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(BusySpinLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// let t = std::thread::spawn(move || {
+	///     lock2.lock();
+	///     // do something
+	///     lock2.unlock();
+	/// });
+	///
+	/// lock.lock();
+	/// // do something
+	/// lock.unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn lock(&self) {
+		while self.locked.compare_exchange_weak(false, true, Acquire, Relaxed).is_err() {
+			self.owner.check("BusySpinLock");
+			// Only read while spinning, to be easier on the caches.
+			while self.locked.load(Relaxed) {
+				spin_loop();
+			}
+		}
+		self.owner.locked();
+	}
+
+	/// Try to lock the `BusySpinLock` without blocking. Returns `true` if the lock
+	/// was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::BusySpinLock;
+	///
+	/// let lock = BusySpinLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		let locked = self.locked.compare_exchange(false, true, Acquire, Relaxed).is_ok();
+		if locked {
+			self.owner.locked();
+		}
+		locked
+	}
+
+	/// Unlock the `BusySpinLock`.
+	#[inline]
+	pub fn unlock(&self) {
+		self.owner.unlocking();
+		self.locked.store(false, Release);
+	}
+}
+
+impl Default for BusySpinLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for BusySpinLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = BusySpinLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+}
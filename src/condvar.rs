@@ -0,0 +1,256 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use crate::futex::{futex_wait_until, futex_wake, futex_wake_all};
+
+/// A held exclusive lock that a `Condvar` can wait with. Implemented for a
+/// reference to a locked `RawLock`, like `&SpinLock`, and for the guards of an
+/// `Atom`.
+///
+/// # Safety
+///
+/// The lock returned by `raw_lock` must be held exclusively by the current thread
+/// for as long as the value exists. The `Condvar` releases it while waiting and
+/// acquires it again with `RawLock::lock_after_wait` before returning.
+pub unsafe trait CondvarLock {
+	type Lock: RawLock;
+
+	/// The lock that is held.
+	///
+	/// # Safety
+	///
+	/// The lock must not be released through the returned reference, other than by
+	/// a `Condvar` that acquires it again before the value is used. A guard still
+	/// gives access to the data the lock protects.
+	unsafe fn raw_lock(&self) -> &Self::Lock;
+}
+
+unsafe impl<L: RawLock> CondvarLock for &L {
+	type Lock = L;
+
+	#[inline]
+	unsafe fn raw_lock(&self) -> &L {
+		self
+	}
+}
+
+/// A condition variable that can be used together with a `SpinLock`, a `SpinPark`,
+/// an `Atom` or any other `RawLock` to block a thread until a condition becomes
+/// true. The lock is released while the thread is waiting and acquired again
+/// before it returns.
+///
+/// Every `Condvar` must always be used with the same lock. On Linux, `notify_all`
+/// wakes up only one of the waiters and moves the rest to wait on the lock
+/// instead, if the lock supports it, so that they don't all race for the lock at
+/// once.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{Atom, Condvar};
+/// use std::sync::Arc;
+///
+/// let ready = Atom::new(false);
+/// let cond = Arc::new(Condvar::new());
+///
+/// let t_ready = ready.clone();
+/// let t_cond = cond.clone();
+/// let t = std::thread::spawn(move || {
+///     *t_ready.guard() = true;
+///     t_cond.notify_one();
+/// });
+///
+/// let mut guard = ready.guard();
+/// while !*guard {
+///     guard = cond.wait(guard);
+/// }
+/// drop(guard);
+/// t.join().unwrap();
+/// ```
+pub struct Condvar {
+	futex: AtomicU32,
+	lock: AtomicUsize,
+	word: AtomicUsize,
+}
+
+impl Condvar {
+
+	/// Create a new `Condvar`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Condvar;
+	///
+	/// let cond = Condvar::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		Condvar {
+			futex: AtomicU32::new(0),
+			lock: AtomicUsize::new(0),
+			word: AtomicUsize::new(0),
+		}
+	}
+
+	/// Release the lock held by `guard` and block until notified, then acquire
+	/// the lock again and give the guard back. The thread can also wake up
+	/// spuriously, so the condition should be checked in a loop, or `wait_while`
+	/// used instead.
+	///
+	/// # Panics
+	///
+	/// Panics if the `Condvar` has been used with a different lock before.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Condvar, SpinLock};
+	/// use std::sync::atomic::{AtomicBool, Ordering};
+	/// use std::sync::Arc;
+	///
+	/// let state = Arc::new((SpinLock::new(), Condvar::new(), AtomicBool::new(false)));
+	/// let t_state = state.clone();
+	/// let t = std::thread::spawn(move || {
+	///     let (lock, cond, ready) = &*t_state;
+	///     lock.lock();
+	///     ready.store(true, Ordering::Relaxed);
+	///     lock.unlock();
+	///     cond.notify_one();
+	/// });
+	///
+	/// let (lock, cond, ready) = &*state;
+	/// lock.lock();
+	/// while !ready.load(Ordering::Relaxed) {
+	///     cond.wait(lock);
+	/// }
+	/// lock.unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn wait<G: CondvarLock>(&self, guard: G) -> G {
+		self.wait_until(&guard, None);
+		guard
+	}
+
+	/// Like `wait`, but gives up waiting once `timeout` has passed. The lock is
+	/// acquired again in either case. Returns the guard and `true` if the wait
+	/// timed out.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, Condvar};
+	/// use std::time::Duration;
+	///
+	/// let atom = Atom::new(0);
+	/// let cond = Condvar::new();
+	/// let (guard, timed_out) = cond.wait_timeout(atom.guard(), Duration::from_millis(10));
+	/// assert!(timed_out);
+	/// assert_eq!(*guard, 0);
+	/// ```
+	#[inline]
+	pub fn wait_timeout<G: CondvarLock>(&self, guard: G, timeout: Duration) -> (G, bool) {
+		let notified = self.wait_until(&guard, Instant::now().checked_add(timeout));
+		(guard, !notified)
+	}
+
+	/// Block until `condition` returns `false`. The condition is checked while
+	/// holding the lock, first before waiting and then every time the thread
+	/// wakes up.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{Atom, Condvar};
+	/// use std::sync::Arc;
+	///
+	/// let queue = Atom::new(Vec::new());
+	/// let cond = Arc::new(Condvar::new());
+	///
+	/// let t_queue = queue.clone();
+	/// let t_cond = cond.clone();
+	/// let t = std::thread::spawn(move || {
+	///     t_queue.lock(|q| q.push(1));
+	///     t_cond.notify_all();
+	/// });
+	///
+	/// let mut guard = cond.wait_while(queue.guard(), |q| q.is_empty());
+	/// assert_eq!(guard.pop(), Some(1));
+	/// drop(guard);
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn wait_while<G: CondvarLock>(&self, mut guard: G, mut condition: impl FnMut(&mut G) -> bool) -> G {
+		while condition(&mut guard) {
+			guard = self.wait(guard);
+		}
+		guard
+	}
+
+	/// Wake up one of the threads waiting on this `Condvar`.
+	#[inline]
+	pub fn notify_one(&self) {
+		self.futex.fetch_add(1, Relaxed);
+		futex_wake(&self.futex);
+	}
+
+	/// Wake up all the threads waiting on this `Condvar`.
+	#[inline]
+	pub fn notify_all(&self) {
+		let seq = self.futex.fetch_add(1, Relaxed).wrapping_add(1);
+		if !self.requeue(seq) {
+			futex_wake_all(&self.futex);
+		}
+	}
+
+	/// Wake up one waiter and move the rest to wait on the lock. Any thread
+	/// returning from `wait` marks the lock contended, so the woken thread will
+	/// wake up the next one when it releases the lock, and so on.
+	#[cfg(all(any(target_os = "linux", target_os = "android"), not(feature = "portable-wait"), not(miri)))]
+	#[inline]
+	fn requeue(&self, seq: u32) -> bool {
+		// The futex word is only read by the kernel if there are threads waiting,
+		// and those threads keep the lock alive.
+		let word = self.word.load(Relaxed) as *const AtomicU32;
+		!word.is_null() && crate::futex::futex_cmp_requeue(&self.futex, seq, word)
+	}
+
+	#[cfg(not(all(any(target_os = "linux", target_os = "android"), not(feature = "portable-wait"), not(miri))))]
+	#[inline]
+	fn requeue(&self, _seq: u32) -> bool {
+		false
+	}
+
+	fn wait_until<G: CondvarLock>(&self, guard: &G, deadline: Option<Instant>) -> bool {
+		// The lock is acquired again before the guard is handed back.
+		let lock = unsafe { guard.raw_lock() };
+		self.check_lock(lock);
+		let seq = self.futex.load(Relaxed);
+		lock.unlock();
+		let notified = futex_wait_until(&self.futex, seq, deadline);
+		lock.lock_after_wait();
+		notified
+	}
+
+	/// Remember the lock on first use, and panic if a different one is used
+	/// later. Waiters can only be moved to the futex word of the lock if all of
+	/// them are waiting with it.
+	#[inline]
+	fn check_lock<L: RawLock>(&self, lock: &L) {
+		let addr = lock as *const L as usize;
+		match self.lock.compare_exchange(0, addr, Relaxed, Relaxed) {
+			Ok(_) => {
+				if let Some(word) = lock.requeue_word() {
+					self.word.store(word as *const AtomicU32 as usize, Relaxed);
+				}
+			}
+			Err(prev) => assert_eq!(prev, addr, "attempted to use a condition variable with two locks"),
+		}
+	}
+}
+
+impl Default for Condvar {
+	fn default() -> Self {
+		Self::new()
+	}
+}
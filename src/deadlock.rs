@@ -0,0 +1,208 @@
+//! Detection of lock orders that could deadlock, in debug builds with the
+//! `deadlock-detection` feature.
+//!
+//! Every thread records the `Atom`s it holds. When a thread blocks on an `Atom`
+//! while holding others, the order is added to a global graph of the locks that
+//! have been acquired before others. An acquisition that closes a cycle in the
+//! graph can deadlock with the acquisitions that recorded the rest of the cycle,
+//! when they run at the same time, so it is reported right away with the
+//! backtraces of all of them, even if the deadlock didn't happen this time.
+//!
+//! Try-locks never block and don't add to the graph. Every order is reported
+//! once, by calling the handler set with `set_handler`, which panics by default.
+//! In release builds nothing is recorded.
+//!
+//! # Examples
+//!
+//! ```
+//! use spinout::{deadlock, Atom};
+//!
+//! deadlock::set_handler(|deadlock| eprintln!("{}", deadlock));
+//!
+//! let a = Atom::new(1);
+//! let b = Atom::new(2);
+//! a.lock(|_| b.lock(|_| ()));
+//! // Could deadlock with the line above, and is reported.
+//! b.lock(|_| a.lock(|_| ()));
+//! ```
+
+use std::backtrace::Backtrace;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// An acquisition that could deadlock with earlier ones, because it closes a cycle
+/// in the order the locks have been acquired in.
+pub struct PotentialDeadlock {
+	acquiring: Arc<Backtrace>,
+	earlier: Vec<Arc<Backtrace>>,
+}
+
+impl PotentialDeadlock {
+	/// Where the thread acquires a lock while holding another one that has been
+	/// acquired after it before.
+	pub fn backtrace(&self) -> &Backtrace {
+		&self.acquiring
+	}
+
+	/// Where the earlier acquisitions that make up the rest of the cycle took their
+	/// locks, in the order of the cycle.
+	pub fn earlier(&self) -> impl Iterator<Item = &Backtrace> {
+		self.earlier.iter().map(|backtrace| &**backtrace)
+	}
+}
+
+impl fmt::Display for PotentialDeadlock {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "potential deadlock: a lock is acquired while holding a lock that was acquired after it before")?;
+		writeln!(f, "\nthis acquisition:\n{}", self.acquiring)?;
+		for (i, backtrace) in self.earlier.iter().enumerate() {
+			writeln!(f, "\nearlier acquisition {}:\n{}", i + 1, backtrace)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for PotentialDeadlock {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Display::fmt(self, f)
+	}
+}
+
+fn panic_on_deadlock(deadlock: &PotentialDeadlock) {
+	panic!("{}", deadlock);
+}
+
+static HANDLER: Mutex<fn(&PotentialDeadlock)> = Mutex::new(panic_on_deadlock);
+
+/// Set the function called with a potential deadlock, before the lock is acquired.
+/// If it returns, the acquisition goes on. The default handler panics with the
+/// report.
+pub fn set_handler(handler: fn(&PotentialDeadlock)) {
+	*HANDLER.lock().unwrap_or_else(|e| e.into_inner()) = handler;
+}
+
+#[cfg(debug_assertions)]
+pub(crate) use self::graph::{acquire, acquired, forget, released};
+
+#[cfg(debug_assertions)]
+mod graph {
+	use super::*;
+	use std::cell::RefCell;
+	use std::collections::{BTreeMap, VecDeque};
+	use std::sync::MutexGuard;
+
+	/// For every lock, the locks that have been acquired while holding it, with
+	/// the backtrace of the first such acquisition.
+	static GRAPH: Mutex<BTreeMap<usize, BTreeMap<usize, Arc<Backtrace>>>> = Mutex::new(BTreeMap::new());
+
+	fn graph() -> MutexGuard<'static, BTreeMap<usize, BTreeMap<usize, Arc<Backtrace>>>> {
+		GRAPH.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	thread_local! {
+		/// The locks held by the thread, in the order they were acquired. Shared
+		/// locks held more than once are in it more than once.
+		static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+	}
+
+	#[inline]
+	fn key<L>(lock: &L) -> usize {
+		lock as *const L as usize
+	}
+
+	/// The backtraces along a path from `from` to `to` in the graph, if any.
+	fn path(
+		graph: &BTreeMap<usize, BTreeMap<usize, Arc<Backtrace>>>,
+		from: usize,
+		to: usize,
+	) -> Option<Vec<Arc<Backtrace>>> {
+		let mut came_from = BTreeMap::new();
+		let mut queue = VecDeque::from([from]);
+		while let Some(lock) = queue.pop_front() {
+			if lock == to {
+				let mut backtraces = Vec::new();
+				let mut lock = to;
+				while lock != from {
+					let before = came_from[&lock];
+					backtraces.push(graph[&before][&lock].clone());
+					lock = before;
+				}
+				backtraces.reverse();
+				return Some(backtraces);
+			}
+			for &after in graph.get(&lock).into_iter().flat_map(|after| after.keys()) {
+				if after != from && !came_from.contains_key(&after) {
+					came_from.insert(after, lock);
+					queue.push_back(after);
+				}
+			}
+		}
+		None
+	}
+
+	/// The thread is about to block on `lock`. Records that it is acquired after
+	/// every lock the thread holds, and reports the first order that closes a cycle.
+	pub(crate) fn acquire<L>(lock: &L) {
+		let lock = key(lock);
+		let Ok(held) = HELD.try_with(|held| held.borrow().clone()) else {
+			return;
+		};
+		if held.is_empty() {
+			return;
+		}
+		let mut graph = graph();
+		let mut backtrace = None;
+		let mut deadlock = None;
+		for before in held {
+			if before == lock || graph.get(&before).is_some_and(|after| after.contains_key(&lock)) {
+				continue;
+			}
+			let backtrace = backtrace.get_or_insert_with(|| Arc::new(Backtrace::force_capture()));
+			if deadlock.is_none() {
+				deadlock = path(&graph, lock, before).map(|earlier| PotentialDeadlock {
+					acquiring: backtrace.clone(),
+					earlier,
+				});
+			}
+			graph.entry(before).or_default().insert(lock, backtrace.clone());
+		}
+		drop(graph);
+		if let Some(deadlock) = deadlock {
+			let handler = *HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+			handler(&deadlock);
+		}
+	}
+
+	/// The thread acquired `lock`, blocking or not.
+	#[inline]
+	pub(crate) fn acquired<L>(lock: &L) {
+		let _ = HELD.try_with(|held| held.borrow_mut().push(key(lock)));
+	}
+
+	/// The thread is about to release `lock`.
+	#[inline]
+	pub(crate) fn released<L>(lock: &L) {
+		let lock = key(lock);
+		let _ = HELD.try_with(|held| {
+			let mut held = held.borrow_mut();
+			if let Some(i) = held.iter().rposition(|&l| l == lock) {
+				held.remove(i);
+			}
+		});
+	}
+
+	/// `lock` is about to be deallocated, and its address may be reused by a new
+	/// lock, which must not inherit its orders.
+	pub(crate) fn forget<L>(lock: &L) {
+		let lock = key(lock);
+		let mut graph = graph();
+		if graph.is_empty() {
+			return;
+		}
+		graph.remove(&lock);
+		graph.retain(|_, after| {
+			after.remove(&lock);
+			!after.is_empty()
+		});
+	}
+}
@@ -0,0 +1,207 @@
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
+use std::sync::{Mutex, MutexGuard};
+
+/// Epoch-based reclamation, for `AtomSnapshot`.
+///
+/// A reader pins its thread in the current global epoch for as long as it holds
+/// references to shared values, which takes no lock and no loop. A writer that
+/// replaces a value retires the old one, tagged with the epoch after the swap. The
+/// global epoch only advances once every pinned thread is pinned in it, so while a
+/// thread is pinned the epoch moves at most one past the epoch it is pinned in. A
+/// value retired in epoch `e` can't be reached by a reader pinned after the swap,
+/// and every reader pinned before is gone once the epoch reaches `e + 2`.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// The threads that have pinned themselves, in a list that only grows. Threads that
+/// exit leave their record for the next thread to take.
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+/// Frees a retired value.
+type Free = Box<dyn FnOnce() + Send>;
+
+/// Retired values, with the epoch they were retired in.
+static GARBAGE: Mutex<Vec<(usize, Free)>> = Mutex::new(Vec::new());
+
+struct Participant {
+	/// The epoch the thread is pinned in, shifted left by one with the lowest bit
+	/// set, or 0 when it isn't pinned.
+	state: AtomicUsize,
+	in_use: AtomicBool,
+	next: AtomicPtr<Participant>,
+}
+
+impl Participant {
+	/// Take a free record, or add a new one to the list.
+	fn acquire() -> &'static Participant {
+		let mut next = PARTICIPANTS.load(Acquire);
+		while let Some(participant) = unsafe { next.as_ref() } {
+			if !participant.in_use.load(Relaxed)
+				&& participant.in_use.compare_exchange(false, true, Acquire, Relaxed).is_ok()
+			{
+				return participant;
+			}
+			next = participant.next.load(Relaxed);
+		}
+		let participant = Box::leak(Box::new(Participant {
+			state: AtomicUsize::new(0),
+			in_use: AtomicBool::new(true),
+			next: AtomicPtr::new(ptr::null_mut()),
+		}));
+		let mut head = PARTICIPANTS.load(Relaxed);
+		loop {
+			participant.next.store(head, Relaxed);
+			match PARTICIPANTS.compare_exchange_weak(head, participant, Release, Relaxed) {
+				Ok(_) => return participant,
+				Err(h) => head = h,
+			}
+		}
+	}
+
+	fn release(&self) {
+		self.state.store(0, Release);
+		self.in_use.store(false, Release);
+	}
+
+	#[inline]
+	fn pin(&self) {
+		let epoch = EPOCH.load(Relaxed);
+		self.state.store((epoch << 1) | 1, Relaxed);
+		// The pin must be visible to `try_advance` before the reader loads any value
+		// that a writer could retire.
+		fence(SeqCst);
+	}
+
+	#[inline]
+	fn unpin(&self) {
+		self.state.store(0, Release);
+	}
+}
+
+struct Local {
+	participant: &'static Participant,
+	/// The number of `Guard`s of the thread that are alive.
+	pins: Cell<usize>,
+}
+
+impl Drop for Local {
+	fn drop(&mut self) {
+		self.participant.release();
+	}
+}
+
+thread_local! {
+	static LOCAL: Local = Local {
+		participant: Participant::acquire(),
+		pins: Cell::new(0),
+	};
+}
+
+/// Keeps the thread pinned until it is dropped. The guards of a thread nest.
+pub(crate) struct Guard {
+	/// A record of its own, when the thread local is already gone.
+	owned: Option<&'static Participant>,
+	/// A guard belongs to the thread that pinned itself.
+	_not_send: std::marker::PhantomData<*const ()>,
+}
+
+/// Pin the current thread, so that nothing it loads from now on is reclaimed until
+/// the returned guard is dropped.
+#[inline]
+pub(crate) fn pin() -> Guard {
+	let pinned = LOCAL.try_with(|local| {
+		let pins = local.pins.get();
+		if pins == 0 {
+			local.participant.pin();
+		}
+		local.pins.set(pins + 1);
+	});
+	let owned = pinned.is_err().then(|| {
+		let participant = Participant::acquire();
+		participant.pin();
+		participant
+	});
+	Guard {
+		owned,
+		_not_send: std::marker::PhantomData,
+	}
+}
+
+impl Drop for Guard {
+	#[inline]
+	fn drop(&mut self) {
+		match self.owned {
+			Some(participant) => {
+				participant.unpin();
+				participant.release();
+			}
+			None => {
+				let _ = LOCAL.try_with(|local| {
+					let pins = local.pins.get() - 1;
+					local.pins.set(pins);
+					if pins == 0 {
+						local.participant.unpin();
+					}
+				});
+			}
+		}
+	}
+}
+
+fn garbage() -> MutexGuard<'static, Vec<(usize, Free)>> {
+	GARBAGE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Run `free` once no thread can hold a reference to what it frees, which must have
+/// been unlinked from everywhere readers can load it before the call.
+pub(crate) fn retire(free: Free) {
+	// Tag with an epoch read after the unlink.
+	fence(SeqCst);
+	let epoch = EPOCH.load(Relaxed);
+	garbage().push((epoch, free));
+}
+
+/// Advance the epoch if every pinned thread is pinned in it. Returns the epoch.
+fn try_advance() -> usize {
+	let epoch = EPOCH.load(Relaxed);
+	fence(SeqCst);
+	let mut next = PARTICIPANTS.load(Acquire);
+	while let Some(participant) = unsafe { next.as_ref() } {
+		let state = participant.state.load(Relaxed);
+		if state & 1 == 1 && state >> 1 != epoch {
+			return epoch;
+		}
+		next = participant.next.load(Relaxed);
+	}
+	fence(Acquire);
+	match EPOCH.compare_exchange(epoch, epoch.wrapping_add(1), Release, Relaxed) {
+		Ok(_) => epoch.wrapping_add(1),
+		Err(epoch) => epoch,
+	}
+}
+
+/// Advance the epoch as far as the pinned threads allow, and run the retired frees
+/// that are safe to run.
+pub(crate) fn collect() {
+	try_advance();
+	let epoch = try_advance();
+	let ready: Vec<_> = {
+		let mut garbage = garbage();
+		let mut ready = Vec::new();
+		let mut i = 0;
+		while i < garbage.len() {
+			// Signed, as a value retired since the epoch was read has a later tag.
+			if epoch.wrapping_sub(garbage[i].0) as isize >= 2 {
+				ready.push(garbage.swap_remove(i).1);
+			} else {
+				i += 1;
+			}
+		}
+		ready
+	};
+	// Outside of the lock, as dropping a value may retire more.
+	for free in ready {
+		free();
+	}
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// How long the oldest waiter of a lock can wait before `unlock` hands the lock
+/// directly to a woken waiter, instead of releasing it for any thread to take.
+pub(crate) const FAIR_TIMEOUT: Duration = Duration::from_micros(500);
+
+/// The state of a futex lock that has been handed over by `unlock`, but not yet
+/// taken over by a woken waiter. The lock counts as held and contended.
+pub(crate) const HANDOFF: u32 = 3;
+
+const NOBODY: u64 = u64::MAX;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Keeps track of since when the oldest waiter of a lock has been waiting, as
+/// nanoseconds since a process-wide epoch.
+///
+/// A waiter registers itself with `waiting` every time it goes to sleep, and
+/// unregisters with `done` once it has the lock or gives up. Waiters that are
+/// asleep register again when they are woken up, so the time is that of the
+/// oldest waiter that has been active since the oldest one left.
+pub(crate) struct WaitTimer(AtomicU64);
+
+impl WaitTimer {
+	#[inline]
+	pub const fn new() -> Self {
+		WaitTimer(AtomicU64::new(NOBODY))
+	}
+
+	/// The time for a waiter to register with.
+	#[inline]
+	pub fn now() -> u64 {
+		let epoch = *EPOCH.get_or_init(Instant::now);
+		epoch.elapsed().as_nanos().min(NOBODY as u128 - 1) as u64
+	}
+
+	#[inline]
+	pub fn waiting(&self, since: u64) {
+		self.0.fetch_min(since, Relaxed);
+	}
+
+	#[inline]
+	pub fn done(&self, since: u64) {
+		let _ = self.0.compare_exchange(since, NOBODY, Relaxed, Relaxed);
+	}
+
+	/// Returns `true` if the oldest waiter has been waiting for longer than
+	/// `FAIR_TIMEOUT`, so the lock should be handed over to a waiter.
+	#[inline]
+	pub fn expired(&self) -> bool {
+		let since = self.0.load(Relaxed);
+		since != NOBODY && Self::now().saturating_sub(since) >= FAIR_TIMEOUT.as_nanos() as u64
+	}
+}
@@ -9,7 +9,7 @@
 ))]
 
 use std::sync::atomic::AtomicU32;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Wait for a futex_wake operation to wake us.
 ///
@@ -99,14 +99,242 @@ pub fn futex_wake(futex: &AtomicU32) -> bool {
 }
 
 /// Wake up all threads that are waiting on futex_wait on this futex.
-// #[cfg(any(target_os = "linux", target_os = "android"))]
-// pub fn futex_wake_all(futex: &AtomicU32) {
-//     let ptr = futex as *const AtomicU32;
-//     let op = libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG;
-//     unsafe {
-//         libc::syscall(libc::SYS_futex, ptr, op, i32::MAX);
-//     }
-// }
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn futex_wake_all(futex: &AtomicU32) {
+    let ptr = futex as *const AtomicU32;
+    let op = libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG;
+    unsafe {
+        libc::syscall(libc::SYS_futex, ptr, op, i32::MAX);
+    }
+}
+
+/// Wake up one thread that's blocked on futex_wait on this futex, and move all the
+/// other waiters over to wait on the `target` futex instead, without waking them.
+///
+/// Returns false if the futex didn't hold the expected value, or the operation
+/// failed otherwise, in which case no thread was woken up or moved. The `target`
+/// futex is only accessed when there are waiters to move.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn futex_cmp_requeue(futex: &AtomicU32, expected: u32, target: *const AtomicU32) -> bool {
+    let op = libc::FUTEX_CMP_REQUEUE | libc::FUTEX_PRIVATE_FLAG;
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex as *const AtomicU32,
+            op,
+            1,                          // Wake up one thread.
+            i32::MAX as libc::c_long,   // Requeue all the rest. Passed in the timeout argument.
+            target,
+            expected,
+        ) >= 0
+    }
+}
+
+/// Like `futex_wait_until`, but without `FUTEX_PRIVATE_FLAG`, so that it can be
+/// woken up by `futex_wake_shared` from another process that has the same memory
+/// mapped.
+///
+/// Returns false if the deadline has passed, and true in all other cases.
+#[cfg(target_os = "linux")]
+pub fn futex_wait_shared(futex: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+    use crate::timespec::Timespec;
+    use std::ptr::null;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    let timespec = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(timeout) if !timeout.is_zero() => Timespec::now(libc::CLOCK_MONOTONIC)
+                .checked_add_duration(&timeout)
+                .and_then(|t| t.to_timespec()),
+            _ => return false,
+        },
+        None => None,
+    };
+
+    loop {
+        if futex.load(Relaxed) != expected {
+            return true;
+        }
+
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex as *const AtomicU32,
+                libc::FUTEX_WAIT_BITSET,
+                expected,
+                timespec.as_ref().map_or(null(), |t| t as *const libc::timespec),
+                null::<u32>(),
+                !0u32,
+            )
+        };
+
+        match (r < 0).then(std::io::Error::last_os_error) {
+			Some(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+			Some(err) if err.kind() == std::io::ErrorKind::TimedOut => return false,
+			Some(err) if err.raw_os_error() == Some(libc::EWOULDBLOCK) => return true,
+			Some(err) => panic!("futex_wait failed: {}", err),
+			None => return true,
+        }
+    }
+}
+
+/// Wake up one thread, in any process, that's blocked on futex_wait_shared on this
+/// futex.
+#[cfg(target_os = "linux")]
+pub fn futex_wake_shared(futex: &AtomicU32) -> bool {
+    let ptr = futex as *const AtomicU32;
+    unsafe { libc::syscall(libc::SYS_futex, ptr, libc::FUTEX_WAKE, 1) > 0 }
+}
+
+/// Wake up all the threads, in any process, that are blocked on futex_wait_shared
+/// on this futex.
+#[cfg(target_os = "linux")]
+pub fn futex_wake_all_shared(futex: &AtomicU32) {
+    let ptr = futex as *const AtomicU32;
+    unsafe {
+        libc::syscall(libc::SYS_futex, ptr, libc::FUTEX_WAKE, i32::MAX);
+    }
+}
+
+/// Set in a robust futex word when there are threads waiting on it.
+#[cfg(target_os = "linux")]
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+
+/// Set in a robust futex word by the kernel when its owner died.
+#[cfg(target_os = "linux")]
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+
+/// The bits of a robust futex word that hold the thread id of the owner.
+#[cfg(target_os = "linux")]
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// The head of the list of robust futexes held by a thread, which the kernel
+/// walks when the thread exits. Each entry points to the next one, and the futex
+/// word of an entry is found at `futex_offset` bytes from it.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+pub struct RobustListHead {
+    pub list: *mut RobustListHead,
+    pub futex_offset: libc::c_long,
+    pub list_op_pending: *mut RobustListHead,
+}
+
+/// The robust list registered for the current thread, if any.
+#[cfg(target_os = "linux")]
+pub fn get_robust_list() -> *mut RobustListHead {
+    let mut head: *mut RobustListHead = std::ptr::null_mut();
+    let mut len: libc::size_t = 0;
+    let r = unsafe { libc::syscall(libc::SYS_get_robust_list, 0, &mut head, &mut len) };
+    if r < 0 { std::ptr::null_mut() } else { head }
+}
+
+/// Register the robust list of the current thread with the kernel.
+#[cfg(target_os = "linux")]
+pub fn set_robust_list(head: *mut RobustListHead) -> bool {
+    let len = std::mem::size_of::<RobustListHead>();
+    unsafe { libc::syscall(libc::SYS_set_robust_list, head, len) == 0 }
+}
+
+/// The thread id of the current thread, as stored in robust futex words.
+#[cfg(target_os = "linux")]
+pub fn gettid() -> u32 {
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(target_os = "linux")]
+thread_local! {
+    static TID: std::cell::Cell<u32> = const { std::cell::Cell::new(0) };
+}
+
+/// The thread id of the current thread, cached per thread. The cache of the
+/// forking thread is cleared in the child after a `fork`, since the child runs
+/// with a new thread id.
+#[cfg(target_os = "linux")]
+pub fn current_tid() -> u32 {
+    extern "C" fn clear_tid() {
+        TID.with(|tid| tid.set(0));
+    }
+    static ATFORK: std::sync::Once = std::sync::Once::new();
+
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            ATFORK.call_once(|| unsafe {
+                libc::pthread_atfork(None, None, Some(clear_tid));
+            });
+            tid.set(gettid());
+        }
+        tid.get()
+    })
+}
+
+/// Acquire a priority inheritance futex, which holds the thread id of its owner,
+/// after it was found locked. The kernel queues the thread by priority and boosts
+/// the owner to the highest priority among the waiters until it unlocks.
+///
+/// Returns false if the deadline has passed, and true once the lock is acquired.
+#[cfg(target_os = "linux")]
+pub fn futex_lock_pi(futex: &AtomicU32, deadline: Option<Instant>) -> bool {
+    use std::ptr::null;
+
+    // FUTEX_LOCK_PI takes an absolute timeout on CLOCK_REALTIME.
+    let timespec = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(timeout) if !timeout.is_zero() => crate::timespec::Timespec::now(libc::CLOCK_REALTIME)
+                .checked_add_duration(&timeout)
+                .and_then(|t| t.to_timespec()),
+            _ => return false,
+        },
+        None => None,
+    };
+
+    loop {
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                futex as *const AtomicU32,
+                libc::FUTEX_LOCK_PI | libc::FUTEX_PRIVATE_FLAG,
+                0,
+                timespec.as_ref().map_or(null(), |t| t as *const libc::timespec),
+            )
+        };
+
+        match (r < 0).then(std::io::Error::last_os_error) {
+			Some(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+			Some(err) if err.kind() == std::io::ErrorKind::TimedOut => return false,
+			Some(err) if err.raw_os_error() == Some(libc::EDEADLK) => match deadline {
+				// The lock is held by this thread, so it would never be acquired.
+				Some(deadline) => {
+					std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+					return false;
+				}
+				None => panic!("PiLock locked again by the thread holding it, which would deadlock"),
+			},
+			Some(err) => panic!("futex_lock_pi failed: {}", err),
+			None => return true,
+        }
+    }
+}
+
+/// Try to acquire a priority inheritance futex through the kernel. Returns false
+/// if it's held by another thread.
+#[cfg(target_os = "linux")]
+pub fn futex_trylock_pi(futex: &AtomicU32) -> bool {
+    let ptr = futex as *const AtomicU32;
+    let op = libc::FUTEX_TRYLOCK_PI | libc::FUTEX_PRIVATE_FLAG;
+    unsafe { libc::syscall(libc::SYS_futex, ptr, op) == 0 }
+}
+
+/// Release a priority inheritance futex that has waiters, handing it over to the
+/// waiter with the highest priority.
+#[cfg(target_os = "linux")]
+pub fn futex_unlock_pi(futex: &AtomicU32) {
+    let ptr = futex as *const AtomicU32;
+    let op = libc::FUTEX_UNLOCK_PI | libc::FUTEX_PRIVATE_FLAG;
+    let r = unsafe { libc::syscall(libc::SYS_futex, ptr, op) };
+    if r < 0 {
+        panic!("futex_unlock_pi failed: {}", std::io::Error::last_os_error());
+    }
+}
 
 // FreeBSD doesn't tell us how many threads are woken up, so this always returns false.
 #[cfg(target_os = "freebsd")]
@@ -304,3 +532,18 @@ pub fn futex_wake(futex: &AtomicU32) -> bool {
 pub fn futex_wake_all(futex: &AtomicU32) {
     unsafe { zircon::zx_futex_wake(futex, u32::MAX) };
 }
+
+/// Wait for a futex_wake operation to wake us, like `futex_wait`, but with an
+/// absolute deadline instead of a relative timeout. `None` waits forever.
+///
+/// Returns false if the deadline has passed, and true in all other cases.
+pub fn futex_wait_until(futex: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+    let timeout = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(timeout) if !timeout.is_zero() => Some(timeout),
+            _ => return false,
+        },
+        None => None,
+    };
+    futex_wait(futex, expected, timeout)
+}
@@ -1,19 +1,100 @@
 mod spin_lock;
 mod atom;
-mod futex;
-mod timespec;
+cfg_if::cfg_if! {
+	if #[cfg(all(
+		not(feature = "portable-wait"),
+		not(miri),
+		any(
+			target_os = "linux",
+			target_os = "android",
+			all(target_os = "emscripten", target_feature = "atomics"),
+			target_os = "freebsd",
+			target_os = "openbsd",
+			target_os = "dragonfly",
+			target_os = "fuchsia",
+		),
+	))] {
+		mod futex;
+		mod timespec;
+	} else {
+		// Used with the `portable-wait` feature, under Miri, and on platforms that
+		// have no futex.
+		#[path = "portable_futex.rs"]
+		mod futex;
+	}
+}
 mod park;
+pub mod parking_lot;
+pub mod backoff;
+mod fairness;
+mod stats;
+mod track;
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "deadlock-detection")]
+pub mod deadlock;
+mod lockdep;
+mod owner;
+mod reentrant_spin_lock;
+mod reentrant_atom;
+mod lock_all;
+pub mod stm;
+mod seq_atom;
+mod epoch;
+mod atom_snapshot;
 mod spin_park;
+mod spin_rw_lock;
+mod poison;
+mod notify;
+mod raw_lock;
+mod busy_spin_lock;
+mod ticket_lock;
+mod mcs_lock;
+mod condvar;
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+mod shared_spin_lock;
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+mod shared_atom;
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+mod robust_lock;
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+mod pi_lock;
 pub use spin_lock::SpinLock;
-pub use atom::{Atom, Weak};
+pub use atom::{Atom, Weak, AtomGuard, MappedAtomGuard};
 pub use park::Park;
 pub use spin_park::SpinPark;
+pub use reentrant_spin_lock::ReentrantSpinLock;
+pub use reentrant_atom::{ReentrantAtom, ReentrantGuard};
+pub use seq_atom::SeqAtom;
+pub use atom_snapshot::{AtomSnapshot, Snapshot};
+pub use lock_all::{lock_all, lock_all_backoff, lock_slice, lock_slice_backoff, LockAll, LockAllRefs};
+pub use spin_rw_lock::SpinRwLock;
+pub use poison::{PoisonError, LockResult};
+pub use raw_lock::RawLock;
+#[cfg(feature = "stats")]
+pub use stats::LockStats;
+pub use busy_spin_lock::BusySpinLock;
+pub use ticket_lock::TicketLock;
+pub use mcs_lock::McsLock;
+pub use condvar::{Condvar, CondvarLock};
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+pub use shared_spin_lock::SharedSpinLock;
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+pub use shared_atom::{SharedAtom, SharedLock, RobustGuard};
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+pub use robust_lock::{RobustLock, RobustError};
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+pub use pi_lock::{PiLock, PiAtom};
 
-use std::sync::atomic::{AtomicUsize, Ordering::*};
+use std::sync::atomic::{AtomicUsize, fence, Ordering::*};
 use std::ptr::NonNull;
 use std::cell::UnsafeCell;
 // use std::hint::spin_loop;
 use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+use std::panic::{UnwindSafe, RefUnwindSafe};
 
 #[test]
 fn ut_atom_map() {
@@ -58,6 +139,1300 @@ fn ut_atom_new() {
 }
 
 #[test]
+fn ut_atom_poison() {
+	let atom = Atom::new(vec![1, 2, 3]);
+	let t_atom = atom.clone();
+	let result = std::thread::spawn(move || {
+		t_atom.lock(|x| {
+			x.push(4);
+			panic!("poison");
+		});
+	}).join();
+	assert!(result.is_err());
+	assert!(atom.is_poisoned());
+
+	// The lock was released while unwinding, so other clones don't deadlock.
+	let err = atom.checked_map(|x| x.len()).unwrap_err();
+	assert_eq!(err.into_inner(), 4);
+
+	let t_atom = atom.clone();
+	let result = std::thread::spawn(move || t_atom.get()).join();
+	assert!(result.is_err());
+
+	atom.checked_lock(|x| x.truncate(3)).unwrap_err();
+	atom.clear_poison();
+	assert!(!atom.is_poisoned());
+	assert_eq!(atom.checked_map(|x| x.clone()).unwrap(), vec![1, 2, 3]);
+
+	// The value is only handed out by the last `Atom`, poisoned or not.
+	let t_atom = atom.clone();
+	let _ = std::thread::spawn(move || t_atom.lock(|_| panic!())).join();
+	let weak = atom.downgrade();
+	let atom2 = atom.clone();
+	assert!(atom.into_inner().is_none());
+	let err = atom2.into_inner().unwrap().unwrap_err();
+	assert_eq!(err.into_inner(), vec![1, 2, 3]);
+	assert!(weak.upgrade().is_none());
+	assert_eq!(Atom::new(5).into_inner().unwrap().unwrap(), 5);
+}
+
+#[test]
+fn ut_atom_shared_map() {
+	use std::sync::Barrier;
+	use std::sync::Arc;
+
+	// Both readers must be inside `map` at the same time to pass the barrier.
+	let atom = Atom::new(5);
+	let barrier = Arc::new(Barrier::new(2));
+	let threads: Vec<_> = (0..2).map(|_| {
+		let t_atom = atom.clone();
+		let t_barrier = barrier.clone();
+		std::thread::spawn(move || {
+			t_atom.map(|x| {
+				t_barrier.wait();
+				*x
+			})
+		})
+	}).collect();
+	for t in threads {
+		assert_eq!(t.join().unwrap(), 5);
+	}
+}
+
+#[test]
+fn ut_spin_rw_lock() {
+	use std::sync::Arc;
+
+	let lock = Arc::new(SpinRwLock::new());
+	let count = Arc::new(AtomicUsize::new(0));
+	let mut threads = Vec::new();
+	for i in 0..8 {
+		let t_lock = lock.clone();
+		let t_count = count.clone();
+		threads.push(std::thread::spawn(move || {
+			for _ in 0..1000 {
+				if i % 2 == 0 {
+					t_lock.write();
+					assert_eq!(t_count.fetch_add(1, SeqCst), 0);
+					t_count.fetch_sub(1, SeqCst);
+					t_lock.write_unlock();
+				} else {
+					t_lock.read();
+					assert_eq!(t_count.load(SeqCst), 0);
+					t_lock.read_unlock();
+				}
+			}
+		}));
+	}
+	for t in threads {
+		t.join().unwrap();
+	}
+	assert!(lock.try_write());
+	lock.write_unlock();
+}
+
+#[test]
+fn ut_atom_guard() {
+	let from = Atom::new(10);
+	let to = Atom::new(0);
+
+	fn transfer(from: &Atom<i32>, to: &Atom<i32>, amount: i32) -> Option<()> {
+		let mut from = from.guard();
+		let mut to = to.guard();
+		*from = from.checked_sub(amount).filter(|x| *x >= 0)?;
+		*to += amount;
+		Some(())
+	}
+
+	assert!(transfer(&from, &to, 7).is_some());
+	assert!(transfer(&from, &to, 7).is_none());
+	assert_eq!((from.get(), to.get()), (3, 7));
+}
+
+#[test]
+fn ut_atom_mapped_guard_poison() {
+	let atom = Atom::new((1, vec![1, 2, 3]));
+	let t_atom = atom.clone();
+	let result = std::thread::spawn(move || {
+		let mut v = AtomGuard::map(t_atom.guard(), |x| &mut x.1);
+		v.push(4);
+		panic!("poison");
+	}).join();
+	assert!(result.is_err());
+	assert!(atom.is_poisoned());
+
+	let guard = atom.checked_guard().unwrap_err().into_inner();
+	assert_eq!(guard.1, vec![1, 2, 3, 4]);
+	drop(guard);
+	atom.clear_poison();
+	assert_eq!(atom.get().0, 1);
+}
+
+#[test]
+fn ut_spin_lock_timeout() {
+	use std::sync::Arc;
+	use std::time::Instant;
+
+	let lock = Arc::new(SpinLock::new());
+	lock.lock();
+	let t_lock = lock.clone();
+	let t = std::thread::spawn(move || {
+		let start = Instant::now();
+		assert!(!t_lock.try_lock());
+		assert!(!t_lock.lock_timeout(Duration::from_millis(20)));
+		assert!(start.elapsed() >= Duration::from_millis(20));
+		// Succeeds once the main thread unlocks.
+		assert!(t_lock.lock_until(Instant::now() + Duration::from_secs(10)));
+		t_lock.unlock();
+	});
+	std::thread::sleep(Duration::from_millis(50));
+	lock.unlock();
+	t.join().unwrap();
+}
+
+#[test]
+fn ut_atom_timeout() {
+	let atom = Atom::new(vec![1, 2, 3]);
+	let t_atom = atom.clone();
+	let guard = atom.guard();
+	let t = std::thread::spawn(move || {
+		assert_eq!(t_atom.try_map(|x| x.len()), None);
+		assert_eq!(t_atom.map_timeout(Duration::from_millis(10), |x| x.len()), None);
+		assert!(!t_atom.lock_timeout(Duration::from_millis(10), |x| x.push(4)));
+		assert!(t_atom.lock_timeout(Duration::from_secs(10), |x| x.push(4)));
+	});
+	std::thread::sleep(Duration::from_millis(50));
+	drop(guard);
+	t.join().unwrap();
+	assert_eq!(atom.get(), vec![1, 2, 3, 4]);
+}
+
+#[cfg(test)]
+fn raw_lock_counter<L: RawLock + 'static>(lock: L) {
+	let atom = Atom::with_lock(0usize, lock);
+	let mut threads = Vec::new();
+	for _ in 0..4 {
+		let t_atom = atom.clone();
+		threads.push(std::thread::spawn(move || {
+			for _ in 0..1000 {
+				t_atom.lock(|x| *x += 1);
+				assert!(t_atom.map(|x| *x) > 0);
+			}
+		}));
+	}
+	for t in threads {
+		t.join().unwrap();
+	}
+	assert_eq!(atom.get(), 4000);
+	let guard = atom.guard();
+	assert_eq!(atom.try_map(|x| *x), None);
+	assert!(!atom.lock_timeout(Duration::from_millis(1), |x| *x += 1));
+	drop(guard);
+	assert!(atom.try_lock(|x| *x += 1));
+}
+
+#[test]
+fn ut_raw_lock_strategies() {
+	raw_lock_counter(SpinRwLock::new());
+	raw_lock_counter(SpinLock::new());
+	raw_lock_counter(SpinPark::new());
+	raw_lock_counter(BusySpinLock::new());
+	raw_lock_counter(TicketLock::new());
+	raw_lock_counter(McsLock::new());
+}
+
+#[test]
+fn ut_backoff_strategies() {
+	use backoff::{Exponential, Sleep, Spin, SpinThenPark, Yield};
+	raw_lock_counter(SpinLock::with_backoff(Spin));
+	raw_lock_counter(SpinLock::with_backoff(Exponential::new(64)));
+	raw_lock_counter(SpinLock::with_backoff(Yield::new(10)));
+	raw_lock_counter(SpinPark::with_backoff(Sleep::new(Duration::from_micros(1), 3)));
+	raw_lock_counter(SpinPark::with_backoff(SpinThenPark::new(0)));
+	raw_lock_counter(SpinPark::with_backoff(Exponential::default()));
+
+	// A waiter that never gives up spinning still times out.
+	let lock = SpinLock::with_backoff(Spin);
+	lock.lock();
+	assert!(!lock.lock_timeout(Duration::from_millis(10)));
+	lock.unlock();
+}
+
+#[test]
+fn ut_adaptive_spinning() {
+	use backoff::Backoff;
+	use std::sync::Arc;
+	raw_lock_counter(SpinPark::adaptive());
+
+	// The first acquisition is always timed.
+	let lock = Arc::new(SpinPark::adaptive());
+	lock.lock();
+	std::thread::sleep(Duration::from_millis(1));
+	lock.unlock();
+	assert!(lock.backoff().hold_time() >= Duration::from_millis(1));
+
+	// Waiters that keep having to go to sleep stop spinning.
+	for _ in 0..4 {
+		lock.lock();
+		let t_lock = lock.clone();
+		let t = std::thread::spawn(move || {
+			t_lock.lock();
+			t_lock.unlock();
+		});
+		std::thread::sleep(Duration::from_millis(20));
+		lock.unlock();
+		t.join().unwrap();
+	}
+	assert_eq!(lock.backoff().budget(), 0);
+
+	// Spinning again once it pays off.
+	lock.backoff().spun(true);
+	assert!(lock.backoff().budget() > 0);
+}
+
+#[cfg(all(test, feature = "stats"))]
+fn contended_stats<L: RawLock + 'static>(lock: L) {
+	let atom = Atom::with_lock(0, lock);
+	let guard = atom.guard();
+	let t_atom = atom.clone();
+	let t = std::thread::spawn(move || t_atom.lock(|x| *x += 1));
+	std::thread::sleep(Duration::from_millis(20));
+	drop(guard);
+	t.join().unwrap();
+
+	let stats = atom.stats().unwrap();
+	assert_eq!(stats.acquisitions, 2);
+	assert_eq!(stats.contended, 1);
+	assert!(stats.futex_waits >= 1);
+	assert!(stats.wait_max >= Duration::from_millis(10));
+	assert!(stats.wait_total >= stats.wait_max);
+	assert!(stats.hold_max >= Duration::from_millis(20));
+	assert!(stats.hold_total >= stats.hold_max);
+
+	atom.reset_stats();
+	assert_eq!(atom.stats(), Some(LockStats::default()));
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn ut_lock_stats() {
+	contended_stats(SpinRwLock::new());
+	contended_stats(SpinLock::new());
+	contended_stats(SpinPark::new());
+	assert_eq!(Atom::with_lock(0, TicketLock::new()).stats(), None);
+
+	// Readers are counted, but don't take part in the hold times.
+	let atom = Atom::new(0);
+	atom.map(|x| *x);
+	let stats = atom.stats().unwrap();
+	assert_eq!(stats.acquisitions, 1);
+	assert_eq!(stats.hold_total, Duration::ZERO);
+}
+
+#[cfg(feature = "registry")]
+#[test]
+fn ut_registry() {
+	use registry::LockState;
+	use std::sync::mpsc;
+	let find = |name: &str| registry::dump().locks.into_iter().find(|l| l.name == name);
+
+	let orders = Atom::named("ut-orders", 0);
+	assert_eq!(find("ut-orders").unwrap().state, LockState::Free);
+
+	let (locked_tx, locked_rx) = mpsc::channel();
+	let (release_tx, release_rx) = mpsc::channel::<()>();
+	let t_orders = orders.clone();
+	let holder = std::thread::Builder::new().name("holder".into()).spawn(move || {
+		t_orders.lock(|_| {
+			locked_tx.send(()).unwrap();
+			release_rx.recv().unwrap();
+		});
+	}).unwrap();
+	locked_rx.recv().unwrap();
+	let t_orders = orders.clone();
+	let waiter = std::thread::spawn(move || t_orders.lock(|x| *x += 1));
+	while find("ut-orders").unwrap().waiters == 0 {
+		std::thread::sleep(Duration::from_millis(1));
+	}
+
+	let info = find("ut-orders").unwrap();
+	assert_ne!(info.state, LockState::Free);
+	assert_eq!(info.owner.as_ref().unwrap().name.as_deref(), Some("holder"));
+	assert_eq!(info.waiters, 1);
+	assert!(info.held_for.is_some());
+	let text = registry::dump().to_string();
+	assert!(text.contains("ut-orders: ") && text.contains("by \"holder\""), "{}", text);
+	assert!(registry::dump().to_json().contains("\"name\":\"holder\""));
+
+	release_tx.send(()).unwrap();
+	holder.join().unwrap();
+	waiter.join().unwrap();
+	let info = find("ut-orders").unwrap();
+	assert_eq!((info.state, info.owner, info.waiters, info.held_for), (LockState::Free, None, 0, None));
+
+	// Readers are counted, and locks that are gone are dropped from the registry.
+	orders.map(|_| assert_eq!(find("ut-orders").unwrap().readers, 1));
+	drop(orders);
+	assert!(find("ut-orders").is_none());
+	let lock = SpinPark::named("ut-park");
+	assert!(find("ut-park").is_some());
+	drop(lock);
+	assert!(find("ut-park").is_none());
+
+	// Locks that are gone are also dropped when more are registered, without a dump.
+	for i in 0..1000 {
+		SpinLock::named(format!("ut-gone-{i}"));
+	}
+	assert!(registry::len() < 100, "{}", registry::len());
+}
+
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+#[test]
+#[should_panic(expected = "potential deadlock")]
+fn ut_deadlock_detection() {
+	let a = Atom::new(1);
+	let b = Atom::new(2);
+	a.lock(|_| b.lock(|_| ()));
+	// The same order again, and a try-lock in the other order, are fine.
+	a.map(|_| b.map(|_| ()));
+	b.lock(|_| assert!(a.try_lock(|_| ())));
+	// Locks that are gone take their orders with them.
+	let c = Atom::new(3);
+	c.lock(|_| a.lock(|_| ()));
+	drop(c);
+	let c = Atom::new(3);
+	a.lock(|_| c.lock(|_| ()));
+	b.lock(|_| a.lock(|_| ()));
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+#[test]
+#[should_panic(expected = "locked again by the thread holding it")]
+fn ut_reentrancy_detection() {
+	let atom = Atom::with_lock(1, SpinLock::new());
+	// Timed and try locks just fail.
+	atom.lock(|_| assert_eq!(atom.try_map(|x| *x), None));
+	atom.lock(|_| assert_eq!(atom.map_timeout(Duration::from_millis(1), |x| *x), None));
+	atom.map(|_| atom.get());
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+#[test]
+#[should_panic(expected = "locked again by the thread holding it")]
+fn ut_reentrancy_detection_shared() {
+	let atom = Atom::new(1);
+	std::thread::scope(|s| {
+		atom.map(|_| {
+			// The writer waits for the outer read lock, and new readers wait for it.
+			s.spawn(|| atom.set(2));
+			std::thread::sleep(Duration::from_millis(50));
+			atom.get()
+		});
+	});
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+#[test]
+fn ut_reentrancy_detection_locks() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	fn assert_reentered(f: impl FnOnce()) {
+		let err = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+		let msg = err.downcast_ref::<String>().unwrap();
+		assert!(msg.contains("locked again by the thread holding it"), "{msg}");
+	}
+	let atom = Atom::new(1);
+	assert_reentered(|| atom.lock(|_| { atom.get(); }));
+	assert!(atom.is_poisoned());
+	let atom = Atom::new(1);
+	assert_reentered(|| atom.lock(|_| atom.lock(|_| ())));
+	// A reader would wait behind a writer queued up before its second read lock.
+	let atom = Atom::new(1);
+	assert_reentered(|| { atom.map(|_| atom.get()); });
+	assert_reentered(|| atom.map(|_| atom.lock(|_| ())));
+	// Other threads still share it, and try locks don't wait.
+	atom.map(|_| std::thread::scope(|s| s.spawn(|| atom.get()).join().unwrap()));
+	atom.map(|_| assert_eq!(atom.try_map(|x| *x), Some(1)));
+	atom.map(|_| assert!(!atom.try_lock(|_| ())));
+	atom.map(|_| assert_eq!(atom.map_timeout(Duration::from_millis(1), |x| *x), Some(1)));
+
+	fn reenter<L: RawLock>(lock: L) {
+		lock.lock();
+		assert_reentered(|| lock.lock());
+		lock.unlock();
+		lock.lock();
+		lock.unlock();
+	}
+	reenter(SpinLock::new());
+	reenter(SpinPark::new());
+	reenter(SpinRwLock::new());
+	reenter(TicketLock::new());
+	reenter(McsLock::new());
+	reenter(BusySpinLock::new());
+}
+
+#[test]
+fn ut_reentrant() {
+	use std::cell::Cell;
+	let atom = ReentrantAtom::new(Cell::new(0));
+	let mut threads = Vec::new();
+	for _ in 0..4 {
+		let atom = atom.clone();
+		threads.push(std::thread::spawn(move || {
+			for _ in 0..1000 {
+				atom.map(|outer| {
+					let x = outer.get();
+					atom.map(|inner| inner.set(x + 1));
+					assert_eq!(outer.get(), x + 1);
+				});
+			}
+		}));
+	}
+	for t in threads {
+		t.join().unwrap();
+	}
+	assert_eq!(atom.get().get(), 4000);
+
+	let lock = ReentrantSpinLock::new();
+	lock.lock();
+	lock.lock();
+	lock.unlock();
+	assert!(lock.is_locked_by_current_thread());
+	lock.unlock();
+	assert!(!lock.is_locked_by_current_thread());
+	let result = std::panic::catch_unwind(|| lock.unlock());
+	assert!(result.is_err());
+}
+
+#[test]
+fn ut_lock_all() {
+	// Threads transferring in opposite directions don't deadlock.
+	let (a, b, c) = (Atom::new(1000), Atom::new(1000), Atom::new(1000));
+	let mut threads = Vec::new();
+	for t in 0..4 {
+		let (a, b, c) = (a.clone(), b.clone(), c.clone());
+		threads.push(std::thread::spawn(move || {
+			for i in 0..1000 {
+				match (t + i) % 4 {
+					0 => lock_all((&a, &b), |(x, y)| { *x -= 1; *y += 1; }),
+					1 => lock_all((&b, &a), |(x, y)| { *x -= 1; *y += 1; }),
+					2 => lock_all_backoff((&c, &b, &a), |(x, y, z)| { *x -= 2; *y += 1; *z += 1; }),
+					_ => {
+						let atoms = [c.clone(), a.clone()];
+						lock_slice_backoff(&atoms, |v| { *v[0] += 2; *v[1] -= 2; });
+						lock_slice(&atoms, |v| { *v[1] += 2; *v[0] -= 2; });
+					}
+				}
+			}
+		}));
+	}
+	for t in threads {
+		t.join().unwrap();
+	}
+	assert_eq!(lock_all((&a, &b, &c), |(x, y, z)| *x + *y + *z), 3000);
+
+	let result = std::panic::catch_unwind(|| lock_all((&a, &b, &a), |_| ()));
+	assert!(result.is_err());
+	assert_eq!(lock_all((&a,), |(x,)| *x), lock_slice(std::slice::from_ref(&a), |v| *v[0]));
+
+	// The values can borrow data that outlives the `Atom`s.
+	let (first, second) = (String::from("first"), String::from("second"));
+	let a = Atom::new(first.as_str());
+	let b = Atom::new(vec![second.as_str()]);
+	lock_all((&a, &b), |(x, y)| y.push(std::mem::replace(x, "none")));
+	assert_eq!(lock_all_backoff((&b, &a), |(y, x)| (y.len(), *x)), (2, "none"));
+}
+
+#[test]
+fn ut_stm() {
+	use stm::{atomically, retry, TVar};
+	use std::sync::Arc;
+	// Transfers between accounts keep the total, whatever order they touch them in.
+	let accounts: Vec<TVar<i64>> = (0..4).map(|_| TVar::new(1000)).collect();
+	let mut threads = Vec::new();
+	for t in 0..4 {
+		let accounts = accounts.clone();
+		threads.push(std::thread::spawn(move || {
+			for i in 0..500 {
+				let (from, to) = (&accounts[(t + i) % 4], &accounts[(t + 3 * i + 1) % 4]);
+				atomically(|tx| {
+					let amount = tx.read(from)? % 7;
+					tx.modify(from, |x| x - amount)?;
+					tx.modify(to, |x| x + amount)
+				});
+			}
+		}));
+	}
+	for t in threads {
+		t.join().unwrap();
+	}
+	let total = atomically(|tx| accounts.iter().map(|a| tx.read(a)).sum::<stm::StmResult<i64>>());
+	assert_eq!(total, 4000);
+
+	// A retrying transaction blocks until a variable it read changes.
+	let ready = TVar::new(false);
+	let value = TVar::new(0);
+	let (t_ready, t_value) = (ready.clone(), value.clone());
+	let waiter = std::thread::spawn(move || atomically(|tx| {
+		if !tx.read(&t_ready)? {
+			return retry();
+		}
+		tx.read(&t_value)
+	}));
+	std::thread::sleep(Duration::from_millis(10));
+	atomically(|tx| {
+		tx.write(&value, 42);
+		tx.write(&ready, true);
+		Ok(())
+	});
+	assert_eq!(waiter.join().unwrap(), 42);
+
+	// Commits to other variables don't run it again.
+	let ready = TVar::new(false);
+	let other = TVar::new(0);
+	let runs = Arc::new(AtomicUsize::new(0));
+	let (t_ready, t_runs) = (ready.clone(), runs.clone());
+	let waiter = std::thread::spawn(move || atomically(|tx| {
+		t_runs.fetch_add(1, SeqCst);
+		if !tx.read(&t_ready)? {
+			return retry();
+		}
+		Ok(())
+	}));
+	std::thread::sleep(Duration::from_millis(10));
+	for _ in 0..10 {
+		atomically(|tx| tx.modify(&other, |x| x + 1));
+	}
+	std::thread::sleep(Duration::from_millis(10));
+	atomically(|tx| {
+		tx.write(&ready, true);
+		Ok(())
+	});
+	waiter.join().unwrap();
+	assert_eq!(runs.load(SeqCst), 2);
+
+	// `or_else` undoes the writes of the alternative that retried.
+	let result = atomically(|tx| tx.or_else(
+		|tx| {
+			tx.write(&value, 1);
+			retry()
+		},
+		|tx| tx.read(&value),
+	));
+	assert_eq!((result, value.get()), (42, 42));
+}
+
+#[test]
+fn ut_stm_panicking_drop() {
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use stm::{atomically, TVar};
+
+	#[derive(Clone)]
+	struct Bomb(bool);
+	impl Drop for Bomb {
+		fn drop(&mut self) {
+			if self.0 {
+				panic!("boom");
+			}
+		}
+	}
+
+	let bomb = TVar::new(Bomb(true));
+	let count = TVar::new(0);
+	let result = catch_unwind(AssertUnwindSafe(|| atomically(|tx| {
+		tx.write(&bomb, Bomb(false));
+		tx.modify(&count, |x| x + 1)
+	})));
+	assert!(result.is_err());
+	// The writes are made, and the `TVar`s unlocked.
+	assert!(!bomb.get().0);
+	assert_eq!(count.get(), 1);
+	atomically(|tx| {
+		tx.write(&bomb, Bomb(false));
+		tx.modify(&count, |x| x + 1)
+	});
+	assert_eq!(count.get(), 2);
+}
+
+#[test]
+fn ut_seq_atom() {
+	let atom = SeqAtom::new((0u64, 0u64, false));
+	let mut readers = Vec::new();
+	for _ in 0..3 {
+		let atom = atom.clone();
+		readers.push(std::thread::spawn(move || {
+			let mut last = 0;
+			loop {
+				let (a, b, done) = atom.get();
+				assert_eq!(a, b);
+				assert!(a >= last);
+				last = a;
+				if done {
+					break;
+				}
+			}
+		}));
+	}
+	for i in 1..=10000 {
+		atom.set((i, i, false));
+	}
+	atom.lock(|x| x.2 = true);
+	for t in readers {
+		t.join().unwrap();
+	}
+
+	// A panic leaves the value as it was.
+	let result = std::panic::catch_unwind(|| atom.lock(|x| {
+		x.0 = 0;
+		panic!();
+	}));
+	assert!(result.is_err());
+	assert_eq!(atom.get(), (10000, 10000, true));
+	assert_eq!(atom.map_mut(|x| std::mem::replace(&mut x.0, 1)), 10000);
+}
+
+#[test]
+fn ut_atom_snapshot() {
+	let atom = AtomSnapshot::new((0u64, vec![0u64; 8]));
+	let mut readers = Vec::new();
+	for _ in 0..3 {
+		let atom = atom.clone();
+		readers.push(std::thread::spawn(move || {
+			let mut last = 0;
+			loop {
+				let snapshot = atom.load();
+				assert!(snapshot.1.iter().all(|&x| x == snapshot.0));
+				assert!(snapshot.0 >= last);
+				last = snapshot.0;
+				if last == u64::MAX {
+					break;
+				}
+			}
+		}));
+	}
+	for i in 1..=10000 {
+		if i % 2 == 0 {
+			atom.store((i, vec![i; 8]));
+		} else {
+			atom.rcu(|(x, v)| (x + 1, v.iter().map(|y| y + 1).collect()));
+		}
+	}
+	atom.store((u64::MAX, vec![u64::MAX; 8]));
+	for t in readers {
+		t.join().unwrap();
+	}
+
+	// An old value lives as long as a snapshot of it. The thread holding the
+	// snapshot is pinned, so nothing retired since can be reclaimed.
+	let drops = std::sync::Arc::new(AtomicUsize::new(0));
+	let atom = AtomSnapshot::new(DropCounter(drops.clone()));
+	let snapshot = atom.load();
+	atom.store(DropCounter(drops.clone()));
+	atom.store(DropCounter(drops.clone()));
+	assert_eq!(drops.load(SeqCst), 0);
+	drop(snapshot);
+	atom.store(DropCounter(drops.clone()));
+	// Other tests may keep the epoch pinned for a while, so collect until the old
+	// values are dropped.
+	let deadline = Instant::now() + Duration::from_secs(10);
+	while drops.load(SeqCst) < 3 {
+		assert!(Instant::now() < deadline, "old values not reclaimed");
+		epoch::collect();
+		std::thread::yield_now();
+	}
+	assert_eq!(drops.load(SeqCst), 3);
+	drop(atom);
+	assert_eq!(drops.load(SeqCst), 4);
+}
+
+#[cfg(test)]
+fn fifo_order<L: RawLock + 'static>(lock: L) {
+	let atom = Atom::with_lock(Vec::new(), lock);
+	let guard = atom.guard();
+	let mut threads = Vec::new();
+	for i in 0..4 {
+		let t_atom = atom.clone();
+		threads.push(std::thread::spawn(move || t_atom.lock(|x| x.push(i))));
+		// Give the thread time to join the queue and go to sleep.
+		std::thread::sleep(Duration::from_millis(20));
+	}
+	drop(guard);
+	for t in threads {
+		t.join().unwrap();
+	}
+	assert_eq!(atom.get(), vec![0, 1, 2, 3]);
+}
+
+#[cfg(test)]
+fn barging_waiter<L: RawLock + 'static>(lock: L) {
+	use std::sync::atomic::AtomicBool;
+	use std::sync::Arc;
+	let lock = Arc::new(lock);
+	let stop = Arc::new(AtomicBool::new(false));
+	let (t_lock, t_stop) = (lock.clone(), stop.clone());
+	// The thread takes the lock back right after releasing it, before a woken
+	// waiter gets a chance to.
+	let t = std::thread::spawn(move || {
+		while !t_stop.load(Relaxed) {
+			t_lock.lock();
+			let start = Instant::now();
+			while start.elapsed() < Duration::from_micros(20) {}
+			t_lock.unlock();
+		}
+	});
+	std::thread::sleep(Duration::from_millis(10));
+	let start = Instant::now();
+	lock.lock();
+	let waited = start.elapsed();
+	lock.unlock();
+	stop.store(true, Relaxed);
+	t.join().unwrap();
+	assert!(waited < Duration::from_secs(1), "waited {:?}", waited);
+}
+
+#[test]
+fn ut_eventual_fairness() {
+	barging_waiter(SpinPark::new());
+	barging_waiter(SpinLock::new());
+}
+
+#[test]
+fn ut_fair_locks() {
+	fifo_order(TicketLock::new());
+	fifo_order(McsLock::new());
+
+	// Nested locks each take a node of their own.
+	let a = McsLock::new();
+	let b = McsLock::new();
+	a.lock();
+	b.lock();
+	assert!(!a.try_lock());
+	a.unlock();
+	assert!(a.try_lock());
+	b.unlock();
+	a.unlock();
+}
+
+#[test]
+fn ut_atom_wait_until() {
+	let queue = Atom::new(Vec::new());
+	let mut threads = Vec::new();
+	for _ in 0..4 {
+		let t_queue = queue.clone();
+		threads.push(std::thread::spawn(move || {
+			let mut sum = 0;
+			for _ in 0..250 {
+				sum += t_queue.wait_until(|q| !q.is_empty(), |q| q.pop().unwrap());
+			}
+			sum
+		}));
+	}
+	for i in 0..1000 {
+		queue.lock(|q| q.push(i));
+		queue.notify_one();
+	}
+	let sum: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+	assert_eq!(sum, (0..1000).sum());
+}
+
+#[test]
+fn ut_atom_wait_until_auto_notify() {
+	let counter = Atom::new(0);
+	counter.set_auto_notify(true);
+	assert_eq!(counter.wait_until_timeout(Duration::from_millis(10), |x| *x == 100, |x| *x), None);
+
+	let t_counter = counter.clone();
+	let t = std::thread::spawn(move || {
+		t_counter.wait_until_timeout(Duration::from_secs(10), |x| *x == 100, |x| *x)
+	});
+	for _ in 0..100 {
+		counter.lock(|x| *x += 1);
+	}
+	assert_eq!(t.join().unwrap(), Some(100));
+}
+
+#[cfg(test)]
+fn condvar_queue<L: RawLock + 'static>(lock: L) {
+	let queue = Atom::with_lock(Vec::new(), lock);
+	let cond = std::sync::Arc::new(Condvar::new());
+	let mut handles = Vec::new();
+	for _ in 0..8 {
+		let t_queue = queue.clone();
+		let t_cond = cond.clone();
+		handles.push(std::thread::spawn(move || {
+			let mut guard = t_cond.wait_while(t_queue.guard(), |q| q.is_empty());
+			guard.pop().unwrap()
+		}));
+	}
+	for i in 0..8 {
+		queue.lock(|q| q.push(i));
+		if i % 2 == 0 {
+			cond.notify_one();
+		} else {
+			cond.notify_all();
+		}
+	}
+	let mut values: Vec<i32> = handles.into_iter().map(|t| t.join().unwrap()).collect();
+	values.sort();
+	assert_eq!(values, (0..8).collect::<Vec<_>>());
+}
+
+#[test]
+fn ut_condvar() {
+	condvar_queue(SpinRwLock::new());
+	condvar_queue(SpinLock::new());
+	condvar_queue(SpinPark::new());
+	condvar_queue(TicketLock::new());
+}
+
+#[test]
+fn ut_condvar_notify_all_requeue() {
+	let state = std::sync::Arc::new((SpinLock::new(), Condvar::new(), AtomicUsize::new(0)));
+	let mut handles = Vec::new();
+	for _ in 0..16 {
+		let t_state = state.clone();
+		handles.push(std::thread::spawn(move || {
+			let (lock, cond, flag) = &*t_state;
+			lock.lock();
+			cond.wait_while(lock, |_| flag.load(Relaxed) == 0);
+			flag.fetch_add(1, Relaxed);
+			lock.unlock();
+		}));
+	}
+	std::thread::sleep(Duration::from_millis(10));
+	let (lock, cond, flag) = &*state;
+	lock.lock();
+	flag.store(1, Relaxed);
+	lock.unlock();
+	cond.notify_all();
+	for t in handles {
+		t.join().unwrap();
+	}
+	assert_eq!(flag.load(Relaxed), 17);
+
+	lock.lock();
+	let (_, timed_out) = cond.wait_timeout(lock, Duration::from_millis(1));
+	assert!(timed_out);
+	lock.unlock();
+}
+
+#[test]
+#[should_panic]
+fn ut_condvar_two_locks() {
+	let cond = Condvar::new();
+	let (a, b) = (SpinLock::new(), SpinLock::new());
+	a.lock();
+	let _ = cond.wait_timeout(&a, Duration::from_millis(1));
+	b.lock();
+	let _ = cond.wait_timeout(&b, Duration::from_millis(1));
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_shared_atom_fork() {
+	let counter = SharedAtom::new((0u64, 0u64)).unwrap();
+	let mut children = Vec::new();
+	for _ in 0..3 {
+		match unsafe { libc::fork() } {
+			0 => {
+				for _ in 0..10_000 {
+					counter.lock(|x| {
+						x.0 += 1;
+						x.1 += 2;
+					});
+				}
+				unsafe { libc::_exit(0) };
+			}
+			pid => children.push(pid),
+		}
+	}
+	for _ in 0..10_000 {
+		counter.lock(|x| {
+			x.0 += 1;
+			x.1 += 2;
+		});
+	}
+	for pid in children {
+		let mut status = 0;
+		assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+		assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+	}
+	assert_eq!(counter.get(), (40_000, 80_000));
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_shared_atom_named() {
+	let name = format!("/spinout-test-{}", std::process::id());
+	let atom = SharedAtom::create(&name, 1u32).unwrap();
+	assert!(SharedAtom::create(&name, 1u32).is_err());
+	let other = unsafe { SharedAtom::<u32>::open(&name) }.unwrap();
+	let wrong_type = unsafe { SharedAtom::<u64>::open(&name) };
+	assert!(matches!(wrong_type, Err(e) if e.kind() == std::io::ErrorKind::InvalidData));
+
+	match unsafe { libc::fork() } {
+		0 => {
+			other.lock(|x| *x += 1);
+			unsafe { libc::_exit(0) };
+		}
+		pid => {
+			let mut status = 0;
+			assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+		}
+	}
+	assert_eq!(atom.get(), 2);
+	SharedAtom::<u32>::unlink(&name).unwrap();
+	assert!(unsafe { SharedAtom::<u32>::open(&name) }.is_err());
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_robust_lock() {
+	let lock = std::sync::Arc::new(RobustLock::new());
+	let counter = std::sync::Arc::new(AtomicUsize::new(0));
+	let mut handles = Vec::new();
+	for _ in 0..4 {
+		let t_lock = lock.clone();
+		let t_counter = counter.clone();
+		handles.push(std::thread::spawn(move || {
+			for _ in 0..10_000 {
+				t_lock.lock().unwrap();
+				let x = t_counter.load(Relaxed);
+				t_counter.store(x + 1, Relaxed);
+				t_lock.unlock();
+			}
+		}));
+	}
+	for t in handles {
+		t.join().unwrap();
+	}
+	assert_eq!(counter.load(Relaxed), 40_000);
+
+	// A thread dies holding the lock while another one is waiting for it.
+	lock.lock().unwrap();
+	let t_lock = lock.clone();
+	let dying = std::thread::spawn(move || {
+		t_lock.lock().unwrap();
+	});
+	let t_lock = lock.clone();
+	let waiting = std::thread::spawn(move || {
+		std::thread::sleep(Duration::from_millis(20));
+		let r = t_lock.lock();
+		if r != Err(RobustError::NotRecoverable) {
+			t_lock.unlock();
+		}
+		r
+	});
+	std::thread::sleep(Duration::from_millis(10));
+	lock.unlock();
+	dying.join().unwrap();
+	assert_eq!(waiting.join().unwrap(), Err(RobustError::OwnerDied(())));
+
+	// The waiter didn't mark it consistent.
+	assert_eq!(lock.lock(), Err(RobustError::NotRecoverable));
+	assert_eq!(lock.try_lock(), Err(RobustError::NotRecoverable));
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_robust_lock_dropped_while_held() {
+	// Walking the robust list of the thread must not reach the freed locks.
+	let other = RobustLock::new();
+	other.lock().unwrap();
+	{
+		let lock = Box::new(RobustLock::new());
+		lock.lock().unwrap();
+	}
+	let reused = Box::new([0xffu8; std::mem::size_of::<RobustLock>()]);
+	other.unlock();
+	drop(reused);
+
+	let atom = SharedAtom::new_robust(0u32).unwrap();
+	std::mem::forget(atom.guard().unwrap());
+	drop(atom);
+	other.lock().unwrap();
+	other.unlock();
+
+	// The kernel walks the list when the thread exits.
+	std::thread::spawn(|| {
+		let lock = Box::new(RobustLock::new());
+		lock.lock().unwrap();
+		drop(lock);
+		let _reused = Box::new([0xffu8; std::mem::size_of::<RobustLock>()]);
+	}).join().unwrap();
+
+	// Only the thread holding the lock can unlock it.
+	let lock = std::sync::Arc::new(RobustLock::new());
+	lock.lock().unwrap();
+	let t_lock = lock.clone();
+	assert!(std::thread::spawn(move || t_lock.unlock()).join().is_err());
+	lock.unlock();
+	assert_eq!(lock.try_lock(), Ok(true));
+	lock.unlock();
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_robust_shared_atom_owner_died() {
+	let atom = SharedAtom::new_robust((0u32, 0u32)).unwrap();
+	match unsafe { libc::fork() } {
+		0 => {
+			let mut guard = atom.guard().unwrap();
+			guard.0 = 1;
+			std::mem::forget(guard);
+			unsafe { libc::_exit(0) };
+		}
+		pid => {
+			let mut status = 0;
+			assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+		}
+	}
+
+	match atom.guard() {
+		Err(RobustError::OwnerDied(mut guard)) => {
+			assert_eq!(*guard, (1, 0));
+			guard.1 = 1;
+			guard.mark_consistent();
+		}
+		_ => panic!("expected OwnerDied"),
+	}
+	let mut guard = atom.guard().unwrap();
+	assert_eq!(*guard, (1, 1));
+	*guard = (2, 2);
+	drop(guard);
+
+	let name = format!("/spinout-robust-test-{}", std::process::id());
+	let named = SharedAtom::create_robust(&name, 0u64).unwrap();
+	assert!(unsafe { SharedAtom::<u64>::open(&name) }.is_err());
+	let other = unsafe { SharedAtom::<u64, RobustLock>::open_robust(&name) }.unwrap();
+	*other.guard().unwrap() = 5;
+	assert_eq!(*named.guard().unwrap(), 5);
+	SharedAtom::<u64, RobustLock>::unlink(&name).unwrap();
+}
+
+#[cfg(all(target_os = "linux", not(feature = "portable-wait"), not(miri)))]
+#[test]
+fn ut_pi_lock() {
+	raw_lock_counter(PiLock::new());
+
+	// A low priority thread holds the lock while others wait for it.
+	let atom = PiAtom::with_lock(0, PiLock::new());
+	let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+	let t_atom = atom.clone();
+	let t_barrier = barrier.clone();
+	let low = std::thread::spawn(move || {
+		unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::syscall(libc::SYS_gettid) as libc::id_t, 10) };
+		let mut guard = t_atom.guard();
+		t_barrier.wait();
+		std::thread::sleep(Duration::from_millis(20));
+		*guard += 1;
+	});
+	barrier.wait();
+	assert!(!atom.try_lock(|_| ()));
+	assert!(!atom.lock_timeout(Duration::from_millis(1), |_| ()));
+	let mut high = Vec::new();
+	for _ in 0..4 {
+		let t_atom = atom.clone();
+		high.push(std::thread::spawn(move || {
+			for _ in 0..1000 {
+				t_atom.lock(|x| *x += 1);
+			}
+		}));
+	}
+	low.join().unwrap();
+	for t in high {
+		t.join().unwrap();
+	}
+	assert_eq!(atom.get(), 4001);
+
+	// The cached thread id is reset in a forked child.
+	let lock = PiLock::new();
+	lock.lock();
+	lock.unlock();
+	match unsafe { libc::fork() } {
+		0 => {
+			lock.lock();
+			let held = !lock.try_lock();
+			lock.unlock();
+			unsafe { libc::_exit(if held { 0 } else { 1 }) };
+		}
+		pid => {
+			let mut status = 0;
+			assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+			assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+		}
+	}
+}
+
+#[test]
+fn ut_parking_lot_fifo() {
+	use parking_lot::{FilterOp, ParkResult};
+
+	let key = Box::new(0u8);
+	let addr = &*key as *const u8 as usize;
+	let parked = std::sync::Arc::new(AtomicUsize::new(0));
+	let order = Atom::new(Vec::new());
+	let mut handles = Vec::new();
+	for i in 0..4 {
+		let t_parked = parked.clone();
+		let t_order = order.clone();
+		handles.push(std::thread::Builder::new().name(format!("t{}", i)).spawn(move || {
+			let r = parking_lot::park(addr, || {
+				t_parked.fetch_add(1, SeqCst);
+				true
+			}, Some(Duration::from_secs(10)));
+			t_order.lock(|o| o.push(i));
+			r
+		}).unwrap());
+		while parked.load(SeqCst) != i + 1 {
+			std::thread::yield_now();
+		}
+	}
+
+	// Skip the first thread and unpark the second.
+	let n = parking_lot::unpark_filter(addr, |t| match t.name() {
+		Some("t1") => FilterOp::Unpark,
+		Some("t2") => FilterOp::Stop,
+		_ => FilterOp::Skip,
+	});
+	assert_eq!(n, 1);
+	while order.map(|o| o.len()) != 1 {
+		std::thread::yield_now();
+	}
+	for _ in 0..3 {
+		let len = order.map(|o| o.len());
+		assert!(parking_lot::unpark_one(addr));
+		while order.map(|o| o.len()) == len {
+			std::thread::yield_now();
+		}
+	}
+	assert!(!parking_lot::unpark_one(addr));
+	for t in handles {
+		assert_eq!(t.join().unwrap(), ParkResult::Unparked);
+	}
+	assert_eq!(order.get(), vec![1, 0, 2, 3]);
+}
+
+#[test]
+fn ut_park() {
+	let park = Park::new();
+	assert!(!park.park_timeout(Duration::from_millis(1)));
+	assert_eq!(park.unpark_all_count(), 0);
+
+	let woken = std::sync::Arc::new(AtomicUsize::new(0));
+	let mut handles = Vec::new();
+	for _ in 0..4 {
+		let t_park = park.clone();
+		let t_woken = woken.clone();
+		handles.push(std::thread::spawn(move || {
+			t_park.park();
+			t_woken.fetch_add(1, SeqCst);
+		}));
+	}
+	let mut unparked = 0;
+	while unparked < 4 {
+		unparked += park.unpark_all_count();
+		std::thread::yield_now();
+	}
+	for t in handles {
+		t.join().unwrap();
+	}
+	assert_eq!(woken.load(SeqCst), 4);
+	// Woken threads were removed from the queue.
+	assert!(!park.unpark_one());
+	park.unpark();
+	park.unpark_all();
+}
+
+#[cfg(test)]
+struct DropCounter(std::sync::Arc<AtomicUsize>);
+
+#[cfg(test)]
+impl Drop for DropCounter {
+	fn drop(&mut self) {
+		self.0.fetch_add(1, SeqCst);
+	}
+}
+
+#[test]
+fn ut_weak_outlives_atom() {
+	let drops = std::sync::Arc::new(AtomicUsize::new(0));
+	let atom = Atom::new(DropCounter(drops.clone()));
+	let weak = atom.downgrade();
+	let weak2 = weak.clone();
+	assert_eq!(atom.strong_count(), 1);
+	assert_eq!(atom.weak_count(), 2);
+
+	drop(atom);
+	assert_eq!(drops.load(SeqCst), 1);
+	assert_eq!(weak.strong_count(), 0);
+	assert!(weak.upgrade().is_none());
+	drop(weak);
+	assert!(weak2.upgrade().is_none());
+	drop(weak2);
+	assert_eq!(drops.load(SeqCst), 1);
+}
+
+#[test]
+fn ut_weak_upgrade_drop_stress() {
+	// Miri is too slow for many rounds, but checks every one of them for UB.
+	let rounds = if cfg!(miri) { 5 } else { 100 };
+	for _ in 0..rounds {
+		let drops = std::sync::Arc::new(AtomicUsize::new(0));
+		let atom = Atom::new(DropCounter(drops.clone()));
+		let mut threads = Vec::new();
+		for _ in 0..4 {
+			let weak = atom.downgrade();
+			threads.push(std::thread::spawn(move || {
+				for _ in 0..100 {
+					match weak.upgrade() {
+						Some(atom) => assert!(atom.strong_count() >= 1),
+						None => break,
+					}
+				}
+			}));
+		}
+		for _ in 0..4 {
+			let t_atom = atom.clone();
+			threads.push(std::thread::spawn(move || {
+				for _ in 0..100 {
+					drop(t_atom.clone());
+				}
+				drop(t_atom.downgrade());
+			}));
+		}
+		drop(atom);
+		for t in threads {
+			t.join().unwrap();
+		}
+		assert_eq!(drops.load(SeqCst), 1);
+	}
+}
+
+#[test]
+fn ut_weak_no_resurrection_stress() {
+	let rounds = if cfg!(miri) { 10 } else { 1000 };
+	for _ in 0..rounds {
+		let drops = std::sync::Arc::new(AtomicUsize::new(0));
+		let atom = Atom::new(DropCounter(drops.clone()));
+		let weak = atom.downgrade();
+		let t = std::thread::spawn(move || {
+			// Once the value has been dropped, upgrading must keep failing.
+			let mut dead = false;
+			for _ in 0..100 {
+				match weak.upgrade() {
+					Some(_) => assert!(!dead),
+					None => dead = true,
+				}
+			}
+		});
+		drop(atom);
+		t.join().unwrap();
+		assert_eq!(drops.load(SeqCst), 1);
+	}
+}
+
+#[test]
+#[allow(clippy::assign_op_pattern)]
 fn ut_write_vec_4theads_loop() {
 	for _ in 0..10 {
 		let atom = Atom::new(vec![1, 1]);
@@ -103,6 +1478,7 @@ fn ut_write_vec_4theads_loop() {
 }
 
 #[test]
+#[allow(clippy::question_mark, clippy::wrong_self_convention)]
 fn ut_cyclic() {
 	struct DoublyLinkedNode {
 		value: i32,
@@ -0,0 +1,243 @@
+use super::*;
+
+/// A tuple of up to six references to `Atom`s, that `lock_all` can lock together.
+/// The values can borrow data, as the references to them handed to the function
+/// can't outlive the tuple.
+///
+/// Two threads that lock the same `Atom`s in opposite orders, each holding one and
+/// waiting for the other, wait forever. `lock_all` and `lock_slice` always lock in
+/// the order of the addresses of the `Atom`s, so all threads locking them this way
+/// agree on the order. `lock_all_backoff` and `lock_slice_backoff` never wait for a
+/// lock while holding another, so they don't deadlock with code that locks the
+/// same `Atom`s in any other order either.
+pub trait LockAll: for<'g> LockAllRefs<'g> {
+	/// Lock all the `Atom`s in the order of their addresses.
+	fn lock_all<U>(self, f: impl for<'g> FnOnce(<Self as LockAllRefs<'g>>::Refs) -> U) -> U;
+
+	/// Lock all the `Atom`s without waiting for one while holding another.
+	fn lock_all_backoff<U>(self, f: impl for<'g> FnOnce(<Self as LockAllRefs<'g>>::Refs) -> U) -> U;
+}
+
+/// The mutable references to the values of a `LockAll` tuple that live for `'g`, in
+/// the order of the tuple.
+///
+/// A `type Refs<'g> where Self: 'g` on `LockAll` would have the compiler require
+/// `Self: 'static` of a function taking them for any `'g`. The `Bound` parameter
+/// defaults to `&'g Self`, which can only exist if the tuple outlives `'g`, so the
+/// impls may assume it without the trait saying so.
+pub trait LockAllRefs<'g, Bound = &'g Self> {
+	type Refs;
+}
+
+/// Lock all the `Atom`s of a tuple, and apply the given function to mutable
+/// references to their values. The locks are taken in the order of the addresses
+/// of the `Atom`s, and released when the function returns.
+///
+/// # Panics
+///
+/// Panics if the tuple has the same `Atom` more than once, or if one of them is
+/// poisoned.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{lock_all, Atom};
+///
+/// let a = Atom::new(100);
+/// let b = Atom::new(0);
+/// lock_all((&a, &b), |(from, to)| {
+///     *from -= 30;
+///     *to += 30;
+/// });
+/// // Locking the other way around takes the locks in the same order.
+/// let total = lock_all((&b, &a), |(b, a)| *a + *b);
+/// assert_eq!(total, 100);
+/// ```
+#[inline]
+pub fn lock_all<A: LockAll, U>(atoms: A, f: impl for<'g> FnOnce(<A as LockAllRefs<'g>>::Refs) -> U) -> U {
+	atoms.lock_all(f)
+}
+
+/// Lock all the `Atom`s of a tuple like `lock_all`, but without ever waiting for
+/// one while holding another. The thread waits for one of the locks, tries the
+/// others, and if one of them is held releases them all and starts over by
+/// waiting for that one.
+///
+/// # Panics
+///
+/// Panics if the tuple has the same `Atom` more than once, or if one of them is
+/// poisoned.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{lock_all_backoff, Atom};
+///
+/// let a = Atom::new(1);
+/// let b = Atom::new(String::from("one"));
+/// lock_all_backoff((&a, &b), |(n, s)| {
+///     *n += 1;
+///     s.push_str(" and two");
+/// });
+/// assert_eq!(b.get(), "one and two");
+/// ```
+#[inline]
+pub fn lock_all_backoff<A: LockAll, U>(atoms: A, f: impl for<'g> FnOnce(<A as LockAllRefs<'g>>::Refs) -> U) -> U {
+	atoms.lock_all_backoff(f)
+}
+
+/// Lock all the `Atom`s of a slice, and apply the given function to mutable
+/// references to their values, in the order of the slice. The locks are taken in
+/// the order of the addresses of the `Atom`s, and released when the function
+/// returns.
+///
+/// # Panics
+///
+/// Panics if the slice has the same `Atom` more than once, or if one of them is
+/// poisoned.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{lock_slice, Atom};
+///
+/// let accounts = vec![Atom::new(10), Atom::new(20), Atom::new(30)];
+/// lock_slice(&accounts, |balances| {
+///     let total: i32 = balances.iter().map(|b| **b).sum();
+///     for balance in balances.iter_mut() {
+///         **balance = total / 3;
+///     }
+/// });
+/// assert_eq!(accounts[0].get(), 20);
+/// ```
+pub fn lock_slice<T: Send, L: RawLock, U>(atoms: &[Atom<T, L>], f: impl FnOnce(&mut [&mut T]) -> U) -> U {
+	let mut guards: Vec<Option<AtomGuard<'_, T, L>>> = atoms.iter().map(|_| None).collect();
+	for i in order(atoms.iter().map(Atom::addr)) {
+		guards[i] = Some(atoms[i].guard());
+	}
+	f(&mut refs(&mut guards))
+}
+
+/// Lock all the `Atom`s of a slice like `lock_slice`, but without ever waiting for
+/// one while holding another, like `lock_all_backoff`.
+///
+/// # Panics
+///
+/// Panics if the slice has the same `Atom` more than once, or if one of them is
+/// poisoned.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{lock_slice_backoff, Atom};
+///
+/// let atoms = vec![Atom::new(1), Atom::new(2)];
+/// let sum = lock_slice_backoff(&atoms, |values| values.iter().map(|v| **v).sum::<i32>());
+/// assert_eq!(sum, 3);
+/// ```
+pub fn lock_slice_backoff<T: Send, L: RawLock, U>(atoms: &[Atom<T, L>], f: impl FnOnce(&mut [&mut T]) -> U) -> U {
+	order(atoms.iter().map(Atom::addr));
+	let mut first = 0;
+	loop {
+		let mut guards: Vec<Option<AtomGuard<'_, T, L>>> = atoms.iter().map(|_| None).collect();
+		guards[first] = Some(atoms[first].guard());
+		let mut failed = None;
+		for (i, atom) in atoms.iter().enumerate() {
+			if i != first {
+				match atom.try_guard() {
+					Some(guard) => guards[i] = Some(guard),
+					None => {
+						failed = Some(i);
+						break;
+					}
+				}
+			}
+		}
+		match failed {
+			None => return f(&mut refs(&mut guards)),
+			Some(i) => first = i,
+		}
+		drop(guards);
+		std::thread::yield_now();
+	}
+}
+
+fn refs<'g, T, L: RawLock>(guards: &'g mut [Option<AtomGuard<'_, T, L>>]) -> Vec<&'g mut T> {
+	guards.iter_mut().map(|guard| &mut **guard.as_mut().unwrap()).collect()
+}
+
+/// The indices of the `Atom`s in the order of their addresses.
+fn order(addrs: impl Iterator<Item = usize>) -> Vec<usize> {
+	let mut order: Vec<(usize, usize)> = addrs.enumerate().map(|(i, addr)| (addr, i)).collect();
+	order.sort_unstable();
+	if order.windows(2).any(|w| w[0].0 == w[1].0) {
+		panic_same_atom();
+	}
+	order.into_iter().map(|(_, i)| i).collect()
+}
+
+#[cold]
+#[inline(never)]
+fn panic_same_atom() -> ! {
+	panic!("the same Atom can't be locked twice at once");
+}
+
+macro_rules! impl_lock_all {
+	($($n:tt $T:ident $L:ident $a:ident $g:ident),+) => {
+		impl<'g, 'a, $($T: Send, $L: RawLock),+> LockAllRefs<'g, &'g Self> for ($(&'a Atom<$T, $L>,)+) {
+			type Refs = ($(&'g mut $T,)+);
+		}
+
+		impl<'a, $($T: Send, $L: RawLock),+> LockAll for ($(&'a Atom<$T, $L>,)+) {
+
+			fn lock_all<U>(self, f: impl for<'g> FnOnce(<Self as LockAllRefs<'g>>::Refs) -> U) -> U {
+				let ($($a,)+) = self;
+				$(let mut $g = None;)+
+				for i in order([$($a.addr()),+].into_iter()) {
+					match i {
+						$($n => $g = Some($a.guard()),)+
+						_ => unreachable!(),
+					}
+				}
+				f(($(&mut **$g.as_mut().unwrap(),)+))
+			}
+
+			// With a single `Atom`, the guard is always the one waited for.
+			#[allow(unused_assignments)]
+			fn lock_all_backoff<U>(self, f: impl for<'g> FnOnce(<Self as LockAllRefs<'g>>::Refs) -> U) -> U {
+				let ($($a,)+) = self;
+				order([$($a.addr()),+].into_iter());
+				let mut first = 0;
+				loop {
+					$(let mut $g = None;)+
+					match first {
+						$($n => $g = Some($a.guard()),)+
+						_ => unreachable!(),
+					}
+					let mut failed = None;
+					$(
+						if $n != first && failed.is_none() {
+							match $a.try_guard() {
+								Some(guard) => $g = Some(guard),
+								None => failed = Some($n),
+							}
+						}
+					)+
+					match failed {
+						None => return f(($(&mut **$g.as_mut().unwrap(),)+)),
+						Some(i) => first = i,
+					}
+					$(drop($g);)+
+					std::thread::yield_now();
+				}
+			}
+		}
+	};
+}
+
+impl_lock_all!(0 T0 L0 a0 g0);
+impl_lock_all!(0 T0 L0 a0 g0, 1 T1 L1 a1 g1);
+impl_lock_all!(0 T0 L0 a0 g0, 1 T1 L1 a1 g1, 2 T2 L2 a2 g2);
+impl_lock_all!(0 T0 L0 a0 g0, 1 T1 L1 a1 g1, 2 T2 L2 a2 g2, 3 T3 L3 a3 g3);
+impl_lock_all!(0 T0 L0 a0 g0, 1 T1 L1 a1 g1, 2 T2 L2 a2 g2, 3 T3 L3 a3 g3, 4 T4 L4 a4 g4);
+impl_lock_all!(0 T0 L0 a0 g0, 1 T1 L1 a1 g1, 2 T2 L2 a2 g2, 3 T3 L3 a3 g3, 4 T4 L4 a4 g4, 5 T5 L5 a5 g5);
@@ -0,0 +1,21 @@
+//! The hooks `Atom` calls for `deadlock`. Without the `deadlock-detection` feature,
+//! or in release builds, they do nothing, like `Stats` and `Track`.
+
+#[cfg(all(feature = "deadlock-detection", debug_assertions))]
+pub(crate) use crate::deadlock::{acquire, acquired, forget, released};
+
+#[cfg(not(all(feature = "deadlock-detection", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn acquire<L>(_lock: &L) {}
+
+#[cfg(not(all(feature = "deadlock-detection", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn acquired<L>(_lock: &L) {}
+
+#[cfg(not(all(feature = "deadlock-detection", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn released<L>(_lock: &L) {}
+
+#[cfg(not(all(feature = "deadlock-detection", debug_assertions)))]
+#[inline(always)]
+pub(crate) fn forget<L>(_lock: &L) {}
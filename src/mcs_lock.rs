@@ -0,0 +1,234 @@
+use super::*;
+use std::cell::RefCell;
+use std::hint::spin_loop;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicU32};
+use std::sync::Mutex;
+use crate::futex::{futex_wait, futex_wake};
+use crate::owner::Owner;
+
+const UNLOCKED: u32 = 0;
+const WAITING: u32 = 1;
+const PARKED: u32 = 2;
+
+/// A place in the queue of an `McsLock`. Each node has a cache line of its own, so
+/// that a waiter spinning on it doesn't disturb the others.
+#[repr(align(64))]
+struct McsNode {
+	state: AtomicU32,
+	next: AtomicPtr<McsNode>,
+}
+
+/// Nodes of threads that have exited. Nodes are never freed, as the thread
+/// handing the lock over might still wake up a node after its owner has already
+/// taken the lock and moved on.
+static NODE_POOL: Mutex<Vec<&'static McsNode>> = Mutex::new(Vec::new());
+
+struct NodeCache(RefCell<Vec<&'static McsNode>>);
+
+impl Drop for NodeCache {
+	fn drop(&mut self) {
+		let nodes = self.0.get_mut();
+		NODE_POOL.lock().unwrap_or_else(|e| e.into_inner()).append(nodes);
+	}
+}
+
+thread_local! {
+	static NODES: NodeCache = const { NodeCache(RefCell::new(Vec::new())) };
+}
+
+/// Take a node for a lock operation. A thread needs one node for every
+/// `McsLock` it holds.
+fn take_node() -> &'static McsNode {
+	let node = NODES
+		.try_with(|nodes| nodes.0.borrow_mut().pop())
+		.ok()
+		.flatten()
+		.or_else(|| NODE_POOL.lock().unwrap_or_else(|e| e.into_inner()).pop());
+	node.unwrap_or_else(|| {
+		Box::leak(Box::new(McsNode {
+			state: AtomicU32::new(UNLOCKED),
+			next: AtomicPtr::new(null_mut()),
+		}))
+	})
+}
+
+fn give_node(node: &'static McsNode) {
+	if NODES.try_with(|nodes| nodes.0.borrow_mut().push(node)).is_err() {
+		NODE_POOL.lock().unwrap_or_else(|e| e.into_inner()).push(node);
+	}
+}
+
+pub struct McsLock {
+	tail: AtomicPtr<McsNode>,
+	holder: AtomicPtr<McsNode>,
+	owner: Owner,
+}
+
+impl McsLock {
+
+	/// Create a new `McsLock`. An MCS lock keeps the waiting threads in a queue and
+	/// hands the lock to them in the order they arrived, like a `TicketLock`. Each
+	/// waiter spins on its own node instead of on the lock, and then sleeps on the
+	/// futex word of the node, so releasing the lock only wakes up the thread that
+	/// gets it next.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::McsLock;
+	///
+	/// let lock = McsLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		McsLock {
+			tail: AtomicPtr::new(null_mut()),
+			holder: AtomicPtr::new(null_mut()),
+			owner: Owner::new(),
+		}
+	}
+
+	/// Lock the `McsLock`. This is a blocking operation. The calling thread joins
+	/// the end of the queue and waits until the thread ahead of it hands the lock
+	/// over.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::McsLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(McsLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// let t = std::thread::spawn(move || {
+	///     lock2.lock();
+	///     // do something
+	///     lock2.unlock();
+	/// });
+	///
+	/// lock.lock();
+	/// // do something
+	/// lock.unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn lock(&self) {
+		// Check before joining the queue, which the thread could never leave.
+		self.owner.check("McsLock");
+		let node = take_node();
+		node.next.store(null_mut(), Relaxed);
+		node.state.store(WAITING, Relaxed);
+		let prev = self.tail.swap(node as *const McsNode as *mut McsNode, AcqRel);
+		if !prev.is_null() {
+			unsafe { (*prev).next.store(node as *const McsNode as *mut McsNode, Release) };
+			Self::wait(node);
+		}
+		self.holder.store(node as *const McsNode as *mut McsNode, Relaxed);
+		self.owner.locked();
+	}
+
+	#[cold]
+	fn wait(node: &McsNode) {
+		for _ in 0..100 {
+			if node.state.load(Acquire) == UNLOCKED {
+				return;
+			}
+			spin_loop();
+		}
+		if node.state.compare_exchange(WAITING, PARKED, Acquire, Acquire).is_err() {
+			return;
+		}
+		while node.state.load(Acquire) != UNLOCKED {
+			futex_wait(&node.state, PARKED, None);
+		}
+	}
+
+	/// Try to lock the `McsLock` without blocking. Only succeeds if the lock is free
+	/// and no other thread is waiting for it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::McsLock;
+	///
+	/// let lock = McsLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		if !self.tail.load(Relaxed).is_null() {
+			return false;
+		}
+		let node = take_node();
+		node.next.store(null_mut(), Relaxed);
+		let ptr = node as *const McsNode as *mut McsNode;
+		if self.tail.compare_exchange(null_mut(), ptr, Acquire, Relaxed).is_ok() {
+			self.holder.store(ptr, Relaxed);
+			self.owner.locked();
+			true
+		} else {
+			give_node(node);
+			false
+		}
+	}
+
+	/// Unlock the `McsLock`, handing it to the next thread in line.
+	#[inline]
+	pub fn unlock(&self) {
+		self.owner.unlocking();
+		let node = unsafe { &*self.holder.load(Relaxed) };
+		let mut next = node.next.load(Acquire);
+		if next.is_null() {
+			let ptr = node as *const McsNode as *mut McsNode;
+			if self.tail.compare_exchange(ptr, null_mut(), Release, Relaxed).is_ok() {
+				give_node(node);
+				return;
+			}
+			// A thread has joined the queue, but hasn't linked itself to us yet.
+			loop {
+				next = node.next.load(Acquire);
+				if !next.is_null() {
+					break;
+				}
+				spin_loop();
+			}
+		}
+		// Nodes are never freed, so waking up the next node is fine even if its
+		// thread has already moved on.
+		let next = unsafe { &*next };
+		if next.state.swap(UNLOCKED, Release) == PARKED {
+			futex_wake(&next.state);
+		}
+		give_node(node);
+	}
+}
+
+impl Default for McsLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for McsLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = McsLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::*};
+use std::time::Instant;
+use crate::futex::{futex_wait_until, futex_wake, futex_wake_all};
+
+/// Condition word of a lock. Waiters read the sequence number while holding the
+/// lock, release the lock and sleep until the sequence number changes. Notifiers
+/// bump the sequence number after changing the protected value, so a change that
+/// happens between a waiter checking its condition and going to sleep can't be
+/// missed.
+pub(crate) struct Notify {
+	seq: AtomicU32,
+	waiters: AtomicU32,
+	auto: AtomicBool,
+}
+
+impl Notify {
+	#[inline]
+	pub const fn new() -> Self {
+		Notify {
+			seq: AtomicU32::new(0),
+			waiters: AtomicU32::new(0),
+			auto: AtomicBool::new(false),
+		}
+	}
+
+	/// Register as a waiter and return the sequence number to wait on. Must be
+	/// called while holding the lock.
+	#[inline]
+	pub fn prepare_wait(&self) -> u32 {
+		self.waiters.fetch_add(1, SeqCst);
+		self.seq.load(SeqCst)
+	}
+
+	/// Sleep until notified or the deadline has passed, after the lock has been
+	/// released. Returns false on timeout.
+	#[inline]
+	pub fn wait(&self, seq: u32, deadline: Option<Instant>) -> bool {
+		let r = futex_wait_until(&self.seq, seq, deadline);
+		self.waiters.fetch_sub(1, Relaxed);
+		r
+	}
+
+	#[inline]
+	pub fn notify_one(&self) {
+		self.seq.fetch_add(1, SeqCst);
+		if self.waiters.load(SeqCst) != 0 {
+			futex_wake(&self.seq);
+		}
+	}
+
+	#[inline]
+	pub fn notify_all(&self) {
+		self.seq.fetch_add(1, SeqCst);
+		if self.waiters.load(SeqCst) != 0 {
+			futex_wake_all(&self.seq);
+		}
+	}
+
+	#[inline]
+	pub fn set_auto(&self, auto: bool) {
+		self.auto.store(auto, Relaxed);
+	}
+
+	/// Notify all waiters if automatic notification is enabled. Called after an
+	/// exclusive lock has been released.
+	#[inline]
+	pub fn after_unlock(&self) {
+		if self.auto.load(Relaxed) {
+			self.notify_all();
+		}
+	}
+}
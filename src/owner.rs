@@ -0,0 +1,134 @@
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+use std::cell::RefCell;
+
+thread_local! {
+	static ID: u8 = const { 0 };
+}
+
+/// An id of the current thread that is never 0, unique among the threads that are
+/// alive: the address of a thread local.
+#[inline]
+pub(crate) fn current_thread() -> usize {
+	ID.with(|id| id as *const u8 as usize)
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+thread_local! {
+	/// The `Owner`s of the read locks held by the current thread, by address, once
+	/// for every read lock held.
+	static SHARED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The thread holding a lock, or the write side of a `SpinRwLock`, to catch a thread
+/// locking it again, which would wait for itself forever. The read locks of a
+/// `SpinRwLock` are kept by the threads holding them, in a thread local keyed by
+/// the address of the `Owner`. It is kept in debug builds, and with the
+/// `reentrancy-detection` feature also in release builds. Otherwise it has no
+/// fields and all of its methods do nothing, like `Stats`.
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+pub(crate) struct Owner(AtomicUsize);
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+impl Owner {
+	#[inline]
+	pub const fn new() -> Self {
+		Owner(AtomicUsize::new(0))
+	}
+
+	/// The lock was found held by a thread about to wait for it. Only the thread
+	/// holding the lock stores its own id, so a thread sees its id only if it holds
+	/// the lock itself. `lock` names the type of the lock in the panic message.
+	#[inline]
+	pub fn check(&self, lock: &'static str) {
+		if self.0.load(Relaxed) == current_thread() {
+			panic_reentered(lock);
+		}
+	}
+
+	#[inline]
+	pub fn locked(&self) {
+		self.0.store(current_thread(), Relaxed);
+	}
+
+	#[inline]
+	pub fn unlocking(&self) {
+		self.0.store(0, Relaxed);
+	}
+
+	/// The current thread is about to read lock, or to wait for the write lock. If
+	/// it holds a read lock already, it would wait for itself: a `SpinRwLock` lets
+	/// waiting writers go before new readers, so even a second read lock waits
+	/// forever once a writer queues up in between. Panics on any such nested lock,
+	/// whether a writer is waiting yet or not.
+	#[inline]
+	pub fn check_shared(&self, lock: &'static str) {
+		let addr = self as *const Self as usize;
+		if SHARED.try_with(|shared| shared.borrow().contains(&addr)).unwrap_or(false) {
+			panic_reentered_shared(lock);
+		}
+	}
+
+	#[inline]
+	pub fn locked_shared(&self) {
+		let addr = self as *const Self as usize;
+		let _ = SHARED.try_with(|shared| shared.borrow_mut().push(addr));
+	}
+
+	/// A read lock released by another thread than the one that took it isn't
+	/// found, and stays recorded for the thread that took it.
+	#[inline]
+	pub fn unlocking_shared(&self) {
+		let addr = self as *const Self as usize;
+		let _ = SHARED.try_with(|shared| {
+			let mut shared = shared.borrow_mut();
+			if let Some(i) = shared.iter().rposition(|&a| a == addr) {
+				shared.swap_remove(i);
+			}
+		});
+	}
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+#[cold]
+#[inline(never)]
+fn panic_reentered(lock: &'static str) -> ! {
+	panic!("{lock} locked again by the thread holding it, which would deadlock; use a ReentrantSpinLock to lock recursively");
+}
+
+#[cfg(any(debug_assertions, feature = "reentrancy-detection"))]
+#[cold]
+#[inline(never)]
+fn panic_reentered_shared(lock: &'static str) -> ! {
+	panic!("{lock} locked again by the thread holding it for reading, which would deadlock behind a waiting writer");
+}
+
+#[cfg(not(any(debug_assertions, feature = "reentrancy-detection")))]
+pub(crate) struct Owner;
+
+#[cfg(not(any(debug_assertions, feature = "reentrancy-detection")))]
+impl Owner {
+	#[inline(always)]
+	pub const fn new() -> Self {
+		Owner
+	}
+
+	#[inline(always)]
+	pub fn check(&self, _lock: &'static str) {}
+
+	#[inline(always)]
+	pub fn locked(&self) {}
+
+	#[inline(always)]
+	pub fn unlocking(&self) {}
+
+	#[inline(always)]
+	pub fn check_shared(&self, _lock: &'static str) {}
+
+	#[inline(always)]
+	pub fn locked_shared(&self) {}
+
+	#[inline(always)]
+	pub fn unlocking_shared(&self) {}
+}
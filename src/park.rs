@@ -1,37 +1,95 @@
 use super::*;
-use std::thread::Thread;
+use crate::parking_lot;
+use std::sync::Arc;
 
+/// A queue of parked threads. Threads park themselves with `park` and are woken
+/// up by other threads with `unpark` in the order they parked, or all at once
+/// with `unpark_all`. Clones of a `Park` share the same queue.
+///
+/// The threads are parked in the global `parking_lot`, keyed by the address of
+/// a small allocation that the clones share.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::Park;
+///
+/// let park = Park::new();
+/// let t_park = park.clone();
+/// let t = std::thread::spawn(move || t_park.park());
+///
+/// while !park.unpark_one() {
+///     std::thread::yield_now();
+/// }
+/// t.join().unwrap();
+/// ```
 #[derive(Clone)]
 pub struct Park {
-	threads: Atom<Vec<Thread>>,
+	key: Arc<u8>,
 }
 
 impl Park {
+	/// Create a new `Park` with no parked threads.
 	pub fn new() -> Self {
 		Self {
-			threads: Atom::new(vec![]),
+			key: Arc::new(0),
 		}
 	}
 
+	#[inline]
+	fn addr(&self) -> usize {
+		&*self.key as *const u8 as usize
+	}
+
+	/// Park the current thread until it's woken up by `unpark` or `unpark_all`.
 	pub fn park(&self) {
-		let thread = std::thread::current();
-		self.threads.lock(|threads| threads.push(thread));
-		std::thread::park();
+		parking_lot::park(self.addr(), || true, None);
+	}
+
+	/// Park the current thread until it's woken up or `timeout` has passed. Returns
+	/// `false` on timeout, in which case the thread is no longer in the queue.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::Park;
+	/// use std::time::Duration;
+	///
+	/// let park = Park::new();
+	/// assert!(!park.park_timeout(Duration::from_millis(1)));
+	/// assert!(!park.unpark_one());
+	/// ```
+	pub fn park_timeout(&self, timeout: Duration) -> bool {
+		parking_lot::park(self.addr(), || true, Some(timeout)).is_unparked()
 	}
 
+	/// Wake up the thread that has been parked the longest, if there is one.
+	#[inline]
 	pub fn unpark(&self) {
-		self.threads.lock(|threads| {
-			match threads.pop() {
-				Some(thread) => thread.unpark(),
-				None => {},
-			}
-		});
+		self.unpark_one();
 	}
 
+	/// Wake up all the parked threads.
+	#[inline]
 	pub fn unpark_all(&self) {
-		let threads = self.threads.map(|threads| threads.clone());
-		for thread in threads {
-			thread.unpark();
-		}
+		self.unpark_all_count();
+	}
+
+	/// Wake up the thread that has been parked the longest, like `unpark`. Returns
+	/// `true` if there was a parked thread.
+	pub fn unpark_one(&self) -> bool {
+		parking_lot::unpark_one(self.addr())
+	}
+
+	/// Wake up all the parked threads, like `unpark_all`. Returns the number of
+	/// threads woken up.
+	pub fn unpark_all_count(&self) -> usize {
+		parking_lot::unpark_all(self.addr())
+	}
+}
+
+impl Default for Park {
+	fn default() -> Self {
+		Self::new()
 	}
 }
@@ -0,0 +1,220 @@
+//! A global parking lot: threads can park on any address and be unparked by
+//! other threads through the same address, without the address holding a queue
+//! of its own. The waiting threads are kept in a hash table of FIFO queues keyed
+//! by the address.
+//!
+//! # Examples
+//!
+//! ```
+//! use spinout::parking_lot::{self, ParkResult};
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//! use std::sync::Arc;
+//!
+//! let ready = Arc::new(AtomicBool::new(false));
+//! let t_ready = ready.clone();
+//! let t = std::thread::spawn(move || {
+//!     let addr = &*t_ready as *const AtomicBool as usize;
+//!     while !t_ready.load(Ordering::Acquire) {
+//!         parking_lot::park(addr, || !t_ready.load(Ordering::Relaxed), None);
+//!     }
+//! });
+//!
+//! ready.store(true, Ordering::Release);
+//! parking_lot::unpark_all(&*ready as *const AtomicBool as usize);
+//! t.join().unwrap();
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+/// The number of queues in the table. Addresses that hash to the same queue
+/// share its lock, but are otherwise independent.
+const BUCKETS: usize = 64;
+
+struct Waiter {
+	addr: usize,
+	thread: Thread,
+	unparked: AtomicBool,
+}
+
+/// The queues are protected by the `Mutex` of std and not by one of the locks of
+/// this crate, so that the locks can be built on the parking lot.
+static TABLE: [Mutex<VecDeque<Arc<Waiter>>>; BUCKETS] = [const { Mutex::new(VecDeque::new()) }; BUCKETS];
+
+#[inline]
+fn bucket(addr: usize) -> MutexGuard<'static, VecDeque<Arc<Waiter>>> {
+	let hash = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - BUCKETS.trailing_zeros());
+	// A thread can't panic while holding the lock, so poisoning can be ignored.
+	TABLE[hash as usize].lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The result of `park`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParkResult {
+	/// The thread was unparked by another thread.
+	Unparked,
+	/// The `validate` closure returned `false`, so the thread didn't park.
+	Invalid,
+	/// The timeout passed before the thread was unparked.
+	TimedOut,
+}
+
+impl ParkResult {
+	/// Returns `true` if the thread was unparked by another thread.
+	#[inline]
+	pub fn is_unparked(self) -> bool {
+		self == ParkResult::Unparked
+	}
+}
+
+/// What `unpark_filter` should do with a parked thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterOp {
+	/// Unpark the thread and continue with the next one.
+	Unpark,
+	/// Leave the thread parked and continue with the next one.
+	Skip,
+	/// Leave the thread parked and stop.
+	Stop,
+}
+
+/// Park the current thread on `addr` until it's unparked by `unpark_one`,
+/// `unpark_all` or `unpark_filter` on the same address, or `timeout` has passed.
+///
+/// `validate` is called while holding the lock of the queue, before the thread
+/// is parked. If it returns `false` the thread doesn't park. Since an unparking
+/// thread takes the same lock, a change to the condition that `validate` checks,
+/// followed by an unpark, can't be missed. `validate` must not call any function
+/// of the parking lot.
+///
+/// Parked threads are unparked in the order they arrived. A thread that times
+/// out is removed from the queue.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::parking_lot::{self, ParkResult};
+/// use std::time::Duration;
+///
+/// let x = 0u32;
+/// let addr = &x as *const u32 as usize;
+/// assert_eq!(parking_lot::park(addr, || false, None), ParkResult::Invalid);
+/// assert_eq!(parking_lot::park(addr, || true, Some(Duration::from_millis(1))), ParkResult::TimedOut);
+/// ```
+pub fn park(addr: usize, validate: impl FnOnce() -> bool, timeout: Option<Duration>) -> ParkResult {
+	// A deadline that overflows `Instant` is treated as no deadline at all.
+	let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+	let waiter = {
+		let mut queue = bucket(addr);
+		if !validate() {
+			return ParkResult::Invalid;
+		}
+		let waiter = Arc::new(Waiter {
+			addr,
+			thread: thread::current(),
+			unparked: AtomicBool::new(false),
+		});
+		queue.push_back(waiter.clone());
+		waiter
+	};
+
+	loop {
+		if waiter.unparked.load(Acquire) {
+			return ParkResult::Unparked;
+		}
+		match deadline {
+			None => thread::park(),
+			Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+				Some(timeout) if !timeout.is_zero() => thread::park_timeout(timeout),
+				_ => break,
+			},
+		}
+	}
+
+	// Timed out, but an unparker might have taken the thread off the queue in the
+	// meantime, in which case the thread counts as unparked.
+	let mut queue = bucket(addr);
+	if waiter.unparked.load(Acquire) {
+		return ParkResult::Unparked;
+	}
+	if let Some(i) = queue.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+		queue.remove(i);
+	}
+	ParkResult::TimedOut
+}
+
+/// Unpark the thread that has been parked on `addr` the longest. Returns `true`
+/// if a thread was unparked.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::parking_lot;
+///
+/// let x = 0u32;
+/// assert!(!parking_lot::unpark_one(&x as *const u32 as usize));
+/// ```
+pub fn unpark_one(addr: usize) -> bool {
+	let mut first = true;
+	unpark_filter(addr, |_| {
+		if first {
+			first = false;
+			FilterOp::Unpark
+		} else {
+			FilterOp::Stop
+		}
+	}) == 1
+}
+
+/// Unpark all the threads parked on `addr`. Returns the number of threads
+/// unparked.
+#[inline]
+pub fn unpark_all(addr: usize) -> usize {
+	unpark_filter(addr, |_| FilterOp::Unpark)
+}
+
+/// Go through the threads parked on `addr` in the order they arrived, and unpark
+/// the ones `filter` picks. The filter is called while holding the lock of the
+/// queue, and must not call any function of the parking lot. Returns the number
+/// of threads unparked.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::parking_lot::{self, FilterOp};
+///
+/// let x = 0u32;
+/// let addr = &x as *const u32 as usize;
+/// let n = parking_lot::unpark_filter(addr, |thread| {
+///     if thread.name() == Some("worker") { FilterOp::Unpark } else { FilterOp::Skip }
+/// });
+/// assert_eq!(n, 0);
+/// ```
+pub fn unpark_filter(addr: usize, mut filter: impl FnMut(&Thread) -> FilterOp) -> usize {
+	let mut unparked = Vec::new();
+	{
+		let mut queue = bucket(addr);
+		let mut i = 0;
+		while i < queue.len() {
+			if queue[i].addr != addr {
+				i += 1;
+				continue;
+			}
+			match filter(&queue[i].thread) {
+				FilterOp::Unpark => unparked.push(queue.remove(i).unwrap()),
+				FilterOp::Skip => i += 1,
+				FilterOp::Stop => break,
+			}
+		}
+		for waiter in &unparked {
+			waiter.unparked.store(true, Release);
+		}
+	}
+	for waiter in &unparked {
+		waiter.thread.unpark();
+	}
+	unparked.len()
+}
@@ -0,0 +1,172 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use crate::futex::{current_tid, futex_lock_pi, futex_trylock_pi, futex_unlock_pi};
+
+/// An `Atom` protected by a `PiLock`.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{PiAtom, PiLock};
+///
+/// let atom: PiAtom<Vec<i32>> = PiAtom::with_lock(vec![1, 2, 3], PiLock::new());
+/// atom.lock(|x| x.push(4));
+/// assert_eq!(atom.get(), vec![1, 2, 3, 4]);
+/// ```
+pub type PiAtom<T> = Atom<T, PiLock>;
+
+pub struct PiLock(AtomicU32);
+
+impl PiLock {
+
+	/// Create a new `PiLock`. A priority inheritance lock stores the thread id of
+	/// its owner, and waits in the kernel with `FUTEX_LOCK_PI` when it's contended.
+	/// While a thread is waiting for the lock, the kernel raises the priority of the
+	/// owner to that of the waiter, so that a high priority thread isn't held up by
+	/// a low priority owner that can't get scheduled.
+	///
+	/// The lock isn't reentrant: locking it again from the thread that holds it
+	/// panics.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::PiLock;
+	///
+	/// let lock = PiLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		PiLock(AtomicU32::new(0))
+	}
+
+	/// Lock the `PiLock`. This is a blocking operation. If the lock is held by another
+	/// thread, the calling thread waits in the kernel, lending its priority to the
+	/// owner, until the lock is handed over to it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::PiLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(PiLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// let t = std::thread::spawn(move || {
+	///     lock2.lock();
+	///     // do something
+	///     lock2.unlock();
+	/// });
+	///
+	/// lock.lock();
+	/// // do something
+	/// lock.unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn lock(&self) {
+		if self.0.compare_exchange(0, current_tid(), Acquire, Relaxed).is_err() {
+			futex_lock_pi(&self.0, None);
+		}
+	}
+
+	/// Try to lock the `PiLock` without blocking. Returns `true` if the lock was
+	/// acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::PiLock;
+	///
+	/// let lock = PiLock::new();
+	/// assert!(lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		match self.0.compare_exchange(0, current_tid(), Acquire, Relaxed) {
+			Ok(_) => true,
+			// Let the kernel decide, it might have left the word in a state that only
+			// it can take over.
+			Err(_) => futex_trylock_pi(&self.0),
+		}
+	}
+
+	/// Lock the `PiLock`, giving up once `timeout` has passed. Returns `true` if the
+	/// lock was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::PiLock;
+	/// use std::sync::Arc;
+	/// use std::time::Duration;
+	///
+	/// let lock = Arc::new(PiLock::new());
+	/// lock.lock();
+	/// let lock2 = lock.clone();
+	/// let t = std::thread::spawn(move || lock2.lock_timeout(Duration::from_millis(10)));
+	/// assert!(!t.join().unwrap());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_timeout(&self, timeout: Duration) -> bool {
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.lock_until(deadline),
+			None => {
+				self.lock();
+				true
+			}
+		}
+	}
+
+	/// Lock the `PiLock`, giving up once `deadline` has passed. Returns `true` if the
+	/// lock was acquired.
+	#[inline]
+	pub fn lock_until(&self, deadline: Instant) -> bool {
+		self.0.compare_exchange(0, current_tid(), Acquire, Relaxed).is_ok()
+			|| futex_lock_pi(&self.0, Some(deadline))
+	}
+
+	/// Unlock the `PiLock`. If there are threads waiting, the kernel hands the lock
+	/// over to the one with the highest priority. Must be called by the thread that
+	/// locked it.
+	#[inline]
+	pub fn unlock(&self) {
+		if self.0.compare_exchange(current_tid(), 0, Release, Relaxed).is_err() {
+			futex_unlock_pi(&self.0);
+		}
+	}
+}
+
+impl Default for PiLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for PiLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = PiLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+
+	#[inline]
+	fn lock_until(&self, deadline: Instant) -> bool {
+		self.lock_until(deadline)
+	}
+}
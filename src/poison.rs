@@ -0,0 +1,112 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::thread;
+
+/// Poison flag of a lock. A lock is poisoned when a thread panics while
+/// holding it, which means that the protected data might be left in an
+/// inconsistent state.
+pub(crate) struct Flag(AtomicBool);
+
+/// Records whether the thread was already panicking when the lock was taken,
+/// so that only a panic that happens inside the critical section poisons it.
+#[derive(Clone, Copy)]
+pub(crate) struct Guard {
+	panicking: bool,
+}
+
+impl Flag {
+	#[inline]
+	pub const fn new() -> Self {
+		Flag(AtomicBool::new(false))
+	}
+
+	#[inline]
+	pub fn guard(&self) -> Guard {
+		Guard {
+			panicking: thread::panicking(),
+		}
+	}
+
+	#[inline]
+	pub fn done(&self, guard: &Guard) {
+		if !guard.panicking && thread::panicking() {
+			self.0.store(true, Relaxed);
+		}
+	}
+
+	#[inline]
+	pub fn get(&self) -> bool {
+		self.0.load(Relaxed)
+	}
+
+	#[inline]
+	pub fn clear(&self) {
+		self.0.store(false, Relaxed);
+	}
+}
+
+/// An error returned by the checked locking operations of an `Atom` when the
+/// lock was poisoned by a thread that panicked while holding it. The error
+/// still carries the result of the operation, which can be recovered with
+/// `into_inner`.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::Atom;
+///
+/// let atom = Atom::new(5);
+/// let t_atom = atom.clone();
+/// let _ = std::thread::spawn(move || {
+///     t_atom.lock(|_| panic!());
+/// }).join();
+///
+/// let value = atom.checked_map(|x| *x).unwrap_or_else(|e| e.into_inner());
+/// assert_eq!(value, 5);
+/// ```
+pub struct PoisonError<T> {
+	data: T,
+}
+
+/// The result of a checked locking operation on an `Atom`.
+pub type LockResult<T> = Result<T, PoisonError<T>>;
+
+impl<T> PoisonError<T> {
+	/// Create a new `PoisonError` wrapping the given value.
+	#[inline]
+	pub fn new(data: T) -> Self {
+		PoisonError { data }
+	}
+
+	/// Consume the error and return the value it carries.
+	#[inline]
+	pub fn into_inner(self) -> T {
+		self.data
+	}
+
+	/// Get a reference to the value the error carries.
+	#[inline]
+	pub fn get_ref(&self) -> &T {
+		&self.data
+	}
+
+	/// Get a mutable reference to the value the error carries.
+	#[inline]
+	pub fn get_mut(&mut self) -> &mut T {
+		&mut self.data
+	}
+}
+
+impl<T> fmt::Debug for PoisonError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("PoisonError").finish_non_exhaustive()
+	}
+}
+
+impl<T> fmt::Display for PoisonError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		"poisoned lock: another task failed inside".fmt(f)
+	}
+}
+
+impl<T> std::error::Error for PoisonError<T> {}
@@ -0,0 +1,51 @@
+//! A futex emulated with the `parking_lot`, for platforms that have no futex, and
+//! for Miri. The waiting threads are parked with `std::thread::park` in the queue
+//! for the address of the futex, and the value is checked while holding the lock
+//! of that queue, which a waking thread also takes.
+
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+use crate::parking_lot::{self, ParkResult};
+
+#[inline]
+fn addr(futex: &AtomicU32) -> usize {
+    futex as *const AtomicU32 as usize
+}
+
+/// Wait for a futex_wake operation to wake us.
+///
+/// Returns directly if the futex doesn't hold the expected value.
+///
+/// Returns false on timeout, and true in all other cases.
+#[cold]
+pub fn futex_wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    parking_lot::park(addr(futex), || futex.load(Relaxed) == expected, timeout) != ParkResult::TimedOut
+}
+
+/// Wake up one thread that's blocked on futex_wait on this futex.
+///
+/// Returns true if this actually woke up such a thread,
+/// or false if no thread was waiting on this futex.
+pub fn futex_wake(futex: &AtomicU32) -> bool {
+    parking_lot::unpark_one(addr(futex))
+}
+
+/// Wake up all threads that are waiting on futex_wait on this futex.
+pub fn futex_wake_all(futex: &AtomicU32) {
+    parking_lot::unpark_all(addr(futex));
+}
+
+/// Wait for a futex_wake operation to wake us, like `futex_wait`, but with an
+/// absolute deadline instead of a relative timeout. `None` waits forever.
+///
+/// Returns false if the deadline has passed, and true in all other cases.
+pub fn futex_wait_until(futex: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+    let timeout = match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(timeout) if !timeout.is_zero() => Some(timeout),
+            _ => return false,
+        },
+        None => None,
+    };
+    futex_wait(futex, expected, timeout)
+}
@@ -0,0 +1,144 @@
+use std::hint::spin_loop;
+use std::sync::atomic::AtomicU32;
+use std::time::Instant;
+#[cfg(feature = "stats")]
+use crate::stats::LockStats;
+
+/// A raw locking strategy that an `Atom` can be built on. `SpinRwLock`, `SpinLock`,
+/// `SpinPark`, `BusySpinLock`, `TicketLock` and `McsLock` implement it, and on Linux also
+/// `SharedSpinLock` and `PiLock`.
+///
+/// A lock only needs to provide exclusive locking. The shared locking methods,
+/// used by the reading operations of `Atom` like `map` and `get`, fall back to
+/// exclusive locking unless a lock overrides them, as `SpinRwLock` does.
+///
+/// # Safety
+///
+/// Implementations must guarantee mutual exclusion: while the lock is held
+/// exclusively no other thread may acquire it in any mode, and while it is held
+/// shared no thread may acquire it exclusively. Acquiring must synchronize with
+/// (`Acquire`) the previous release (`Release`).
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{Atom, TicketLock};
+///
+/// let atom = Atom::with_lock(vec![1, 2, 3], TicketLock::new());
+/// atom.lock(|x| x.push(4));
+/// assert_eq!(atom.get(), vec![1, 2, 3, 4]);
+/// ```
+pub unsafe trait RawLock: Send + Sync {
+	/// An unlocked instance of the lock.
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self;
+
+	/// Acquire the lock exclusively, blocking until it is available.
+	fn lock(&self);
+
+	/// Try to acquire the lock exclusively without blocking. Returns `true` if the
+	/// lock was acquired.
+	fn try_lock(&self) -> bool;
+
+	/// Release an exclusive lock.
+	fn unlock(&self);
+
+	/// Acquire the lock exclusively, giving up once the given deadline has passed.
+	/// Returns `true` if the lock was acquired.
+	///
+	/// The default implementation polls `try_lock`, yielding to other threads in
+	/// between.
+	fn lock_until(&self, deadline: Instant) -> bool {
+		poll_until(deadline, || self.try_lock())
+	}
+
+	/// Acquire the lock shared with other readers, blocking until it is available.
+	#[inline]
+	fn lock_shared(&self) {
+		self.lock();
+	}
+
+	/// Try to acquire the lock shared with other readers without blocking. Returns
+	/// `true` if the lock was acquired.
+	#[inline]
+	fn try_lock_shared(&self) -> bool {
+		self.try_lock()
+	}
+
+	/// Release a shared lock.
+	#[inline]
+	fn unlock_shared(&self) {
+		self.unlock();
+	}
+
+	/// Acquire the lock shared with other readers, giving up once the given deadline
+	/// has passed. Returns `true` if the lock was acquired.
+	#[inline]
+	fn lock_shared_until(&self, deadline: Instant) -> bool {
+		self.lock_until(deadline)
+	}
+
+	/// The futex word that the waiters of a `Condvar` can be moved onto by
+	/// `Condvar::notify_all`, instead of waking them all up at once. Returns `None`
+	/// by default, in which case all the waiters are woken up.
+	///
+	/// A lock returning a futex word must be woken up through it with `FUTEX_WAKE`
+	/// on unlock when it's marked contended, and `lock_after_wait` must mark it
+	/// contended.
+	#[inline]
+	fn requeue_word(&self) -> Option<&AtomicU32> {
+		None
+	}
+
+	/// Acquire the lock exclusively after waiting on a `Condvar`. The thread might
+	/// have been moved to wait on the `requeue_word` of the lock, so the lock has
+	/// to be marked contended to make sure the other moved threads are woken up
+	/// when it's unlocked.
+	#[inline]
+	fn lock_after_wait(&self) {
+		self.lock();
+	}
+
+	/// A snapshot of the contention statistics of the lock, with the `stats`
+	/// feature. Returns `None` by default, for locks that don't record any.
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn stats(&self) -> Option<LockStats> {
+		None
+	}
+
+	/// Reset the contention statistics of the lock to zero.
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn reset_stats(&self) {}
+}
+
+/// Releases an exclusive lock when dropped, also when the code holding the lock
+/// panics.
+pub(crate) struct Unlock<'a, L: RawLock>(pub &'a L);
+
+impl<L: RawLock> Drop for Unlock<'_, L> {
+	#[inline]
+	fn drop(&mut self) {
+		self.0.unlock();
+	}
+}
+
+/// Poll `try_lock` until it succeeds or `deadline` has passed.
+fn poll_until(deadline: Instant, try_lock: impl Fn() -> bool) -> bool {
+	let mut spin = 0u32;
+	loop {
+		if try_lock() {
+			return true;
+		}
+		if Instant::now() >= deadline {
+			return false;
+		}
+		if spin < 100 {
+			spin_loop();
+			spin += 1;
+		} else {
+			std::thread::yield_now();
+		}
+	}
+}
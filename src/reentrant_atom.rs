@@ -0,0 +1,186 @@
+use super::*;
+use std::fmt;
+use std::sync::Arc;
+
+struct ReentrantInner<T: ?Sized> {
+	lock: ReentrantSpinLock,
+	data: T,
+}
+
+/// A thread-safe reference-counted pointer like `Atom`, whose lock the thread
+/// holding it can take again, for code that calls back into itself while holding
+/// the lock. It uses a `ReentrantSpinLock`.
+///
+/// As the same thread can hold the lock more than once, the value can only be
+/// accessed through a shared reference. Use `Cell` or `RefCell` inside to change it.
+/// A panic while holding the lock doesn't poison it.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::ReentrantAtom;
+/// use std::cell::RefCell;
+///
+/// let atom = ReentrantAtom::new(RefCell::new(vec![1, 2, 3]));
+/// let len = atom.map(|v| {
+///     // Locking again on the same thread doesn't deadlock.
+///     atom.map(|v| v.borrow_mut().push(4));
+///     v.borrow().len()
+/// });
+/// assert_eq!(len, 4);
+/// ```
+pub struct ReentrantAtom<T: ?Sized> {
+	inner: Arc<ReentrantInner<T>>,
+}
+
+impl<T> ReentrantAtom<T> {
+
+	/// Create a new `ReentrantAtom` with the given value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantAtom;
+	///
+	/// let atom = ReentrantAtom::new(5);
+	/// assert_eq!(atom.get(), 5);
+	/// ```
+	#[inline]
+	pub fn new(value: T) -> Self {
+		ReentrantAtom {
+			inner: Arc::new(ReentrantInner {
+				lock: ReentrantSpinLock::new(),
+				data: value,
+			}),
+		}
+	}
+}
+
+impl<T: ?Sized> ReentrantAtom<T> {
+
+	/// Lock the `ReentrantAtom` and return a clone of the value inside.
+	#[inline]
+	pub fn get(&self) -> T where T: Clone {
+		self.map(T::clone)
+	}
+
+	/// Lock the `ReentrantAtom` and apply the given function to the value inside. If
+	/// the calling thread holds the lock already, the function is applied right away.
+	#[inline]
+	pub fn map<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+		f(&self.guard())
+	}
+
+	/// Try to lock the `ReentrantAtom` without blocking and apply the given function
+	/// to the value inside. Returns `None` if the lock is held by another thread.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantAtom;
+	///
+	/// let atom = ReentrantAtom::new(1);
+	/// let guard = atom.guard();
+	/// assert_eq!(atom.try_map(|x| *x + 1), Some(2));
+	/// ```
+	#[inline]
+	pub fn try_map<U>(&self, f: impl FnOnce(&T) -> U) -> Option<U> {
+		self.try_guard().map(|guard| f(&guard))
+	}
+
+	/// Lock the `ReentrantAtom` and return a guard that gives access to the value
+	/// inside until it is dropped.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantAtom;
+	/// use std::cell::Cell;
+	///
+	/// let atom = ReentrantAtom::new(Cell::new(1));
+	/// let outer = atom.guard();
+	/// let inner = atom.guard();
+	/// inner.set(2);
+	/// drop(inner);
+	/// assert_eq!(outer.get(), 2);
+	/// ```
+	#[inline]
+	pub fn guard(&self) -> ReentrantGuard<'_, T> {
+		self.inner.lock.lock();
+		ReentrantGuard {
+			inner: &self.inner,
+			phantom: PhantomData,
+		}
+	}
+
+	/// Try to lock the `ReentrantAtom` without blocking. Returns `None` if the lock
+	/// is held by another thread.
+	#[inline]
+	pub fn try_guard(&self) -> Option<ReentrantGuard<'_, T>> {
+		self.inner.lock.try_lock().then(|| ReentrantGuard {
+			inner: &self.inner,
+			phantom: PhantomData,
+		})
+	}
+}
+
+impl<T: ?Sized> Clone for ReentrantAtom<T> {
+	fn clone(&self) -> Self {
+		ReentrantAtom {
+			inner: self.inner.clone(),
+		}
+	}
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ReentrantAtom<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.try_guard() {
+			Some(guard) => f.debug_tuple("ReentrantAtom").field(&&*guard).finish(),
+			None => f.write_str("ReentrantAtom(<locked>)"),
+		}
+	}
+}
+
+impl<T: Default> Default for ReentrantAtom<T> {
+	fn default() -> Self {
+		Self::new(T::default())
+	}
+}
+
+// Only one thread at a time gets a `&T`, like with `Atom`, so `T` only needs to be
+// `Send`.
+unsafe impl<T: ?Sized + Send> Send for ReentrantAtom<T> {}
+unsafe impl<T: ?Sized + Send> Sync for ReentrantAtom<T> {}
+
+/// A guard holding the lock of a `ReentrantAtom`, returned by `ReentrantAtom::guard`.
+/// The value inside can be accessed through `Deref`, and the lock is released once
+/// when the guard is dropped. The guard can't be sent to another thread, as the lock
+/// belongs to the thread that took it.
+pub struct ReentrantGuard<'a, T: ?Sized> {
+	inner: &'a ReentrantInner<T>,
+	phantom: PhantomData<*const ()>,
+}
+
+impl<T: ?Sized> Deref for ReentrantGuard<'_, T> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		&self.inner.data
+	}
+}
+
+impl<T: ?Sized> Drop for ReentrantGuard<'_, T> {
+	#[inline]
+	fn drop(&mut self) {
+		self.inner.lock.unlock();
+	}
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ReentrantGuard<'_, T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Debug::fmt(&**self, f)
+	}
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for ReentrantGuard<'_, T> {}
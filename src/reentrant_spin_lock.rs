@@ -0,0 +1,149 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use crate::owner::current_thread;
+
+/// A `SpinLock` that the thread holding it can lock again. It counts how many
+/// times the thread has locked it, and is released when the thread has unlocked it
+/// as many times.
+///
+/// As the thread holding it can take it more than once, it can't be the lock of an
+/// `Atom`, which hands out exclusive access. `ReentrantAtom` is built on it instead.
+pub struct ReentrantSpinLock {
+	lock: SpinLock,
+	/// The id of the thread holding the lock, or 0.
+	owner: AtomicUsize,
+	/// Only accessed by the thread holding the lock.
+	count: AtomicU32,
+}
+
+impl ReentrantSpinLock {
+
+	/// Create a new `ReentrantSpinLock`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantSpinLock;
+	///
+	/// let lock = ReentrantSpinLock::new();
+	/// lock.lock();
+	/// lock.lock();
+	/// lock.unlock();
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		ReentrantSpinLock {
+			lock: SpinLock::new(),
+			owner: AtomicUsize::new(0),
+			count: AtomicU32::new(0),
+		}
+	}
+
+	/// Lock the `ReentrantSpinLock`, blocking until it is released if it is held by
+	/// another thread. If the calling thread holds it already, it is locked once
+	/// more right away.
+	///
+	/// # Panics
+	///
+	/// Panics if the thread has locked it `u32::MAX` times.
+	#[inline]
+	pub fn lock(&self) {
+		if !self.lock_again() {
+			self.lock.lock();
+			self.locked();
+		}
+	}
+
+	/// Try to lock the `ReentrantSpinLock` without blocking. Returns `true` if the
+	/// lock was acquired, or was already held by the calling thread, and `false` if
+	/// it is held by another thread.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantSpinLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(ReentrantSpinLock::new());
+	/// assert!(lock.try_lock());
+	/// assert!(lock.try_lock());
+	/// let lock2 = lock.clone();
+	/// assert!(!std::thread::spawn(move || lock2.try_lock()).join().unwrap());
+	/// lock.unlock();
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		if self.lock_again() {
+			return true;
+		}
+		let locked = self.lock.try_lock();
+		if locked {
+			self.locked();
+		}
+		locked
+	}
+
+	/// Unlock the `ReentrantSpinLock` once. The lock is released for other threads
+	/// when it has been unlocked as many times as it was locked.
+	///
+	/// # Panics
+	///
+	/// Panics if the calling thread doesn't hold the lock.
+	#[inline]
+	pub fn unlock(&self) {
+		assert!(
+			self.is_locked_by_current_thread(),
+			"ReentrantSpinLock unlocked by a thread that doesn't hold it"
+		);
+		let count = self.count.load(Relaxed) - 1;
+		self.count.store(count, Relaxed);
+		if count == 0 {
+			self.owner.store(0, Relaxed);
+			self.lock.unlock();
+		}
+	}
+
+	/// Returns `true` if the calling thread holds the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::ReentrantSpinLock;
+	///
+	/// let lock = ReentrantSpinLock::new();
+	/// assert!(!lock.is_locked_by_current_thread());
+	/// lock.lock();
+	/// assert!(lock.is_locked_by_current_thread());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn is_locked_by_current_thread(&self) -> bool {
+		self.owner.load(Relaxed) == current_thread()
+	}
+
+	/// Lock once more if the calling thread holds the lock. Only the thread holding
+	/// the lock stores its own id, so a thread sees its id only if it holds it.
+	#[inline]
+	fn lock_again(&self) -> bool {
+		if !self.is_locked_by_current_thread() {
+			return false;
+		}
+		let count = self.count.load(Relaxed).checked_add(1).expect("ReentrantSpinLock count overflow");
+		self.count.store(count, Relaxed);
+		true
+	}
+
+	#[inline]
+	fn locked(&self) {
+		self.owner.store(current_thread(), Relaxed);
+		self.count.store(1, Relaxed);
+	}
+}
+
+impl Default for ReentrantSpinLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
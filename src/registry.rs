@@ -0,0 +1,316 @@
+//! A registry of named locks, to find out who holds what when a program hangs.
+//!
+//! Locks created with `Atom::named`, `SpinLock::named` or `SpinPark::named` are
+//! registered by a weak reference, and record the thread holding them and the
+//! number of threads waiting for them. `dump` lists the named locks that are
+//! still alive. The `Dump` can be printed as text, or rendered as JSON with
+//! `Dump::to_json`.
+//!
+//! # Examples
+//!
+//! ```
+//! use spinout::{registry, Atom};
+//!
+//! let orders = Atom::named("orders", vec![1, 2, 3]);
+//! let guard = orders.guard();
+//! let dump = registry::dump();
+//! let info = dump.locks.iter().find(|l| l.name == "orders").unwrap();
+//! assert_eq!(info.state, registry::LockState::Locked);
+//! assert!(info.owner.is_some());
+//! drop(guard);
+//! println!("{}", registry::dump());
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// The state of a lock at the time of the dump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockState {
+	/// Nobody holds the lock.
+	Free,
+	/// The lock is held, and no thread is asleep waiting for it.
+	Locked,
+	/// The lock is held, and threads are asleep waiting for it.
+	Contended,
+}
+
+impl LockState {
+	fn as_str(self) -> &'static str {
+		match self {
+			LockState::Free => "free",
+			LockState::Locked => "locked",
+			LockState::Contended => "contended",
+		}
+	}
+}
+
+/// The current state of a lock, as seen by the lock itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inspection {
+	pub state: LockState,
+	/// The thread id of the thread holding the lock exclusively.
+	pub owner: Option<u32>,
+	/// The number of threads holding the lock shared.
+	pub readers: u32,
+	/// The number of threads waiting for the lock.
+	pub waiters: u32,
+	/// How long the lock has been held.
+	pub held_for: Option<Duration>,
+}
+
+/// A lock that can be registered in the registry. `SpinLock`, `SpinPark` and
+/// `SpinRwLock` implement it.
+pub trait Inspect {
+	/// Start recording the owner and the waiters of the lock. Called when the lock
+	/// is registered.
+	fn track(&self);
+
+	/// The current state of the lock.
+	fn inspect(&self) -> Inspection;
+}
+
+/// A thread holding a lock.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+	/// The id of the thread, as given by `gettid` on Linux.
+	pub tid: u32,
+	/// The name of the thread, if it has one.
+	pub name: Option<String>,
+}
+
+/// A named lock in a `Dump`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockInfo {
+	pub name: String,
+	pub state: LockState,
+	/// The thread holding the lock exclusively.
+	pub owner: Option<Owner>,
+	/// The number of threads holding the lock shared.
+	pub readers: u32,
+	/// The number of threads waiting for the lock.
+	pub waiters: u32,
+	/// How long the lock has been held.
+	pub held_for: Option<Duration>,
+}
+
+impl fmt::Display for LockInfo {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.name, self.state.as_str())?;
+		if let Some(owner) = &self.owner {
+			match &owner.name {
+				Some(name) => write!(f, " by {:?} (tid {})", name, owner.tid)?,
+				None => write!(f, " by tid {}", owner.tid)?,
+			}
+		}
+		if self.readers > 0 {
+			write!(f, " by {} readers", self.readers)?;
+		}
+		if let Some(held_for) = self.held_for {
+			write!(f, " for {:?}", held_for)?;
+		}
+		if self.waiters > 0 {
+			write!(f, ", {} waiting", self.waiters)?;
+		}
+		Ok(())
+	}
+}
+
+/// The named locks that are alive, in the order they were registered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Dump {
+	pub locks: Vec<LockInfo>,
+}
+
+impl Dump {
+	/// Render the dump as a JSON object, with the times in microseconds.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	/// use spinout::registry;
+	///
+	/// let lock = SpinLock::named("json \"example\"");
+	/// let json = registry::dump().to_json();
+	/// assert!(json.contains(r#"{"name":"json \"example\"","state":"free","owner":null,"#));
+	/// ```
+	pub fn to_json(&self) -> String {
+		let mut json = String::from("{\"locks\":[");
+		for (i, lock) in self.locks.iter().enumerate() {
+			if i > 0 {
+				json.push(',');
+			}
+			json.push_str("{\"name\":");
+			push_json_str(&mut json, &lock.name);
+			let _ = write!(json, ",\"state\":\"{}\",\"owner\":", lock.state.as_str());
+			match &lock.owner {
+				Some(owner) => {
+					let _ = write!(json, "{{\"tid\":{},\"name\":", owner.tid);
+					match &owner.name {
+						Some(name) => push_json_str(&mut json, name),
+						None => json.push_str("null"),
+					}
+					json.push('}');
+				}
+				None => json.push_str("null"),
+			}
+			let _ = write!(json, ",\"readers\":{},\"waiters\":{},\"held_for_us\":", lock.readers, lock.waiters);
+			match lock.held_for {
+				Some(held_for) => {
+					let _ = write!(json, "{}", held_for.as_micros());
+				}
+				None => json.push_str("null"),
+			}
+			json.push('}');
+		}
+		json.push_str("]}");
+		json
+	}
+}
+
+impl fmt::Display for Dump {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for lock in &self.locks {
+			writeln!(f, "{}", lock)?;
+		}
+		Ok(())
+	}
+}
+
+fn push_json_str(json: &mut String, s: &str) {
+	json.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			'\n' => json.push_str("\\n"),
+			'\r' => json.push_str("\\r"),
+			'\t' => json.push_str("\\t"),
+			c if (c as u32) < 0x20 => {
+				let _ = write!(json, "\\u{:04x}", c as u32);
+			}
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+}
+
+struct Entry {
+	name: String,
+	/// Returns `None` once the lock is gone.
+	inspect: Box<dyn Fn() -> Option<Inspection> + Send + Sync>,
+}
+
+struct Locks {
+	entries: Vec<Entry>,
+	/// The number of entries at which `register` drops the locks that are gone:
+	/// twice the number that were alive the last time, so that a program that
+	/// never calls `dump` doesn't keep every lock it ever named.
+	prune_at: usize,
+}
+
+static LOCKS: Mutex<Locks> = Mutex::new(Locks { entries: Vec::new(), prune_at: 16 });
+
+/// Register a named lock. `inspect` holds a weak reference to the lock.
+pub(crate) fn register(name: String, inspect: Box<dyn Fn() -> Option<Inspection> + Send + Sync>) {
+	let mut locks = locks();
+	if locks.entries.len() >= locks.prune_at {
+		locks.entries.retain(|entry| (entry.inspect)().is_some());
+		locks.prune_at = (2 * locks.entries.len()).max(16);
+	}
+	locks.entries.push(Entry { name, inspect });
+}
+
+/// Register a named lock kept in an `Arc`.
+pub(crate) fn register_arc<L: Inspect + Send + Sync + 'static>(name: String, lock: &Arc<L>) {
+	lock.track();
+	let weak = Arc::downgrade(lock);
+	register(name, Box::new(move || weak.upgrade().map(|lock| lock.inspect())));
+}
+
+fn locks() -> MutexGuard<'static, Locks> {
+	LOCKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The number of locks registered, including those that are gone but not yet
+/// dropped from the registry.
+#[cfg(test)]
+pub(crate) fn len() -> usize {
+	locks().entries.len()
+}
+
+/// List the named locks that are still alive. Locks that are gone are dropped
+/// from the registry.
+pub fn dump() -> Dump {
+	let mut locks = locks();
+	let threads = threads();
+	let mut dump = Dump::default();
+	locks.entries.retain(|entry| {
+		let Some(inspection) = (entry.inspect)() else {
+			return false;
+		};
+		dump.locks.push(LockInfo {
+			name: entry.name.clone(),
+			state: inspection.state,
+			owner: inspection.owner.map(|tid| Owner {
+				tid,
+				name: threads.get(&tid).cloned().flatten(),
+			}),
+			readers: inspection.readers,
+			waiters: inspection.waiters,
+			held_for: inspection.held_for,
+		});
+		true
+	});
+	locks.prune_at = (2 * locks.entries.len()).max(16);
+	dump
+}
+
+/// The names of the threads that have held a named lock, by thread id.
+static THREADS: Mutex<BTreeMap<u32, Option<String>>> = Mutex::new(BTreeMap::new());
+
+fn threads() -> MutexGuard<'static, BTreeMap<u32, Option<String>>> {
+	THREADS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct ThreadEntry(u32);
+
+impl ThreadEntry {
+	fn new() -> Self {
+		let tid = thread_id();
+		let name = std::thread::current().name().map(String::from);
+		threads().insert(tid, name);
+		ThreadEntry(tid)
+	}
+}
+
+impl Drop for ThreadEntry {
+	fn drop(&mut self) {
+		threads().remove(&self.0);
+	}
+}
+
+thread_local! {
+	static THREAD: ThreadEntry = ThreadEntry::new();
+}
+
+/// The id of the current thread, registering its name on first use.
+#[inline]
+pub(crate) fn current_thread() -> u32 {
+	THREAD.try_with(|t| t.0).unwrap_or(0)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn thread_id() -> u32 {
+	unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn thread_id() -> u32 {
+	use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+	static NEXT: AtomicU32 = AtomicU32::new(1);
+	NEXT.fetch_add(1, Relaxed)
+}
@@ -0,0 +1,393 @@
+use super::*;
+use std::cell::Cell;
+use std::fmt;
+use std::ptr::{addr_of, addr_of_mut, null_mut, read_volatile, write_volatile};
+use std::sync::atomic::AtomicU32;
+use crate::futex::{
+	futex_wait_shared, futex_wake_shared, futex_wake_all_shared, get_robust_list, gettid,
+	set_robust_list, RobustListHead, FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS,
+};
+
+/// The offset of the robust list entry from the lock word. It's the same as in the
+/// `pthread_mutex_t` of glibc on 64-bit targets, so the locks can be linked into
+/// the robust list that glibc has already registered for the thread.
+const ENTRY_OFFSET: usize = 32;
+
+const CONSISTENT: u32 = 0;
+const INCONSISTENT: u32 = 1;
+const NOT_RECOVERABLE: u32 = 2;
+
+const _: () = assert!(std::mem::offset_of!(RobustLock, entry) == ENTRY_OFFSET);
+
+/// A lock that is released by the kernel when the thread or process holding it
+/// dies, like a `pthread_mutex_t` with `PTHREAD_MUTEX_ROBUST`. The lock word holds
+/// the thread id of the owner, and the lock is linked into the robust futex list
+/// of the owning thread (`set_robust_list`) while it's held.
+///
+/// The next thread to lock it after the owner died gets
+/// `RobustError::OwnerDied`, and holds the lock. The data protected by the lock
+/// might have been left half updated, so the thread should repair it and call
+/// `mark_consistent`. If the lock is unlocked without that, it becomes permanently
+/// unusable and any further attempt to lock it fails with
+/// `RobustError::NotRecoverable`.
+///
+/// The lock has no pointers that are followed by other threads, so like
+/// `SharedSpinLock` it can be placed in memory that is shared between processes.
+///
+/// A `RobustLock` dropped while the current thread holds it is taken out of the
+/// robust list of the thread. Dropping it while another thread of the process holds
+/// it aborts the process, as the kernel would write to the freed lock when that
+/// thread exits.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::{RobustError, RobustLock};
+/// use std::sync::Arc;
+///
+/// let lock = Arc::new(RobustLock::new());
+/// let t_lock = lock.clone();
+/// std::thread::spawn(move || {
+///     // The thread exits without unlocking.
+///     t_lock.lock().unwrap();
+/// }).join().unwrap();
+///
+/// assert!(matches!(lock.lock(), Err(RobustError::OwnerDied(()))));
+/// lock.mark_consistent();
+/// lock.unlock();
+/// assert!(lock.lock().is_ok());
+/// lock.unlock();
+/// ```
+#[repr(C, align(8))]
+pub struct RobustLock {
+	word: AtomicU32,
+	state: AtomicU32,
+	_pad: [u32; (ENTRY_OFFSET - 8) / 4],
+	entry: Cell<*mut RobustListHead>,
+}
+
+impl RobustLock {
+
+	/// Create a new `RobustLock`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::RobustLock;
+	///
+	/// let lock = RobustLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		RobustLock {
+			word: AtomicU32::new(0),
+			state: AtomicU32::new(CONSISTENT),
+			_pad: [0; (ENTRY_OFFSET - 8) / 4],
+			entry: Cell::new(null_mut()),
+		}
+	}
+
+	/// Lock the `RobustLock`, blocking until it is available. Returns
+	/// `Err(RobustError::OwnerDied(()))` if the lock was acquired from an owner that
+	/// died, and `Err(RobustError::NotRecoverable)` without acquiring the lock if it
+	/// was never made consistent after that.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::RobustLock;
+	///
+	/// let lock = RobustLock::new();
+	/// lock.lock().unwrap();
+	/// // do something
+	/// lock.unlock();
+	/// ```
+	pub fn lock(&self) -> Result<(), RobustError<()>> {
+		if self.state.load(Acquire) == NOT_RECOVERABLE {
+			return Err(RobustError::NotRecoverable);
+		}
+		let robust = robust_list();
+		self.set_pending(robust.head);
+		let mut waiters = 0;
+		let mut state = self.word.load(Relaxed);
+		loop {
+			if state & FUTEX_TID_MASK == 0 {
+				// Once we have waited, there might be other waiters left, so the
+				// waiters bit has to be kept set.
+				match self.word.compare_exchange(state, robust.tid | waiters, Acquire, Relaxed) {
+					Ok(_) => return self.locked(robust.head, state & FUTEX_OWNER_DIED != 0),
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				}
+			}
+			if state & FUTEX_WAITERS == 0 {
+				if let Err(s) = self.word.compare_exchange(state, state | FUTEX_WAITERS, Relaxed, Relaxed) {
+					state = s;
+					continue;
+				}
+			}
+			futex_wait_shared(&self.word, state | FUTEX_WAITERS, None);
+			waiters = FUTEX_WAITERS;
+			state = self.word.load(Relaxed);
+		}
+	}
+
+	/// Try to lock the `RobustLock` without blocking. Returns `Ok(false)` if it is
+	/// held by another thread, and otherwise the same as `lock`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::RobustLock;
+	///
+	/// let lock = RobustLock::new();
+	/// assert_eq!(lock.try_lock(), Ok(true));
+	/// assert_eq!(lock.try_lock(), Ok(false));
+	/// lock.unlock();
+	/// ```
+	pub fn try_lock(&self) -> Result<bool, RobustError<()>> {
+		if self.state.load(Acquire) == NOT_RECOVERABLE {
+			return Err(RobustError::NotRecoverable);
+		}
+		let state = self.word.load(Relaxed);
+		if state & FUTEX_TID_MASK != 0 {
+			return Ok(false);
+		}
+		let robust = robust_list();
+		self.set_pending(robust.head);
+		match self.word.compare_exchange(state, robust.tid | (state & FUTEX_WAITERS), Acquire, Relaxed) {
+			Ok(_) => self.locked(robust.head, state & FUTEX_OWNER_DIED != 0).map(|_| true),
+			Err(_) => {
+				unsafe { write_volatile(addr_of_mut!((*robust.head).list_op_pending), null_mut()) };
+				Ok(false)
+			}
+		}
+	}
+
+	/// Mark the data protected by the lock consistent again after locking it
+	/// returned `RobustError::OwnerDied`. Must be called while holding the lock.
+	#[inline]
+	pub fn mark_consistent(&self) {
+		let _ = self.state.compare_exchange(INCONSISTENT, CONSISTENT, Relaxed, Relaxed);
+	}
+
+	/// Unlock the `RobustLock`. If the lock was acquired from an owner that died
+	/// and `mark_consistent` wasn't called, the lock becomes not recoverable and all
+	/// the waiters are woken up to find out.
+	///
+	/// # Panics
+	///
+	/// Panics if the lock isn't held by the current thread, whose robust list it
+	/// would otherwise be removed from.
+	pub fn unlock(&self) {
+		let robust = robust_list();
+		if self.word.load(Relaxed) & FUTEX_TID_MASK != robust.tid {
+			panic_not_owner();
+		}
+		self.set_pending(robust.head);
+		unsafe { unlink(robust.head, self.entry_ptr()) };
+		let not_recoverable = self.state.load(Relaxed) == INCONSISTENT;
+		if not_recoverable {
+			self.state.store(NOT_RECOVERABLE, Release);
+		}
+		let state = self.word.swap(0, Release);
+		unsafe { write_volatile(addr_of_mut!((*robust.head).list_op_pending), null_mut()) };
+		if not_recoverable {
+			futex_wake_all_shared(&self.word);
+		} else if state & FUTEX_WAITERS != 0 {
+			futex_wake_shared(&self.word);
+		}
+	}
+
+	/// Called after the lock word was taken. Links the lock into the robust list
+	/// of the thread and checks for a dead owner.
+	fn locked(&self, head: *mut RobustListHead, owner_died: bool) -> Result<(), RobustError<()>> {
+		unsafe {
+			let entry = self.entry_ptr();
+			write_volatile(addr_of_mut!((*entry).list), read_volatile(addr_of!((*head).list)));
+			write_volatile(addr_of_mut!((*head).list), entry);
+			write_volatile(addr_of_mut!((*head).list_op_pending), null_mut());
+		}
+		if owner_died {
+			self.state.store(INCONSISTENT, Relaxed);
+			return Err(RobustError::OwnerDied(()));
+		}
+		if self.state.load(Acquire) == NOT_RECOVERABLE {
+			self.unlock();
+			return Err(RobustError::NotRecoverable);
+		}
+		Ok(())
+	}
+
+	/// Mark the lock as the one being locked or unlocked, so that the kernel checks
+	/// it if the thread dies before the robust list is updated.
+	#[inline]
+	fn set_pending(&self, head: *mut RobustListHead) {
+		unsafe { write_volatile(addr_of_mut!((*head).list_op_pending), self.entry_ptr()) };
+	}
+
+	#[inline]
+	fn entry_ptr(&self) -> *mut RobustListHead {
+		self.entry.as_ptr() as *mut RobustListHead
+	}
+
+	/// Called before the memory of the lock goes away. If the current thread holds
+	/// the lock, it's removed from the robust list of the thread, and left to the
+	/// next locker in other processes as if its owner died.
+	pub(crate) fn detach(&self) {
+		let owner = self.word.load(Relaxed) & FUTEX_TID_MASK;
+		if owner == 0 {
+			return;
+		}
+		let robust = robust_list();
+		if owner != robust.tid {
+			// The lock can't be removed from the robust list of another thread, and the
+			// kernel walks that list when the thread exits.
+			if is_thread_of_process(owner) {
+				eprintln!("RobustLock freed while another thread holds it");
+				std::process::abort();
+			}
+			return;
+		}
+		self.set_pending(robust.head);
+		// The lock might have been linked through another mapping of the same memory.
+		if unsafe { unlink(robust.head, self.entry_ptr()) } {
+			let state = self.word.swap(FUTEX_OWNER_DIED, Release);
+			if state & FUTEX_WAITERS != 0 {
+				futex_wake_all_shared(&self.word);
+			}
+		}
+		unsafe { write_volatile(addr_of_mut!((*robust.head).list_op_pending), null_mut()) };
+	}
+}
+
+#[cold]
+#[inline(never)]
+fn panic_not_owner() -> ! {
+	panic!("RobustLock unlocked by a thread that doesn't hold it");
+}
+
+/// Returns `true` if `tid` is a live thread of the current process.
+fn is_thread_of_process(tid: u32) -> bool {
+	unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, 0) == 0 }
+}
+
+impl Default for RobustLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Drop for RobustLock {
+	fn drop(&mut self) {
+		self.detach();
+	}
+}
+
+unsafe impl Send for RobustLock {}
+unsafe impl Sync for RobustLock {}
+
+/// Remove `entry` from the robust list. Returns `false` if it wasn't in the list.
+unsafe fn unlink(head: *mut RobustListHead, entry: *mut RobustListHead) -> bool {
+	let mut slot = addr_of_mut!((*head).list);
+	loop {
+		// The lowest bit marks priority inheritance futexes of libc.
+		let next = (read_volatile(slot) as usize & !1) as *mut RobustListHead;
+		if next == head {
+			return false;
+		}
+		if next == entry {
+			write_volatile(slot, read_volatile(addr_of!((*entry).list)));
+			return true;
+		}
+		slot = addr_of_mut!((*next).list);
+	}
+}
+
+#[derive(Clone, Copy)]
+struct RobustList {
+	pid: u32,
+	tid: u32,
+	head: *mut RobustListHead,
+}
+
+thread_local! {
+	static ROBUST_LIST: Cell<RobustList> = const {
+		Cell::new(RobustList { pid: 0, tid: 0, head: null_mut() })
+	};
+}
+
+/// The robust list of the current thread. The list registered by libc is used if
+/// there is one, otherwise a list is registered for the thread. A forked child
+/// gets a new thread id, so the cache is checked against the process id.
+fn robust_list() -> RobustList {
+	ROBUST_LIST.with(|cached| {
+		let pid = std::process::id();
+		let robust = cached.get();
+		if robust.pid == pid {
+			return robust;
+		}
+		let mut head = get_robust_list();
+		if head.is_null() {
+			// The kernel walks the list after the thread-locals have been destroyed,
+			// so the head is never freed.
+			head = Box::into_raw(Box::new(RobustListHead {
+				list: null_mut(),
+				futex_offset: -(ENTRY_OFFSET as libc::c_long),
+				list_op_pending: null_mut(),
+			}));
+			unsafe { (*head).list = head };
+			assert!(set_robust_list(head), "set_robust_list failed");
+		} else {
+			let offset = unsafe { (*head).futex_offset };
+			assert_eq!(offset, -(ENTRY_OFFSET as libc::c_long), "incompatible robust list registered for the thread");
+		}
+		let robust = RobustList { pid, tid: gettid(), head };
+		cached.set(robust);
+		robust
+	})
+}
+
+/// An error returned when locking a `RobustLock`, or a `SharedAtom` that uses one.
+#[derive(PartialEq, Eq)]
+pub enum RobustError<T> {
+	/// The previous owner died while holding the lock. The lock has been acquired,
+	/// and the value carries the guard or the result of the operation.
+	OwnerDied(T),
+	/// The lock was released without being marked consistent after its owner died,
+	/// and can't be used anymore. The lock was not acquired.
+	NotRecoverable,
+}
+
+impl<T> RobustError<T> {
+	/// Consume the error and return the value it carries, if the lock was acquired.
+	#[inline]
+	pub fn into_inner(self) -> Option<T> {
+		match self {
+			RobustError::OwnerDied(data) => Some(data),
+			RobustError::NotRecoverable => None,
+		}
+	}
+}
+
+impl<T> fmt::Debug for RobustError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RobustError::OwnerDied(_) => f.debug_tuple("OwnerDied").finish_non_exhaustive(),
+			RobustError::NotRecoverable => f.write_str("NotRecoverable"),
+		}
+	}
+}
+
+impl<T> fmt::Display for RobustError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			RobustError::OwnerDied(_) => "owner of the lock died while holding it".fmt(f),
+			RobustError::NotRecoverable => "lock is not recoverable".fmt(f),
+		}
+	}
+}
+
+impl<T> std::error::Error for RobustError<T> {}
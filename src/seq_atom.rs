@@ -0,0 +1,182 @@
+use super::*;
+use std::fmt;
+use std::hint::spin_loop;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use crate::raw_lock::Unlock;
+
+/// How many times `SeqAtom::get` tries to read the value while writers keep
+/// changing it, before taking the lock.
+const OPTIMISTIC_READS: u32 = 16;
+
+struct SeqInner<T, L> {
+	/// Odd while a writer is changing the value.
+	seq: AtomicUsize,
+	lock: L,
+	data: UnsafeCell<T>,
+}
+
+/// A thread-safe reference-counted pointer to a small `Copy` value, for values that
+/// are read far more often than they are written, like a pair of counters or a
+/// config struct.
+///
+/// Writers take the lock, and bump a sequence number before and after changing the
+/// value. Readers don't write to shared memory at all: they copy the value and check
+/// that the sequence number didn't change meanwhile, trying again if it did. After
+/// 16 failed tries in a row, a reader takes the lock like a writer would, so that
+/// readers can't be starved by a stream of writers.
+///
+/// A reader copies the value while a writer may be changing it, which the Rust
+/// memory model counts as a data race, even though a torn copy is never used. The
+/// copy can't go through atomics instead, as the padding bytes of a `T` can't be
+/// loaded as integers. Like crossbeam's `AtomicCell` for values too large for an
+/// atomic, `SeqAtom` copies with volatile reads and writes, which compile to plain
+/// loads and stores the race can't miscompile. Under Miri, which reports the race,
+/// readers always take the lock.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::SeqAtom;
+///
+/// let range = SeqAtom::new((0u64, 10u64));
+/// let range2 = range.clone();
+/// std::thread::spawn(move || range2.lock(|r| *r = (r.0 + 5, r.1 + 5))).join().unwrap();
+/// assert_eq!(range.get(), (5, 15));
+/// ```
+pub struct SeqAtom<T: Copy, L: RawLock = SpinLock> {
+	inner: Arc<SeqInner<T, L>>,
+}
+
+impl<T: Copy + Send> SeqAtom<T> {
+	/// Create a new `SeqAtom` with the given value.
+	#[inline]
+	pub fn new(value: T) -> Self {
+		Self::with_lock(value, SpinLock::new())
+	}
+}
+
+impl<T: Copy + Send, L: RawLock> SeqAtom<T, L> {
+	/// Create a new `SeqAtom` with the given value, whose writers take the given lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{SeqAtom, TicketLock};
+	///
+	/// let atom = SeqAtom::with_lock(1.5f64, TicketLock::new());
+	/// atom.set(2.5);
+	/// assert_eq!(atom.get(), 2.5);
+	/// ```
+	#[inline]
+	pub fn with_lock(value: T, lock: L) -> Self {
+		SeqAtom {
+			inner: Arc::new(SeqInner {
+				seq: AtomicUsize::new(0),
+				lock,
+				data: UnsafeCell::new(value),
+			}),
+		}
+	}
+
+	/// Read the value without taking the lock, unless writers keep changing it.
+	#[inline]
+	pub fn get(&self) -> T {
+		let inner = &*self.inner;
+		let tries = if cfg!(miri) { 0 } else { OPTIMISTIC_READS };
+		for _ in 0..tries {
+			let seq = inner.seq.load(Acquire);
+			if seq.is_multiple_of(2) {
+				// The copy can race with a writer, in which case it is thrown away
+				// before it is taken for a `T`. A volatile read keeps the compiler from
+				// assuming it can't race, see the docs of `SeqAtom`.
+				let value = unsafe { std::ptr::read_volatile(inner.data.get() as *const MaybeUninit<T>) };
+				fence(Acquire);
+				if inner.seq.load(Relaxed) == seq {
+					return unsafe { value.assume_init() };
+				}
+			}
+			spin_loop();
+		}
+		self.get_locked()
+	}
+
+	#[cold]
+	fn get_locked(&self) -> T {
+		let inner = &*self.inner;
+		inner.lock.lock_shared();
+		let value = unsafe { *inner.data.get() };
+		inner.lock.unlock_shared();
+		value
+	}
+
+	/// Replace the value.
+	#[inline]
+	pub fn set(&self, value: T) {
+		self.lock(|x| *x = value);
+	}
+
+	/// Lock the `SeqAtom` and apply the given function to the value. The function
+	/// changes a copy, which is written back when it returns, so readers never wait
+	/// for it, and a panic leaves the value as it was.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SeqAtom;
+	///
+	/// let atom = SeqAtom::new([0u32; 4]);
+	/// atom.lock(|x| x[2] = 7);
+	/// assert_eq!(atom.get(), [0, 0, 7, 0]);
+	/// ```
+	#[inline]
+	pub fn lock(&self, f: impl FnOnce(&mut T)) {
+		self.map_mut(f)
+	}
+
+	/// Lock the `SeqAtom`, apply the given function to a copy of the value, write the
+	/// copy back and return the result of the function.
+	pub fn map_mut<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+		let inner = &*self.inner;
+		inner.lock.lock();
+		let unlock = Unlock(&inner.lock);
+		let mut value = unsafe { *inner.data.get() };
+		let result = f(&mut value);
+		let seq = inner.seq.load(Relaxed);
+		inner.seq.store(seq.wrapping_add(1), Relaxed);
+		// Readers that see the new value also see the odd sequence number.
+		fence(Release);
+		unsafe { std::ptr::write_volatile(inner.data.get(), value) };
+		inner.seq.store(seq.wrapping_add(2), Release);
+		drop(unlock);
+		result
+	}
+}
+
+impl<T: Copy, L: RawLock> Clone for SeqAtom<T, L> {
+	fn clone(&self) -> Self {
+		SeqAtom {
+			inner: self.inner.clone(),
+		}
+	}
+}
+
+impl<T: Copy + Send + Default> Default for SeqAtom<T> {
+	fn default() -> Self {
+		Self::new(T::default())
+	}
+}
+
+impl<T: Copy + Send + fmt::Debug, L: RawLock> fmt::Debug for SeqAtom<T, L> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("SeqAtom").field(&self.get()).finish()
+	}
+}
+
+// Readers only ever get copies of the value, so `T` only needs to be `Send`.
+unsafe impl<T: Copy + Send, L: RawLock> Send for SeqAtom<T, L> {}
+unsafe impl<T: Copy + Send, L: RawLock> Sync for SeqAtom<T, L> {}
+
+// A panic while changing the value leaves it as it was.
+impl<T: Copy, L: RawLock> UnwindSafe for SeqAtom<T, L> {}
+impl<T: Copy, L: RawLock> RefUnwindSafe for SeqAtom<T, L> {}
@@ -0,0 +1,488 @@
+use super::*;
+use std::ffi::CString;
+use std::io;
+use std::mem::{align_of, size_of};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::AtomicU32;
+use crate::futex::{futex_wait_shared, futex_wake_all_shared};
+use crate::robust_lock::RobustError;
+
+/// Written to the header once the value has been initialized.
+const READY: u32 = 0x5350_4e41;
+
+/// The layout of the shared memory of a `SharedAtom`. Only plain integers, the
+/// lock word and the value are stored, so it means the same in every process.
+#[repr(C)]
+struct SharedInner<T, L> {
+	ready: AtomicU32,
+	size: u32,
+	align: u32,
+	kind: u32,
+	lock: L,
+	data: UnsafeCell<T>,
+}
+
+/// A lock that can be placed in the shared memory of a `SharedAtom`. Implemented
+/// by `SharedSpinLock` and `RobustLock`.
+///
+/// # Safety
+///
+/// The lock must work when it's mapped into several processes at different
+/// addresses, so it can't hold pointers to itself or wait on private futexes.
+/// `KIND` must be unique among the implementations.
+pub unsafe trait SharedLock: Send + Sync {
+	/// An unlocked instance of the lock.
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self;
+
+	/// Identifies the lock in the shared memory, so that a `SharedAtom` can't be
+	/// opened with a different lock than it was created with.
+	const KIND: u32;
+
+	/// Called before the process unmaps the shared memory holding the lock.
+	#[inline]
+	fn unmapping(&self) {}
+}
+
+unsafe impl SharedLock for SharedSpinLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = SharedSpinLock::new();
+	const KIND: u32 = 1;
+}
+
+unsafe impl SharedLock for RobustLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = RobustLock::new();
+	const KIND: u32 = 2;
+
+	#[inline]
+	fn unmapping(&self) {
+		self.detach();
+	}
+}
+
+/// A value of a `Copy` type, protected by a `SharedSpinLock`, that lives in shared
+/// memory and can be accessed by several processes at once. A `SharedAtom` is
+/// backed by an anonymous `memfd_create` file that is inherited over `fork`, or by
+/// a named `shm_open` object that unrelated processes can open by name.
+///
+/// The value is copied in and out of the shared memory, so it can't contain heap
+/// allocations or pointers, which would mean nothing in another process.
+///
+/// By default the value is protected by a `SharedSpinLock`. A `SharedAtom` created
+/// with `new_robust` or `create_robust` uses a `RobustLock` instead, which is
+/// released if the process holding it dies.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::SharedAtom;
+///
+/// let counter = SharedAtom::new(0u64).unwrap();
+/// match unsafe { libc::fork() } {
+///     0 => {
+///         counter.lock(|x| *x += 1);
+///         unsafe { libc::_exit(0) };
+///     }
+///     pid => {
+///         counter.lock(|x| *x += 1);
+///         unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+///     }
+/// }
+/// assert_eq!(counter.get(), 2);
+/// ```
+pub struct SharedAtom<T: Copy + Send, L: SharedLock = SharedSpinLock> {
+	fd: RawFd,
+	ptr: NonNull<SharedInner<T, L>>,
+}
+
+impl<T: Copy + Send> SharedAtom<T> {
+	/// Create a new `SharedAtom` in anonymous shared memory. Child processes created
+	/// with `fork` share the value with the parent, and the memory can be handed
+	/// to another process by passing on the file descriptor.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new([0u8; 16]).unwrap();
+	/// atom.lock(|x| x[0] = 1);
+	/// assert_eq!(atom.get()[0], 1);
+	/// ```
+	#[inline]
+	pub fn new(value: T) -> io::Result<Self> {
+		Self::new_in_memfd(value)
+	}
+
+	/// Create a new named `SharedAtom` that other processes can open with
+	/// `SharedAtom::open`. The name should start with a slash, like `"/counter"`.
+	/// Fails if an object with the same name already exists. The object stays
+	/// around until it's removed with `SharedAtom::unlink`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let name = format!("/spinout-doc-{}", std::process::id());
+	/// let atom = SharedAtom::create(&name, 5).unwrap();
+	/// let other = unsafe { SharedAtom::<i32>::open(&name) }.unwrap();
+	/// other.set(10);
+	/// assert_eq!(atom.get(), 10);
+	/// SharedAtom::<i32>::unlink(&name).unwrap();
+	/// ```
+	#[inline]
+	pub fn create(name: &str, value: T) -> io::Result<Self> {
+		Self::create_named(name, value)
+	}
+
+	/// Open a named `SharedAtom` created by another process, or this one, with
+	/// `SharedAtom::create`. Blocks until the creator has written the initial value.
+	///
+	/// Fails with `ErrorKind::InvalidData` if the object isn't the size of a
+	/// `SharedAtom<T>`, or was created with a different lock.
+	///
+	/// # Safety
+	///
+	/// The object must have been created as a `SharedAtom<T>` with the same type `T`,
+	/// by a program built with the same version of this crate. Only the size and
+	/// alignment of `T` can be checked.
+	#[inline]
+	pub unsafe fn open(name: &str) -> io::Result<Self> {
+		Self::open_named(name)
+	}
+
+	/// Get a copy of the value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new(5).unwrap();
+	/// assert_eq!(atom.get(), 5);
+	/// ```
+	#[inline]
+	pub fn get(&self) -> T {
+		self.map(|x| *x)
+	}
+
+	/// Replace the value.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new(5).unwrap();
+	/// atom.set(10);
+	/// assert_eq!(atom.get(), 10);
+	/// ```
+	#[inline]
+	pub fn set(&self, value: T) {
+		self.lock(|x| *x = value);
+	}
+
+	/// Lock the value and access it mutably inside a closure.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new((1, 2)).unwrap();
+	/// atom.lock(|x| x.0 += 1);
+	/// assert_eq!(atom.get(), (2, 2));
+	/// ```
+	#[inline]
+	pub fn lock(&self, f: impl FnOnce(&mut T)) {
+		self.map_mut(f)
+	}
+
+	/// Lock the value and map it to a new value inside a closure.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new((1, 2)).unwrap();
+	/// assert_eq!(atom.map(|x| x.0 + x.1), 3);
+	/// ```
+	#[inline]
+	pub fn map<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+		self.map_mut(|x| f(x))
+	}
+
+	/// Lock the value, mutate it and map it to a new value inside a closure.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedAtom;
+	///
+	/// let atom = SharedAtom::new(1).unwrap();
+	/// let old = atom.map_mut(|x| std::mem::replace(x, 2));
+	/// assert_eq!(old, 1);
+	/// assert_eq!(atom.get(), 2);
+	/// ```
+	#[inline]
+	pub fn map_mut<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+		let inner = self.inner();
+		inner.lock.lock();
+		let _unlock = Unlock(&inner.lock);
+		f(unsafe { &mut *inner.data.get() })
+	}
+}
+
+impl<T: Copy + Send, L: SharedLock> SharedAtom<T, L> {
+	/// Remove a named `SharedAtom`. Processes that have it open can keep using it,
+	/// but it can't be opened anymore.
+	pub fn unlink(name: &str) -> io::Result<()> {
+		let name = shm_name(name)?;
+		if unsafe { libc::shm_unlink(name.as_ptr()) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+
+	fn new_in_memfd(value: T) -> io::Result<Self> {
+		let name = CString::new("spinout-shared-atom").unwrap();
+		let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Self::init(fd, value)
+	}
+
+	fn create_named(name: &str, value: T) -> io::Result<Self> {
+		let name = shm_name(name)?;
+		let fd = unsafe {
+			libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC, 0o600)
+		};
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Self::init(fd, value)
+	}
+
+	unsafe fn open_named(name: &str) -> io::Result<Self> {
+		let name = shm_name(name)?;
+		let fd = libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC, 0);
+		if fd < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let atom = Self::map_fd(fd)?;
+		let inner = atom.inner();
+		while inner.ready.load(Acquire) != READY {
+			futex_wait_shared(&inner.ready, 0, None);
+		}
+		if inner.size as usize != size_of::<T>()
+			|| inner.align as usize != align_of::<T>()
+			|| inner.kind != L::KIND
+		{
+			return Err(invalid_data());
+		}
+		Ok(atom)
+	}
+
+	fn init(fd: RawFd, value: T) -> io::Result<Self> {
+		if unsafe { libc::ftruncate(fd, size_of::<SharedInner<T, L>>() as libc::off_t) } < 0 {
+			let err = io::Error::last_os_error();
+			unsafe { libc::close(fd) };
+			return Err(err);
+		}
+		let atom = Self::map_fd(fd)?;
+		let inner = atom.ptr.as_ptr();
+		unsafe {
+			std::ptr::addr_of_mut!((*inner).size).write(size_of::<T>() as u32);
+			std::ptr::addr_of_mut!((*inner).align).write(align_of::<T>() as u32);
+			std::ptr::addr_of_mut!((*inner).kind).write(L::KIND);
+			std::ptr::addr_of_mut!((*inner).lock).write(L::INIT);
+			std::ptr::addr_of_mut!((*inner).data).write(UnsafeCell::new(value));
+		}
+		atom.inner().ready.store(READY, Release);
+		futex_wake_all_shared(&atom.inner().ready);
+		Ok(atom)
+	}
+
+	/// Map the memory of `fd`, taking ownership of it.
+	fn map_fd(fd: RawFd) -> io::Result<Self> {
+		let len = size_of::<SharedInner<T, L>>();
+		let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+		let err = if unsafe { libc::fstat(fd, &mut stat) } < 0 {
+			Some(io::Error::last_os_error())
+		} else if stat.st_size as usize != len {
+			Some(invalid_data())
+		} else {
+			None
+		};
+		if let Some(err) = err {
+			unsafe { libc::close(fd) };
+			return Err(err);
+		}
+		let ptr = unsafe {
+			libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0)
+		};
+		if ptr == libc::MAP_FAILED {
+			let err = io::Error::last_os_error();
+			unsafe { libc::close(fd) };
+			return Err(err);
+		}
+		// Mappings are page aligned, which is enough for any `T` in practice.
+		assert!((ptr as usize).is_multiple_of(align_of::<SharedInner<T, L>>()));
+		Ok(SharedAtom {
+			fd,
+			ptr: NonNull::new(ptr as *mut SharedInner<T, L>).unwrap(),
+		})
+	}
+
+	#[inline]
+	fn inner(&self) -> &SharedInner<T, L> {
+		unsafe { self.ptr.as_ref() }
+	}
+}
+
+impl<T: Copy + Send> SharedAtom<T, RobustLock> {
+	/// Create a new `SharedAtom` with a `RobustLock` in anonymous shared memory, like
+	/// `SharedAtom::new`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{RobustError, SharedAtom};
+	///
+	/// let atom = SharedAtom::new_robust(0u32).unwrap();
+	/// match unsafe { libc::fork() } {
+	///     0 => {
+	///         // Die while holding the lock, in the middle of an update.
+	///         let mut guard = atom.guard().unwrap();
+	///         *guard = 1;
+	///         std::mem::forget(guard);
+	///         unsafe { libc::_exit(0) };
+	///     }
+	///     pid => unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0); },
+	/// }
+	///
+	/// match atom.guard() {
+	///     Err(RobustError::OwnerDied(mut guard)) => {
+	///         *guard = 0;
+	///         guard.mark_consistent();
+	///     }
+	///     _ => panic!("the owner should have died"),
+	/// }
+	/// assert_eq!(*atom.guard().unwrap(), 0);
+	/// ```
+	#[inline]
+	pub fn new_robust(value: T) -> io::Result<Self> {
+		Self::new_in_memfd(value)
+	}
+
+	/// Create a new named `SharedAtom` with a `RobustLock`, like `SharedAtom::create`.
+	#[inline]
+	pub fn create_robust(name: &str, value: T) -> io::Result<Self> {
+		Self::create_named(name, value)
+	}
+
+	/// Open a named `SharedAtom` created with `SharedAtom::create_robust`, like
+	/// `SharedAtom::open`.
+	///
+	/// # Safety
+	///
+	/// The same as for `SharedAtom::open`.
+	#[inline]
+	pub unsafe fn open_robust(name: &str) -> io::Result<Self> {
+		Self::open_named(name)
+	}
+
+	/// Lock the value and return a guard that gives access to it. If a thread or
+	/// process died while holding the lock, the guard is returned inside
+	/// `RobustError::OwnerDied`, and the value should be repaired and the guard
+	/// marked consistent with `RobustGuard::mark_consistent`. Otherwise the lock can't
+	/// be acquired anymore once the guard is dropped.
+	pub fn guard(&self) -> Result<RobustGuard<'_, T>, RobustError<RobustGuard<'_, T>>> {
+		let inner = self.inner();
+		match inner.lock.lock() {
+			Ok(()) => Ok(RobustGuard { inner }),
+			Err(RobustError::OwnerDied(())) => Err(RobustError::OwnerDied(RobustGuard { inner })),
+			Err(RobustError::NotRecoverable) => Err(RobustError::NotRecoverable),
+		}
+	}
+}
+
+impl<T: Copy + Send, L: SharedLock> AsRawFd for SharedAtom<T, L> {
+	#[inline]
+	fn as_raw_fd(&self) -> RawFd {
+		self.fd
+	}
+}
+
+impl<T: Copy + Send, L: SharedLock> Drop for SharedAtom<T, L> {
+	fn drop(&mut self) {
+		self.inner().lock.unmapping();
+		unsafe {
+			libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, size_of::<SharedInner<T, L>>());
+			libc::close(self.fd);
+		}
+	}
+}
+
+unsafe impl<T: Copy + Send, L: SharedLock> Send for SharedAtom<T, L> {}
+unsafe impl<T: Copy + Send, L: SharedLock> Sync for SharedAtom<T, L> {}
+
+/// A guard holding the `RobustLock` of a `SharedAtom`, returned by
+/// `SharedAtom::guard`. The lock is released when the guard is dropped.
+pub struct RobustGuard<'a, T: Copy + Send> {
+	inner: &'a SharedInner<T, RobustLock>,
+}
+
+impl<T: Copy + Send> RobustGuard<'_, T> {
+	/// Mark the value consistent after the guard was returned inside
+	/// `RobustError::OwnerDied`, so that the lock can be used again.
+	#[inline]
+	pub fn mark_consistent(&self) {
+		self.inner.lock.mark_consistent();
+	}
+}
+
+impl<T: Copy + Send> Deref for RobustGuard<'_, T> {
+	type Target = T;
+
+	#[inline]
+	fn deref(&self) -> &T {
+		unsafe { &*self.inner.data.get() }
+	}
+}
+
+impl<T: Copy + Send> DerefMut for RobustGuard<'_, T> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut T {
+		unsafe { &mut *self.inner.data.get() }
+	}
+}
+
+impl<T: Copy + Send> Drop for RobustGuard<'_, T> {
+	#[inline]
+	fn drop(&mut self) {
+		self.inner.lock.unlock();
+	}
+}
+
+/// Releases the lock also when the closure panics.
+struct Unlock<'a>(&'a SharedSpinLock);
+
+impl Drop for Unlock<'_> {
+	#[inline]
+	fn drop(&mut self) {
+		self.0.unlock();
+	}
+}
+
+fn shm_name(name: &str) -> io::Result<CString> {
+	CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name contains a nul byte"))
+}
+
+fn invalid_data() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "shared memory doesn't hold a SharedAtom of this type")
+}
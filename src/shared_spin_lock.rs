@@ -0,0 +1,161 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use crate::futex::{futex_wait_shared, futex_wake_shared};
+
+/// A `SpinLock` that can be shared between processes. It uses the same locking
+/// protocol as `SpinLock`, but waits on the futex without `FUTEX_PRIVATE_FLAG`,
+/// so that a waiter in one process is woken up when the lock is released in
+/// another. The lock is a single `u32` and has no pointers, so it can be placed
+/// in any memory that is mapped into several processes, like the memory of a
+/// `SharedAtom`.
+#[repr(transparent)]
+pub struct SharedSpinLock(AtomicU32);
+
+impl SharedSpinLock {
+
+	/// Create a new `SharedSpinLock`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedSpinLock;
+	///
+	/// let lock = SharedSpinLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		SharedSpinLock(AtomicU32::new(0))
+	}
+
+	/// Lock the `SharedSpinLock`. This is a blocking operation. If the lock is held
+	/// by another thread or process, this function will wait until the lock is
+	/// released and then acquire it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedSpinLock;
+	///
+	/// let lock = SharedSpinLock::new();
+	/// lock.lock();
+	/// // do something
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock(&self) {
+		if self.0.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+			self.lock_contended(None);
+		}
+	}
+
+	/// Try to lock the `SharedSpinLock` without blocking. Returns `true` if the lock
+	/// was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedSpinLock;
+	///
+	/// let lock = SharedSpinLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		self.0.compare_exchange(0, 1, Acquire, Relaxed).is_ok()
+	}
+
+	/// Lock the `SharedSpinLock`, giving up once `timeout` has passed. Returns `true`
+	/// if the lock was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SharedSpinLock;
+	/// use std::time::Duration;
+	///
+	/// let lock = SharedSpinLock::new();
+	/// lock.lock();
+	/// assert!(!lock.lock_timeout(Duration::from_millis(10)));
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_timeout(&self, timeout: Duration) -> bool {
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.lock_until(deadline),
+			None => {
+				self.lock();
+				true
+			}
+		}
+	}
+
+	/// Lock the `SharedSpinLock`, giving up once `deadline` has passed. Returns
+	/// `true` if the lock was acquired.
+	#[inline]
+	pub fn lock_until(&self, deadline: Instant) -> bool {
+		self.0.compare_exchange(0, 1, Acquire, Relaxed).is_ok()
+			|| self.lock_contended(Some(deadline))
+	}
+
+	#[cold]
+	fn lock_contended(&self, deadline: Option<Instant>) -> bool {
+		std::thread::sleep(Duration::from_nanos(1));
+		let mut state = self.0.load(Relaxed);
+		if state == 0 {
+			match self.0.compare_exchange(0, 1, Acquire, Relaxed) {
+				Ok(_) => return true,
+				Err(s) => state = s,
+			}
+		}
+
+		while state == 2 || self.0.swap(2, Acquire) != 0 {
+			if !futex_wait_shared(&self.0, 2, deadline) {
+				return false;
+			}
+			std::thread::sleep(Duration::from_nanos(1));
+			state = self.0.load(Relaxed);
+		}
+		true
+	}
+
+	/// Unlock the `SharedSpinLock`, waking up one waiter in any process.
+	#[inline]
+	pub fn unlock(&self) {
+		if self.0.swap(0, Release) == 2 {
+			futex_wake_shared(&self.0);
+		}
+	}
+}
+
+impl Default for SharedSpinLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for SharedSpinLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = SharedSpinLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+
+	#[inline]
+	fn lock_until(&self, deadline: Instant) -> bool {
+		self.lock_until(deadline)
+	}
+}
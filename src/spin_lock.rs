@@ -1,7 +1,22 @@
 use super::*;
 use std::sync::atomic::AtomicU32;
-use crate::futex::{futex_wait, futex_wake};
-pub struct SpinLock(AtomicU32);
+use std::time::{Duration, Instant};
+use crate::backoff::{Backoff, Sleep};
+use crate::fairness::{WaitTimer, HANDOFF};
+use crate::stats::Stats;
+use crate::track::Track;
+use crate::owner::Owner;
+use crate::futex::{futex_wait, futex_wait_until, futex_wake};
+pub struct SpinLock<B = Sleep> {
+	/// 0 when unlocked, 1 when locked, 2 when locked with waiters asleep, or
+	/// `HANDOFF` while the lock is handed over to a woken waiter.
+	word: AtomicU32,
+	timer: WaitTimer,
+	backoff: B,
+	stats: Stats,
+	track: Track,
+	owner: Owner,
+}
 
 impl SpinLock {
 
@@ -10,22 +25,91 @@ impl SpinLock {
 	/// contention i.e. when the lock is only held for a short time and there are few
 	/// threads competing for the lock.
 	///
+	/// Like `SpinPark`, the lock is eventually fair: once a thread has been waiting
+	/// for it for more than half a millisecond, `unlock` hands the lock directly to
+	/// a waiting thread.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	///
+	/// let lock = SpinLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		SpinLock::with_backoff(Sleep::DEFAULT)
+	}
+}
+
+impl<B: Backoff> SpinLock<B> {
+
+	/// Create a new `SpinLock` that waits for the lock with the given `Backoff`
+	/// strategy before going to sleep, instead of sleeping once for 1ns.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	/// use spinout::backoff::SpinThenPark;
+	///
+	/// let lock = SpinLock::with_backoff(SpinThenPark::new(500));
+	/// lock.lock();
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub const fn with_backoff(backoff: B) -> Self {
+		SpinLock {
+			word: AtomicU32::new(0),
+			timer: WaitTimer::new(),
+			backoff,
+			stats: Stats::new(),
+			track: Track::new(),
+			owner: Owner::new(),
+		}
+	}
+
+	/// The `Backoff` strategy of the lock.
+	#[inline]
+	pub fn backoff(&self) -> &B {
+		&self.backoff
+	}
+
+	/// A snapshot of the contention statistics of the lock.
+	///
 	/// # Examples
 	///
 	/// ```
 	/// use spinout::SpinLock;
 	///
 	/// let lock = SpinLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// assert_eq!(lock.stats().acquisitions, 1);
 	/// ```
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn stats(&self) -> LockStats {
+		self.stats.snapshot()
+	}
+
+	/// Reset the contention statistics of the lock to zero.
+	#[cfg(feature = "stats")]
 	#[inline]
-	pub fn new() -> Self {
-		SpinLock(AtomicU32::new(0))
+	pub fn reset_stats(&self) {
+		self.stats.reset();
 	}
 
 	/// Lock the `SpinLock`. This is a blocking operation. If the lock is held by another
 	/// thread, this function will spin until the lock is released and then acquire it.
 	/// immediately. This function will return once the lock has been acquired.
 	///
+	/// # Panics
+	///
+	/// In debug builds, and with the `reentrancy-detection` feature, panics if the
+	/// lock is already held by the calling thread, instead of waiting for it forever.
+	///
 	/// # Examples
 	///
 	/// ```
@@ -49,23 +133,176 @@ impl SpinLock {
 	/// ```
 	#[inline]
     pub fn lock(&self) {
-        if self.0.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
-			std::thread::sleep(std::time::Duration::from_nanos(1));
-            let mut state = self.0.load(Relaxed);
-			if state == 0 {
-				match self.0.compare_exchange(0, 1, Acquire, Relaxed) {
-					Ok(_) => return, // Locked!
-					Err(s) => state = s,
+        self.lock_as("SpinLock");
+    }
+
+	/// `lock`, naming the lock `lock` in the panic when the thread holding it locks
+	/// it again, for the locks built on a `SpinLock`.
+	#[inline]
+	pub(crate) fn lock_as(&self, lock: &'static str) {
+		if self.word.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+			self.lock_contended(None, lock);
+		}
+		self.backoff.locked();
+		self.stats.locked();
+		self.track.locked();
+		self.owner.locked();
+	}
+
+	/// Try to lock the `SpinLock` without blocking. Returns `true` if the lock was
+	/// acquired, and `false` if it is held by another thread.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	///
+	/// let lock = SpinLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		let locked = self.word.compare_exchange(0, 1, Acquire, Relaxed).is_ok();
+		if locked {
+			self.backoff.locked();
+			self.stats.locked();
+			self.track.locked();
+			self.owner.locked();
+		}
+		locked
+	}
+
+	/// Lock the `SpinLock`, giving up after the given timeout. Returns `true` if the
+	/// lock was acquired, and `false` if the timeout elapsed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	/// use std::time::Duration;
+	///
+	/// let lock = SpinLock::new();
+	/// assert!(lock.lock_timeout(Duration::from_millis(10)));
+	/// assert!(!lock.lock_timeout(Duration::from_millis(10)));
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_timeout(&self, timeout: Duration) -> bool {
+		// A deadline that overflows `Instant` is treated as no deadline at all.
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.lock_until(deadline),
+			None => {
+				self.lock();
+				true
+			}
+		}
+	}
+
+	/// Lock the `SpinLock`, giving up once the given deadline has passed. Returns
+	/// `true` if the lock was acquired, and `false` if the deadline passed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinLock;
+	/// use std::time::{Duration, Instant};
+	///
+	/// let lock = SpinLock::new();
+	/// lock.lock();
+	/// let deadline = Instant::now() + Duration::from_millis(10);
+	/// assert!(!lock.lock_until(deadline));
+	/// assert!(Instant::now() >= deadline);
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_until(&self, deadline: Instant) -> bool {
+		let locked = self.word.compare_exchange(0, 1, Acquire, Relaxed).is_ok()
+			|| self.lock_contended(Some(deadline), "SpinLock");
+		if locked {
+			self.backoff.locked();
+			self.stats.locked();
+			self.track.locked();
+			self.owner.locked();
+		}
+		locked
+	}
+
+	#[cold]
+	fn lock_contended(&self, deadline: Option<Instant>, lock: &'static str) -> bool {
+		// Waiting with a deadline for a lock the thread holds itself only times out.
+		if deadline.is_none() {
+			self.owner.check(lock);
+		}
+		let _waiting = self.track.waiting();
+		let start = Stats::start();
+		let mut state = self.spin(deadline);
+		if state == 0 {
+			match self.word.compare_exchange(0, 1, Acquire, Relaxed) {
+				Ok(_) => {
+					self.backoff.spun(true);
+					self.stats.contended(start);
+					return true; // Locked!
 				}
+				Err(s) => state = s,
 			}
+		}
+		self.backoff.spun(false);
 
-			while state == 2 || self.0.swap(2, Acquire) != 0 {
-				futex_wait(&self.0, 2, None);
-				std::thread::sleep(std::time::Duration::from_nanos(1));
-				state = self.0.load(Relaxed);
+		let since = WaitTimer::now();
+		let mut woken = false;
+		loop {
+			match state {
+				// Only threads that have been asleep take a handover, as the thread
+				// woken up for it is one of them.
+				HANDOFF if woken => match self.word.compare_exchange(HANDOFF, 2, Acquire, Relaxed) {
+					Ok(_) => break,
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				},
+				HANDOFF | 2 => {}
+				_ => match self.word.compare_exchange(state, 2, Acquire, Relaxed) {
+					Ok(0) => break,
+					Ok(_) => state = 2,
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				},
 			}
-        }
-    }
+			self.timer.waiting(since);
+			self.stats.futex_wait();
+			if !futex_wait_until(&self.word, state, deadline) {
+				self.timer.done(since);
+				return false;
+			}
+			woken = true;
+			state = self.spin(deadline);
+		}
+		self.timer.done(since);
+		self.stats.contended(start);
+		true
+	}
+
+	/// Wait with the backoff strategy while the lock is held and not contended, or
+	/// until the deadline has passed. Returns the last state of the lock.
+	#[inline]
+	fn spin(&self, deadline: Option<Instant>) -> u32 {
+		let mut step = 0;
+		loop {
+			let state = self.word.load(Relaxed);
+			if state != 1
+				|| deadline.is_some_and(|deadline| Instant::now() >= deadline)
+				|| !self.backoff.backoff(step)
+			{
+				return state;
+			}
+			step = step.saturating_add(1);
+		}
+	}
 
 	/// Unlock the `SpinLock`. This function will unlock the lock and allow other threads
 	/// to acquire it.
@@ -93,7 +330,11 @@ impl SpinLock {
 	/// ```
 	#[inline]
     pub fn unlock(&self) {
-        if self.0.swap(0, Release) == 2 {
+        self.backoff.unlocking();
+        self.stats.unlocking();
+        self.track.unlocking();
+        self.owner.unlocking();
+        if self.word.swap(0, Release) == 2 {
             // We only wake up one thread. When that thread locks the mutex, it
             // will mark the mutex as contended (2) (see lock_contended above),
             // which makes sure that any other waiting threads will also be
@@ -102,14 +343,149 @@ impl SpinLock {
         }
     }
 
+	/// Lock and mark the lock contended, even if there's no other thread waiting
+	/// for it, so that unlocking it will wake up a waiter. The thread might have
+	/// been woken up by an unlock handing the lock over, so it takes a handover.
+	#[inline]
+	fn lock_marked_contended(&self) {
+		let since = WaitTimer::now();
+		let mut state = self.word.load(Relaxed);
+		loop {
+			if state != 2 {
+				match self.word.compare_exchange(state, 2, Acquire, Relaxed) {
+					Ok(0) | Ok(HANDOFF) => break,
+					Ok(_) => {}
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				}
+			}
+			self.timer.waiting(since);
+			self.stats.futex_wait();
+			futex_wait(&self.word, 2, None);
+			state = self.word.load(Relaxed);
+		}
+		self.timer.done(since);
+		self.backoff.locked();
+		self.stats.locked();
+		self.track.locked();
+		self.owner.locked();
+	}
+
     #[cold]
     fn wake(&self) {
-        futex_wake(&self.0);
+        // Hand the lock over to the thread that is woken up if a thread has been
+        // waiting for too long, so that it can't be taken by a thread that keeps
+        // coming back for it.
+        if self.timer.expired() && self.word.compare_exchange(0, HANDOFF, Relaxed, Relaxed).is_ok() {
+            if futex_wake(&self.word) {
+                return;
+            }
+            // There was no thread asleep to take the lock, so release it after all,
+            // unless a waiter has taken it in the meantime.
+            if self.word.compare_exchange(HANDOFF, 0, Release, Relaxed).is_err() {
+                return;
+            }
+        }
+        futex_wake(&self.word);
     }
 }
 
-impl Default for SpinLock {
+impl<B: Backoff> Default for SpinLock<B> {
 	fn default() -> Self {
-		Self::new()
+		Self::with_backoff(B::DEFAULT)
+	}
+}
+
+unsafe impl<B: Backoff> RawLock for SpinLock<B> {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = SpinLock::with_backoff(B::DEFAULT);
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+
+	#[inline]
+	fn lock_until(&self, deadline: Instant) -> bool {
+		self.lock_until(deadline)
+	}
+
+	#[inline]
+	fn requeue_word(&self) -> Option<&AtomicU32> {
+		Some(&self.word)
+	}
+
+	#[inline]
+	fn lock_after_wait(&self) {
+		self.lock_marked_contended();
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn stats(&self) -> Option<LockStats> {
+		Some(self.stats())
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn reset_stats(&self) {
+		self.reset_stats();
+	}
+}
+
+#[cfg(feature = "registry")]
+impl SpinLock {
+	/// Create a new `SpinLock` registered under `name` in the `registry`, so that
+	/// `registry::dump` shows who holds it. The registry only keeps a weak
+	/// reference to the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{registry, SpinLock};
+	///
+	/// let lock = SpinLock::named("config");
+	/// lock.lock();
+	/// assert!(registry::dump().to_string().contains("config: locked"));
+	/// lock.unlock();
+	/// ```
+	pub fn named(name: impl Into<String>) -> std::sync::Arc<Self> {
+		let lock = std::sync::Arc::new(Self::new());
+		crate::registry::register_arc(name.into(), &lock);
+		lock
+	}
+}
+
+#[cfg(feature = "registry")]
+impl<B: Backoff> crate::registry::Inspect for SpinLock<B> {
+	fn track(&self) {
+		self.track.enable();
+	}
+
+	fn inspect(&self) -> crate::registry::Inspection {
+		use crate::registry::LockState;
+		crate::registry::Inspection {
+			state: match self.word.load(Relaxed) {
+				0 => LockState::Free,
+				1 => LockState::Locked,
+				_ => LockState::Contended,
+			},
+			owner: self.track.owner(),
+			readers: 0,
+			waiters: self.track.waiters(),
+			held_for: self.track.held_for(),
+		}
 	}
 }
@@ -1,7 +1,12 @@
 use super::*;
 use std::sync::atomic::AtomicU32;
-use crate::futex::{futex_wait, futex_wake};
-pub struct SpinPark(AtomicU32);
+use std::time::{Duration, Instant};
+use crate::backoff::{Adaptive, Backoff, Yield};
+
+/// A `SpinLock` that yields up to 100 times while it waits for the lock, before
+/// going to sleep, instead of sleeping once for 1ns. Apart from the default
+/// `Backoff` strategy it is the same lock, sharing the same implementation.
+pub struct SpinPark<B = Yield>(SpinLock<B>);
 
 impl SpinPark {
 
@@ -10,22 +15,115 @@ impl SpinPark {
 	/// contention i.e. when the lock is only held for a short time and there are few
 	/// threads competing for the lock.
 	///
+	/// The lock is eventually fair: once a thread has been waiting for it for more
+	/// than half a millisecond, `unlock` hands the lock directly to a waiting thread
+	/// instead of letting any thread take it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	///
+	/// let lock = SpinPark::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		SpinPark::with_backoff(Yield::DEFAULT)
+	}
+}
+
+impl SpinPark<Adaptive> {
+
+	/// Create a new `SpinPark` that learns how long to spin before going to sleep
+	/// from how long the lock is held, and how often spinning pays off. See
+	/// `backoff::Adaptive`.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	///
+	/// let lock = SpinPark::adaptive();
+	/// lock.lock();
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub const fn adaptive() -> Self {
+		SpinPark::with_backoff(Adaptive::new())
+	}
+}
+
+impl<B: Backoff> SpinPark<B> {
+
+	/// Create a new `SpinPark` that waits for the lock with the given `Backoff`
+	/// strategy before going to sleep, instead of yielding up to 100 times.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	/// use spinout::backoff::Exponential;
+	///
+	/// let lock = SpinPark::with_backoff(Exponential::new(64));
+	/// lock.lock();
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub const fn with_backoff(backoff: B) -> Self {
+		SpinPark(SpinLock::with_backoff(backoff))
+	}
+
+	/// The `Backoff` strategy of the lock, e.g. to read what an `Adaptive` one has
+	/// learned.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	///
+	/// let lock = SpinPark::adaptive();
+	/// assert!(lock.backoff().hold_time().is_zero());
+	/// ```
+	#[inline]
+	pub fn backoff(&self) -> &B {
+		self.0.backoff()
+	}
+
+	/// A snapshot of the contention statistics of the lock.
+	///
 	/// # Examples
 	///
 	/// ```
 	/// use spinout::SpinPark;
 	///
 	/// let lock = SpinPark::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// assert_eq!(lock.stats().acquisitions, 1);
 	/// ```
+	#[cfg(feature = "stats")]
 	#[inline]
-	pub fn new() -> Self {
-		SpinPark(AtomicU32::new(0))
+	pub fn stats(&self) -> LockStats {
+		self.0.stats()
+	}
+
+	/// Reset the contention statistics of the lock to zero.
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn reset_stats(&self) {
+		self.0.reset_stats();
 	}
 
 	/// Lock the `SpinPark`. This is a blocking operation. If the lock is held by another
 	/// thread, this function will spin until the lock is released and then acquire it.
 	/// immediately. This function will return once the lock has been acquired.
 	///
+	/// # Panics
+	///
+	/// In debug builds, and with the `reentrancy-detection` feature, panics if the
+	/// lock is already held by the calling thread, instead of waiting for it forever.
+	///
 	/// # Examples
 	///
 	/// ```
@@ -48,61 +146,67 @@ impl SpinPark {
 	/// t.join().unwrap();
 	/// ```
 	#[inline]
-    pub fn lock(&self) {
-        if self.0.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
-            self.lock_contended();
-        }
-    }
-
-    #[cold]
-    fn lock_contended(&self) {
-        // Spin first to speed things up if the lock is released quickly.
-        let mut state = self.spin();
-
-        // If it's unlocked now, attempt to take the lock
-        // without marking it as contended.
-        if state == 0 {
-            match self.0.compare_exchange(0, 1, Acquire, Relaxed) {
-                Ok(_) => return, // Locked!
-                Err(s) => state = s,
-            }
-        }
-
-        loop {
-            // Put the lock in contended state.
-            // We avoid an unnecessary write if it as already set to 2,
-            // to be friendlier for the caches.
-            if state != 2 && self.0.swap(2, Acquire) == 0 {
-                // We changed it from 0 to 2, so we just successfully locked it.
-                return;
-            }
-
-            // Wait for the futex to change state, assuming it is still 2.
-            futex_wait(&self.0, 2, None);
-
-            // Spin again after waking up.
-            state = self.spin();
-        }
-    }
-
-	#[inline]
-	fn spin(&self) -> u32 {
-        let mut spin = 100;
-        loop {
-            // We only use `load` (and not `swap` or `compare_exchange`)
-            // while spinning, to be easier on the caches.
-            let state = self.0.load(Relaxed);
-
-            // We stop spinning when the mutex is unlocked (0),
-            // but also when it's contended (2).
-            if state != 1 || spin == 0 {
-                return state;
-            }
-
-			std::thread::yield_now();
-            spin -= 1;
-        }
-    }
+	pub fn lock(&self) {
+		self.0.lock_as("SpinPark");
+	}
+
+	/// Try to lock the `SpinPark` without blocking. Returns `true` if the lock was
+	/// acquired, and `false` if it is held by another thread.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	///
+	/// let lock = SpinPark::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		self.0.try_lock()
+	}
+
+	/// Lock the `SpinPark`, giving up after the given timeout. Returns `true` if the
+	/// lock was acquired, and `false` if the timeout elapsed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	/// use std::time::Duration;
+	///
+	/// let lock = SpinPark::new();
+	/// assert!(lock.lock_timeout(Duration::from_millis(10)));
+	/// assert!(!lock.lock_timeout(Duration::from_millis(10)));
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_timeout(&self, timeout: Duration) -> bool {
+		self.0.lock_timeout(timeout)
+	}
+
+	/// Lock the `SpinPark`, giving up once the given deadline has passed. Returns
+	/// `true` if the lock was acquired, and `false` if the deadline passed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinPark;
+	/// use std::time::{Duration, Instant};
+	///
+	/// let lock = SpinPark::new();
+	/// lock.lock();
+	/// let deadline = Instant::now() + Duration::from_millis(10);
+	/// assert!(!lock.lock_until(deadline));
+	/// assert!(Instant::now() >= deadline);
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn lock_until(&self, deadline: Instant) -> bool {
+		self.0.lock_until(deadline)
+	}
 
 	/// Unlock the `SpinPark`. This function will unlock the lock and allow other threads
 	/// to acquire it.
@@ -129,24 +233,94 @@ impl SpinPark {
 	/// t.join().unwrap();
 	/// ```
 	#[inline]
-    pub fn unlock(&self) {
-        if self.0.swap(0, Release) == 2 {
-            // We only wake up one thread. When that thread locks the mutex, it
-            // will mark the mutex as contended (2) (see lock_contended above),
-            // which makes sure that any other waiting threads will also be
-            // woken up eventually.
-            self.wake();
-        }
-    }
-
-    #[cold]
-    fn wake(&self) {
-        futex_wake(&self.0);
-    }
+	pub fn unlock(&self) {
+		self.0.unlock();
+	}
 }
 
-impl Default for SpinPark {
+impl<B: Backoff> Default for SpinPark<B> {
 	fn default() -> Self {
-		Self::new()
+		Self::with_backoff(B::DEFAULT)
+	}
+}
+
+unsafe impl<B: Backoff> RawLock for SpinPark<B> {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = SpinPark::with_backoff(B::DEFAULT);
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+
+	#[inline]
+	fn lock_until(&self, deadline: Instant) -> bool {
+		self.lock_until(deadline)
+	}
+
+	#[inline]
+	fn requeue_word(&self) -> Option<&AtomicU32> {
+		self.0.requeue_word()
+	}
+
+	#[inline]
+	fn lock_after_wait(&self) {
+		self.0.lock_after_wait();
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn stats(&self) -> Option<LockStats> {
+		Some(self.stats())
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn reset_stats(&self) {
+		self.reset_stats();
+	}
+}
+
+#[cfg(feature = "registry")]
+impl SpinPark {
+	/// Create a new `SpinPark` registered under `name` in the `registry`, so that
+	/// `registry::dump` shows who holds it. The registry only keeps a weak
+	/// reference to the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::{registry, SpinPark};
+	///
+	/// let lock = SpinPark::named("config");
+	/// lock.lock();
+	/// assert!(registry::dump().to_string().contains("config: locked"));
+	/// lock.unlock();
+	/// ```
+	pub fn named(name: impl Into<String>) -> std::sync::Arc<Self> {
+		let lock = std::sync::Arc::new(Self::new());
+		crate::registry::register_arc(name.into(), &lock);
+		lock
+	}
+}
+
+#[cfg(feature = "registry")]
+impl<B: Backoff> crate::registry::Inspect for SpinPark<B> {
+	fn track(&self) {
+		crate::registry::Inspect::track(&self.0);
+	}
+
+	fn inspect(&self) -> crate::registry::Inspection {
+		crate::registry::Inspect::inspect(&self.0)
 	}
 }
@@ -0,0 +1,668 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use std::hint::spin_loop;
+use std::time::{Duration, Instant};
+use crate::futex::{futex_wait_until, futex_wake, futex_wake_all};
+use crate::stats::Stats;
+use crate::track::Track;
+use crate::owner::Owner;
+
+// The state consists of a 30-bit reader counter, a 'readers waiting' flag,
+// and a 'writers waiting' flag. Bits 0..30:
+//   0: Unlocked
+//   1..=0x3FFF_FFFE: Locked by N readers
+//   0x3FFF_FFFF: Write locked
+// Bit 30: Readers are waiting on this futex.
+// Bit 31: Writers are waiting on the writer_notify futex.
+const READ_LOCKED: u32 = 1;
+const MASK: u32 = (1 << 30) - 1;
+const WRITE_LOCKED: u32 = MASK;
+const MAX_READERS: u32 = MASK - 1;
+const READERS_WAITING: u32 = 1 << 30;
+const WRITERS_WAITING: u32 = 1 << 31;
+
+#[inline]
+fn is_unlocked(state: u32) -> bool {
+	state & MASK == 0
+}
+
+#[inline]
+fn is_write_locked(state: u32) -> bool {
+	state & MASK == WRITE_LOCKED
+}
+
+#[inline]
+fn has_readers_waiting(state: u32) -> bool {
+	state & READERS_WAITING != 0
+}
+
+#[inline]
+fn has_writers_waiting(state: u32) -> bool {
+	state & WRITERS_WAITING != 0
+}
+
+#[inline]
+fn is_read_lockable(state: u32) -> bool {
+	// This also returns false if the counter could overflow if we tried to read lock it.
+	//
+	// We don't allow read-locking if there's readers waiting, even if the lock is unlocked
+	// and there's no writers waiting. The only situation when this happens is after unlocking,
+	// at which point the unlocking thread might be waking up writers, which have priority over readers.
+	// The unlocking thread will clear the readers waiting bit and wake up readers, if necessary.
+	state & MASK < MAX_READERS && !has_readers_waiting(state) && !has_writers_waiting(state)
+}
+
+#[inline]
+fn has_reached_max_readers(state: u32) -> bool {
+	state & MASK == MAX_READERS
+}
+
+pub struct SpinRwLock {
+	state: AtomicU32,
+	// Incremented on every signal to a waiting writer.
+	writer_notify: AtomicU32,
+	stats: Stats,
+	track: Track,
+	/// The thread holding the write lock, and the read locks of each thread.
+	owner: Owner,
+}
+
+impl SpinRwLock {
+
+	/// Create a new `SpinRwLock`. A reader-writer lock allows any number of readers or
+	/// a single writer to hold the lock at the same time. Waiting writers are preferred
+	/// over new readers, so a steady stream of readers can't starve the writers.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	///
+	/// let lock = SpinRwLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		SpinRwLock {
+			state: AtomicU32::new(0),
+			writer_notify: AtomicU32::new(0),
+			stats: Stats::new(),
+			track: Track::new(),
+			owner: Owner::new(),
+		}
+	}
+
+	/// A snapshot of the contention statistics of the lock. Hold times only cover
+	/// write locks.
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn stats(&self) -> LockStats {
+		self.stats.snapshot()
+	}
+
+	/// Reset the contention statistics of the lock to zero.
+	#[cfg(feature = "stats")]
+	#[inline]
+	pub fn reset_stats(&self) {
+		self.stats.reset();
+	}
+
+	/// Try to acquire a shared read lock without blocking. Returns `true` if the
+	/// lock was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	///
+	/// let lock = SpinRwLock::new();
+	/// assert!(lock.try_read());
+	/// assert!(lock.try_read());
+	/// assert!(!lock.try_write());
+	/// lock.read_unlock();
+	/// lock.read_unlock();
+	/// ```
+	#[inline]
+	pub fn try_read(&self) -> bool {
+		let locked = self.state
+			.fetch_update(Acquire, Relaxed, |s| is_read_lockable(s).then_some(s + READ_LOCKED))
+			.is_ok();
+		if locked {
+			self.stats.locked_shared();
+			self.track.locked_shared();
+			self.owner.locked_shared();
+		}
+		locked
+	}
+
+	/// Acquire a shared read lock. This is a blocking operation. If the lock is write
+	/// locked, or a writer is waiting for it, this function will spin and then park
+	/// until the lock can be read locked.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(SpinRwLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// lock.read();
+	/// let t = std::thread::spawn(move || {
+	///     // Readers don't block each other.
+	///     lock2.read();
+	///     lock2.read_unlock();
+	/// });
+	/// t.join().unwrap();
+	/// lock.read_unlock();
+	/// ```
+	#[inline]
+	pub fn read(&self) {
+		// Checked before the fast path, so a nested read panics whether or not a
+		// writer has queued up in between yet.
+		self.owner.check_shared("SpinRwLock");
+		let state = self.state.load(Relaxed);
+		if !is_read_lockable(state)
+			|| self
+				.state
+				.compare_exchange_weak(state, state + READ_LOCKED, Acquire, Relaxed)
+				.is_err()
+		{
+			self.read_contended(None);
+		}
+		self.stats.locked_shared();
+		self.track.locked_shared();
+		self.owner.locked_shared();
+	}
+
+	/// Acquire a shared read lock, giving up after the given timeout. Returns `true`
+	/// if the lock was acquired, and `false` if the timeout elapsed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	/// use std::time::Duration;
+	///
+	/// let lock = SpinRwLock::new();
+	/// lock.write();
+	/// assert!(!lock.read_timeout(Duration::from_millis(10)));
+	/// lock.write_unlock();
+	/// assert!(lock.read_timeout(Duration::from_millis(10)));
+	/// lock.read_unlock();
+	/// ```
+	#[inline]
+	pub fn read_timeout(&self, timeout: Duration) -> bool {
+		// A deadline that overflows `Instant` is treated as no deadline at all.
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.read_until(deadline),
+			None => {
+				self.read();
+				true
+			}
+		}
+	}
+
+	/// Acquire a shared read lock, giving up once the given deadline has passed.
+	/// Returns `true` if the lock was acquired, and `false` if the deadline passed
+	/// first.
+	#[inline]
+	pub fn read_until(&self, deadline: Instant) -> bool {
+		if self.try_read() {
+			return true;
+		}
+		let locked = self.read_contended(Some(deadline));
+		if locked {
+			self.stats.locked_shared();
+			self.track.locked_shared();
+			self.owner.locked_shared();
+		}
+		locked
+	}
+
+	/// Release a shared read lock previously acquired with `read` or `try_read`.
+	#[inline]
+	pub fn read_unlock(&self) {
+		self.owner.unlocking_shared();
+		let state = self.state.fetch_sub(READ_LOCKED, Release) - READ_LOCKED;
+		if is_unlocked(state) {
+			self.track.unlocking();
+		}
+
+		// It's impossible for a reader to be waiting on a read-locked RwLock,
+		// except if there is also a writer waiting.
+		debug_assert!(!has_readers_waiting(state) || has_writers_waiting(state));
+
+		// Wake up a writer if we were the last reader and there's a writer waiting.
+		if is_unlocked(state) && has_writers_waiting(state) {
+			self.wake_writer_or_readers(state);
+		}
+	}
+
+	#[cold]
+	fn read_contended(&self, deadline: Option<Instant>) -> bool {
+		// A thread holding the write lock would wait for itself to release it.
+		if deadline.is_none() {
+			self.owner.check("SpinRwLock");
+		}
+		let _waiting = self.track.waiting();
+		let start = Stats::start();
+		let mut state = self.spin_read();
+
+		loop {
+			// If we can lock it, lock it.
+			if is_read_lockable(state) {
+				match self.state.compare_exchange_weak(state, state + READ_LOCKED, Acquire, Relaxed) {
+					Ok(_) => {
+						self.stats.contended(start);
+						return true; // Locked!
+					}
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				}
+			}
+
+			// Check for overflow.
+			if has_reached_max_readers(state) {
+				panic!("too many active read locks on SpinRwLock");
+			}
+
+			// Make sure the readers waiting bit is set before we go to sleep.
+			if !has_readers_waiting(state) {
+				if let Err(s) =
+					self.state.compare_exchange(state, state | READERS_WAITING, Relaxed, Relaxed)
+				{
+					state = s;
+					continue;
+				}
+			}
+
+			// Wait for the state to change. If we give up, the readers waiting bit
+			// is left for the unlocking thread to clear.
+			self.stats.futex_wait();
+			if !futex_wait_until(&self.state, state | READERS_WAITING, deadline) {
+				return false;
+			}
+
+			// Spin again after waking up.
+			state = self.spin_read();
+		}
+	}
+
+	/// Try to acquire the exclusive write lock without blocking. Returns `true` if
+	/// the lock was acquired.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	///
+	/// let lock = SpinRwLock::new();
+	/// assert!(lock.try_write());
+	/// assert!(!lock.try_read());
+	/// lock.write_unlock();
+	/// ```
+	#[inline]
+	pub fn try_write(&self) -> bool {
+		let locked = self.state
+			.fetch_update(Acquire, Relaxed, |s| is_unlocked(s).then_some(s + WRITE_LOCKED))
+			.is_ok();
+		if locked {
+			self.stats.locked();
+			self.track.locked();
+			self.owner.locked();
+		}
+		locked
+	}
+
+	/// Acquire the exclusive write lock. This is a blocking operation. If the lock is
+	/// held by readers or another writer, this function will spin and then park until
+	/// the lock is released.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(SpinRwLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// let t = std::thread::spawn(move || {
+	///     lock2.write();
+	///     // do something
+	///     std::thread::sleep(std::time::Duration::from_millis(100));
+	///     lock2.write_unlock();
+	/// });
+	///
+	/// lock.read();
+	/// // read something after the spawned thread has finished writing.
+	/// lock.read_unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn write(&self) {
+		if self.state.compare_exchange_weak(0, WRITE_LOCKED, Acquire, Relaxed).is_err() {
+			self.write_contended(None);
+		}
+		self.stats.locked();
+		self.track.locked();
+		self.owner.locked();
+	}
+
+	/// Acquire the exclusive write lock, giving up after the given timeout. Returns
+	/// `true` if the lock was acquired, and `false` if the timeout elapsed first.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::SpinRwLock;
+	/// use std::time::Duration;
+	///
+	/// let lock = SpinRwLock::new();
+	/// lock.read();
+	/// assert!(!lock.write_timeout(Duration::from_millis(10)));
+	/// lock.read_unlock();
+	/// assert!(lock.write_timeout(Duration::from_millis(10)));
+	/// lock.write_unlock();
+	/// ```
+	#[inline]
+	pub fn write_timeout(&self, timeout: Duration) -> bool {
+		// A deadline that overflows `Instant` is treated as no deadline at all.
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.write_until(deadline),
+			None => {
+				self.write();
+				true
+			}
+		}
+	}
+
+	/// Acquire the exclusive write lock, giving up once the given deadline has
+	/// passed. Returns `true` if the lock was acquired, and `false` if the deadline
+	/// passed first.
+	#[inline]
+	pub fn write_until(&self, deadline: Instant) -> bool {
+		let locked = self.state.compare_exchange(0, WRITE_LOCKED, Acquire, Relaxed).is_ok()
+			|| self.write_contended(Some(deadline));
+		if locked {
+			self.stats.locked();
+			self.track.locked();
+			self.owner.locked();
+		}
+		locked
+	}
+
+	/// Release the exclusive write lock previously acquired with `write` or
+	/// `try_write`.
+	#[inline]
+	pub fn write_unlock(&self) {
+		self.stats.unlocking();
+		self.track.unlocking();
+		self.owner.unlocking();
+		let state = self.state.fetch_sub(WRITE_LOCKED, Release) - WRITE_LOCKED;
+
+		debug_assert!(is_unlocked(state));
+
+		if has_writers_waiting(state) || has_readers_waiting(state) {
+			self.wake_writer_or_readers(state);
+		}
+	}
+
+	#[cold]
+	fn write_contended(&self, deadline: Option<Instant>) -> bool {
+		// A thread holding a read lock would wait for itself to release it too.
+		if deadline.is_none() {
+			self.owner.check("SpinRwLock");
+			self.owner.check_shared("SpinRwLock");
+		}
+		let _waiting = self.track.waiting();
+		let start = Stats::start();
+		let mut state = self.spin_write();
+
+		let mut other_writers_waiting = 0;
+
+		loop {
+			// If it's unlocked, we try to lock it.
+			if is_unlocked(state) {
+				match self.state.compare_exchange_weak(
+					state,
+					state | WRITE_LOCKED | other_writers_waiting,
+					Acquire,
+					Relaxed,
+				) {
+					Ok(_) => {
+						self.stats.contended(start);
+						return true; // Locked!
+					}
+					Err(s) => {
+						state = s;
+						continue;
+					}
+				}
+			}
+
+			// Set the waiting bit indicating that we're waiting on it.
+			if !has_writers_waiting(state) {
+				if let Err(s) =
+					self.state.compare_exchange(state, state | WRITERS_WAITING, Relaxed, Relaxed)
+				{
+					state = s;
+					continue;
+				}
+			}
+
+			// Other writers might be waiting now too, so we should make sure
+			// we keep that bit on once we manage lock it.
+			other_writers_waiting = WRITERS_WAITING;
+
+			// Examine the notification counter before we check if `state` has changed,
+			// to make sure we don't miss any notifications.
+			let seq = self.writer_notify.load(Acquire);
+
+			// Don't go to sleep if the lock has become available,
+			// or if the writers waiting bit is no longer set.
+			state = self.state.load(Relaxed);
+			if is_unlocked(state) || !has_writers_waiting(state) {
+				continue;
+			}
+
+			// Wait for the state to change. If we give up, the writers waiting bit
+			// is left for the unlocking thread to clear.
+			self.stats.futex_wait();
+			if !futex_wait_until(&self.writer_notify, seq, deadline) {
+				return false;
+			}
+
+			// Spin again after waking up.
+			state = self.spin_write();
+		}
+	}
+
+	/// Wake up waiting threads after unlocking.
+	///
+	/// If both are waiting, this will wake up only one writer, but will fall
+	/// back to waking up readers if there was no writer to wake up.
+	#[cold]
+	fn wake_writer_or_readers(&self, mut state: u32) {
+		assert!(is_unlocked(state));
+
+		// The readers waiting bit might be turned on at any point now,
+		// since readers will block when there's anything waiting.
+		// Writers will just lock the lock though, regardless of the waiting bits,
+		// so we don't have to worry about the writer waiting bit.
+		//
+		// If the lock gets locked in the meantime, we don't have to do
+		// anything, because then the thread that locked the lock will take
+		// care of waking up waiters when it unlocks.
+
+		// If only writers are waiting, wake one of them up.
+		if state == WRITERS_WAITING {
+			match self.state.compare_exchange(state, 0, Relaxed, Relaxed) {
+				Ok(_) => {
+					self.wake_writer();
+					return;
+				}
+				Err(s) => {
+					// Maybe some readers are now waiting too. So, continue to the next `if`.
+					state = s;
+				}
+			}
+		}
+
+		// If both writers and readers are waiting, leave the readers waiting
+		// and only wake up one writer.
+		if state == READERS_WAITING + WRITERS_WAITING {
+			if self.state.compare_exchange(state, READERS_WAITING, Relaxed, Relaxed).is_err() {
+				// The lock got locked. Not our problem anymore.
+				return;
+			}
+			if self.wake_writer() {
+				return;
+			}
+			// No writers were actually blocked on futex_wait, so we continue
+			// to wake up readers instead, since we can't be sure if we notified a writer.
+			state = READERS_WAITING;
+		}
+
+		// If readers are waiting, wake them all up.
+		if state == READERS_WAITING
+			&& self.state.compare_exchange(state, 0, Relaxed, Relaxed).is_ok()
+		{
+			futex_wake_all(&self.state);
+		}
+	}
+
+	/// This wakes one writer and returns true if we woke up a writer that was
+	/// blocked on futex_wait.
+	///
+	/// If this returns false, it might still be the case that we notified a
+	/// writer that was about to go to sleep.
+	fn wake_writer(&self) -> bool {
+		self.writer_notify.fetch_add(1, Release);
+		futex_wake(&self.writer_notify)
+		// Note that FreeBSD and DragonFlyBSD don't tell us whether they woke
+		// up any threads or not, and always return `false` here. That still
+		// results in correct behaviour: it just means readers get woken up as
+		// well in case both readers and writers were waiting.
+	}
+
+	/// Spin for a while, but stop directly at the given condition.
+	#[inline]
+	fn spin_until(&self, f: impl Fn(u32) -> bool) -> u32 {
+		let mut spin = 100; // Chosen by fair dice roll.
+		loop {
+			let state = self.state.load(Relaxed);
+			if f(state) || spin == 0 {
+				return state;
+			}
+			spin_loop();
+			spin -= 1;
+		}
+	}
+
+	#[inline]
+	fn spin_write(&self) -> u32 {
+		// Stop spinning when it's unlocked or when there's waiting writers, to keep things somewhat fair.
+		self.spin_until(|state| is_unlocked(state) || has_writers_waiting(state))
+	}
+
+	#[inline]
+	fn spin_read(&self) -> u32 {
+		// Stop spinning when it's unlocked or read locked, or when there's waiting threads.
+		self.spin_until(|state| {
+			!is_write_locked(state) || has_readers_waiting(state) || has_writers_waiting(state)
+		})
+	}
+}
+
+impl Default for SpinRwLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for SpinRwLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = SpinRwLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.write();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_write()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.write_unlock();
+	}
+
+	#[inline]
+	fn lock_until(&self, deadline: Instant) -> bool {
+		self.write_until(deadline)
+	}
+
+	#[inline]
+	fn lock_shared(&self) {
+		self.read();
+	}
+
+	#[inline]
+	fn try_lock_shared(&self) -> bool {
+		self.try_read()
+	}
+
+	#[inline]
+	fn unlock_shared(&self) {
+		self.read_unlock();
+	}
+
+	#[inline]
+	fn lock_shared_until(&self, deadline: Instant) -> bool {
+		self.read_until(deadline)
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn stats(&self) -> Option<LockStats> {
+		Some(self.stats())
+	}
+
+	#[cfg(feature = "stats")]
+	#[inline]
+	fn reset_stats(&self) {
+		self.reset_stats();
+	}
+}
+
+#[cfg(feature = "registry")]
+impl crate::registry::Inspect for SpinRwLock {
+	fn track(&self) {
+		self.track.enable();
+	}
+
+	fn inspect(&self) -> crate::registry::Inspection {
+		use crate::registry::LockState;
+		let state = self.state.load(Relaxed);
+		let readers = if is_unlocked(state) || is_write_locked(state) { 0 } else { state & MASK };
+		crate::registry::Inspection {
+			state: if is_unlocked(state) {
+				LockState::Free
+			} else if has_readers_waiting(state) || has_writers_waiting(state) {
+				LockState::Contended
+			} else {
+				LockState::Locked
+			},
+			owner: self.track.owner(),
+			readers,
+			waiters: self.track.waiters(),
+			held_for: self.track.held_for(),
+		}
+	}
+}
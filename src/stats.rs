@@ -0,0 +1,171 @@
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+#[cfg(feature = "stats")]
+use std::time::Duration;
+#[cfg(feature = "stats")]
+use crate::fairness::WaitTimer;
+
+/// A snapshot of the contention statistics of a lock, recorded when the `stats`
+/// feature is enabled.
+///
+/// Wait times only cover acquisitions that found the lock held, from the first
+/// look at the lock until it was acquired. Hold times only cover exclusive
+/// acquisitions.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::SpinLock;
+///
+/// let lock = SpinLock::new();
+/// lock.lock();
+/// lock.unlock();
+/// let stats = lock.stats();
+/// assert_eq!(stats.acquisitions, 1);
+/// assert_eq!(stats.contended, 0);
+/// lock.reset_stats();
+/// assert_eq!(lock.stats().acquisitions, 0);
+/// ```
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LockStats {
+	/// The number of times the lock was acquired, in any mode.
+	pub acquisitions: u64,
+	/// The number of acquisitions that found the lock held.
+	pub contended: u64,
+	/// The number of times a thread went to sleep waiting for the lock.
+	pub futex_waits: u64,
+	/// The total time spent waiting by contended acquisitions.
+	pub wait_total: Duration,
+	/// The longest time a contended acquisition waited.
+	pub wait_max: Duration,
+	/// The total time the lock was held exclusively.
+	pub hold_total: Duration,
+	/// The longest time the lock was held exclusively.
+	pub hold_max: Duration,
+}
+
+/// The statistics kept in a lock. Without the `stats` feature it has no fields
+/// and all of its methods do nothing, so the locks can call them unconditionally.
+#[cfg(feature = "stats")]
+pub(crate) struct Stats {
+	acquisitions: AtomicU64,
+	contended: AtomicU64,
+	futex_waits: AtomicU64,
+	wait_total: AtomicU64,
+	wait_max: AtomicU64,
+	hold_total: AtomicU64,
+	hold_max: AtomicU64,
+	/// Only accessed by the thread holding the lock exclusively.
+	locked_at: AtomicU64,
+}
+
+#[cfg(feature = "stats")]
+impl Stats {
+	#[inline]
+	pub const fn new() -> Self {
+		Stats {
+			acquisitions: AtomicU64::new(0),
+			contended: AtomicU64::new(0),
+			futex_waits: AtomicU64::new(0),
+			wait_total: AtomicU64::new(0),
+			wait_max: AtomicU64::new(0),
+			hold_total: AtomicU64::new(0),
+			hold_max: AtomicU64::new(0),
+			locked_at: AtomicU64::new(0),
+		}
+	}
+
+	/// The time a contended acquisition starts waiting, to pass to `contended`.
+	#[inline]
+	pub fn start() -> u64 {
+		WaitTimer::now()
+	}
+
+	/// The lock was acquired exclusively.
+	#[inline]
+	pub fn locked(&self) {
+		self.acquisitions.fetch_add(1, Relaxed);
+		self.locked_at.store(WaitTimer::now(), Relaxed);
+	}
+
+	/// The lock was acquired shared.
+	#[inline]
+	pub fn locked_shared(&self) {
+		self.acquisitions.fetch_add(1, Relaxed);
+	}
+
+	/// An acquisition that started waiting at `start` got the lock.
+	pub fn contended(&self, start: u64) {
+		let wait = WaitTimer::now().saturating_sub(start);
+		self.contended.fetch_add(1, Relaxed);
+		self.wait_total.fetch_add(wait, Relaxed);
+		self.wait_max.fetch_max(wait, Relaxed);
+	}
+
+	#[inline]
+	pub fn futex_wait(&self) {
+		self.futex_waits.fetch_add(1, Relaxed);
+	}
+
+	/// The exclusive holder is about to release the lock.
+	#[inline]
+	pub fn unlocking(&self) {
+		let hold = WaitTimer::now().saturating_sub(self.locked_at.load(Relaxed));
+		self.hold_total.fetch_add(hold, Relaxed);
+		self.hold_max.fetch_max(hold, Relaxed);
+	}
+
+	pub fn snapshot(&self) -> LockStats {
+		LockStats {
+			acquisitions: self.acquisitions.load(Relaxed),
+			contended: self.contended.load(Relaxed),
+			futex_waits: self.futex_waits.load(Relaxed),
+			wait_total: Duration::from_nanos(self.wait_total.load(Relaxed)),
+			wait_max: Duration::from_nanos(self.wait_max.load(Relaxed)),
+			hold_total: Duration::from_nanos(self.hold_total.load(Relaxed)),
+			hold_max: Duration::from_nanos(self.hold_max.load(Relaxed)),
+		}
+	}
+
+	pub fn reset(&self) {
+		self.acquisitions.store(0, Relaxed);
+		self.contended.store(0, Relaxed);
+		self.futex_waits.store(0, Relaxed);
+		self.wait_total.store(0, Relaxed);
+		self.wait_max.store(0, Relaxed);
+		self.hold_total.store(0, Relaxed);
+		self.hold_max.store(0, Relaxed);
+	}
+}
+
+#[cfg(not(feature = "stats"))]
+pub(crate) struct Stats;
+
+#[cfg(not(feature = "stats"))]
+impl Stats {
+	#[inline(always)]
+	pub const fn new() -> Self {
+		Stats
+	}
+
+	#[inline(always)]
+	pub fn start() -> u64 {
+		0
+	}
+
+	#[inline(always)]
+	pub fn locked(&self) {}
+
+	#[inline(always)]
+	pub fn locked_shared(&self) {}
+
+	#[inline(always)]
+	pub fn contended(&self, _start: u64) {}
+
+	#[inline(always)]
+	pub fn futex_wait(&self) {}
+
+	#[inline(always)]
+	pub fn unlocking(&self) {}
+}
@@ -0,0 +1,381 @@
+//! Software transactional memory over `TVar`s.
+//!
+//! A transaction reads and writes any number of `TVar`s, deciding as it goes which
+//! ones to touch, and its writes become visible all at once when it commits. Reads
+//! don't lock anything for longer than it takes to clone the value: every `TVar`
+//! has a version that is bumped by every commit that writes it, and a transaction
+//! checks that the versions of everything it read are unchanged, when it reads
+//! more and when it commits. If one has changed, the transaction runs again from
+//! the start, so the closure passed to `atomically` can run more than once and
+//! shouldn't have other side effects.
+//!
+//! A transaction that can't go on with the values it sees can `retry`: it is run
+//! again once another transaction has changed one of the `TVar`s it read. `or_else`
+//! runs an alternative instead when a transaction retries.
+//!
+//! # Examples
+//!
+//! ```
+//! use spinout::stm::{atomically, retry, TVar};
+//!
+//! let from = TVar::new(100);
+//! let to = TVar::new(0);
+//! atomically(|tx| {
+//!     let balance = tx.read(&from)?;
+//!     if balance < 30 {
+//!         return retry();
+//!     }
+//!     tx.write(&from, balance - 30);
+//!     tx.modify(&to, |x| x + 30)
+//! });
+//! assert_eq!((from.get(), to.get()), (70, 30));
+//! ```
+
+use super::*;
+use std::any::Any;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64};
+use crate::futex::{futex_wait, futex_wake_all};
+use crate::raw_lock::Unlock;
+
+/// Why a transaction stopped before the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StmError {
+	/// The transaction called `retry`. It runs again once one of the `TVar`s it
+	/// read has changed.
+	Retry,
+	/// A `TVar` the transaction read has changed since. It runs again right away.
+	Conflict,
+}
+
+/// The result of a transaction or of one of its steps. Stop the transaction early
+/// by returning an error with `?`.
+pub type StmResult<T> = Result<T, StmError>;
+
+/// Give up on the transaction until one of the `TVar`s it read changes. The
+/// transaction then runs again from the start.
+#[inline]
+pub fn retry<T>() -> StmResult<T> {
+	Err(StmError::Retry)
+}
+
+/// The lock of a `TVar`: a `SpinLock`, and the version of the value, which is
+/// bumped by every commit that writes it. Commits hold the lock while they bump it,
+/// and transactions checking their reads load it without the lock.
+struct TVarLock {
+	lock: SpinLock,
+	version: AtomicU64,
+}
+
+unsafe impl RawLock for TVarLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = TVarLock {
+		lock: SpinLock::new(),
+		version: AtomicU64::new(0),
+	};
+
+	#[inline]
+	fn lock(&self) {
+		self.lock.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.lock.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.lock.unlock();
+	}
+}
+
+/// A transactional variable. It is an `Atom` with a `SpinLock` that also holds the
+/// version of the value, and clones point to the same value. Its value is read and
+/// written in transactions run with `atomically`.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::stm::{atomically, TVar};
+///
+/// let counter = TVar::new(0);
+/// let counter2 = counter.clone();
+/// std::thread::spawn(move || atomically(|tx| tx.modify(&counter2, |x| x + 1))).join().unwrap();
+/// assert_eq!(counter.get(), 1);
+/// ```
+pub struct TVar<T: Send> {
+	atom: Atom<T, TVarLock>,
+}
+
+impl<T: Clone + Send + 'static> TVar<T> {
+	/// Create a new `TVar` with the given value.
+	#[inline]
+	pub fn new(value: T) -> Self {
+		TVar {
+			atom: Atom::with_lock(value, TVarLock::INIT),
+		}
+	}
+
+	/// Read the current value, outside of any transaction.
+	#[inline]
+	pub fn get(&self) -> T {
+		self.read().0
+	}
+
+	/// A clone of the value, and the version it belongs to.
+	fn read(&self) -> (T, u64) {
+		let lock = self.atom.raw();
+		lock.lock();
+		let _unlock = Unlock(lock);
+		let value = unsafe { (*self.atom.data_ptr()).clone() };
+		(value, lock.version.load(Relaxed))
+	}
+}
+
+impl<T: Send> Clone for TVar<T> {
+	fn clone(&self) -> Self {
+		TVar {
+			atom: self.atom.clone(),
+		}
+	}
+}
+
+impl<T: Clone + Send + 'static + fmt::Debug> fmt::Debug for TVar<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("TVar").field(&self.get()).finish()
+	}
+}
+
+// Only one thread at a time accesses the value, holding the lock, so like a `Mutex`
+// `T` only needs to be `Send`.
+unsafe impl<T: Send> Send for TVar<T> {}
+unsafe impl<T: Send> Sync for TVar<T> {}
+
+/// A value written by a transaction, with its type erased.
+type Value = Box<dyn Any + Send>;
+
+/// A `TVar` with the type of its value erased, for the read and write sets.
+trait Var: Send + Sync {
+	fn lock(&self) -> &TVarLock;
+
+	fn addr(&self) -> usize;
+
+	/// Swap the value with a `Box<T>`, leaving the old value in the box.
+	///
+	/// # Safety
+	///
+	/// The lock must be held.
+	unsafe fn swap(&self, value: &mut Value);
+}
+
+impl<T: Send + 'static> Var for TVar<T> {
+	fn lock(&self) -> &TVarLock {
+		self.atom.raw()
+	}
+
+	fn addr(&self) -> usize {
+		self.atom.addr()
+	}
+
+	unsafe fn swap(&self, value: &mut Value) {
+		std::ptr::swap(self.atom.data_ptr(), value.downcast_mut::<T>().unwrap());
+	}
+}
+
+/// Bumped by every commit that writes something, for the transactions blocked in
+/// `retry` to wait on.
+static COMMITS: AtomicU32 = AtomicU32::new(0);
+static RETRYING: AtomicUsize = AtomicUsize::new(0);
+
+/// A running transaction, passed to the closure run by `atomically`.
+pub struct Tx {
+	/// The `TVar`s read, with the versions their values belonged to.
+	reads: Vec<(Box<dyn Var>, u64)>,
+	/// The values written, in order. A `TVar` written more than once has the last
+	/// value that was written.
+	writes: Vec<(Box<dyn Var>, Value)>,
+}
+
+impl Tx {
+	fn new() -> Self {
+		Tx {
+			reads: Vec::new(),
+			writes: Vec::new(),
+		}
+	}
+
+	/// Read the value of a `TVar`, as written by the transaction or as it was when
+	/// the transaction first read it. Fails with `StmError::Conflict` if a `TVar` read
+	/// before has changed since.
+	pub fn read<T: Clone + Send + 'static>(&mut self, var: &TVar<T>) -> StmResult<T> {
+		let key = var.addr();
+		if let Some((_, value)) = self.writes.iter().rev().find(|(v, _)| v.addr() == key) {
+			return Ok(value.downcast_ref::<T>().unwrap().clone());
+		}
+		let (value, version) = var.read();
+		match self.reads.iter().find(|(v, _)| v.addr() == key) {
+			Some(&(_, read)) if read != version => return Err(StmError::Conflict),
+			Some(_) => {}
+			None => self.reads.push((Box::new(var.clone()), version)),
+		}
+		// Check that what was read before is still current, so that the transaction
+		// never sees values from different commits.
+		if !self.valid() {
+			return Err(StmError::Conflict);
+		}
+		Ok(value)
+	}
+
+	/// Write a value to a `TVar`. Other transactions see it once this one commits.
+	pub fn write<T: Clone + Send + 'static>(&mut self, var: &TVar<T>, value: T) {
+		self.writes.push((Box::new(var.clone()), Box::new(value)));
+	}
+
+	/// Read the value of a `TVar` and write back the result of the given function.
+	pub fn modify<T: Clone + Send + 'static>(&mut self, var: &TVar<T>, f: impl FnOnce(T) -> T) -> StmResult<()> {
+		let value = self.read(var)?;
+		self.write(var, f(value));
+		Ok(())
+	}
+
+	/// Run `first`, and if it retries, undo its writes and run `second` instead. If
+	/// `second` retries as well, the transaction waits for a change to the `TVar`s
+	/// read by either of them.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::stm::{atomically, retry, TVar};
+	///
+	/// let queue1 = TVar::new(Vec::<i32>::new());
+	/// let queue2 = TVar::new(vec![1, 2]);
+	/// let pop = |queue: &TVar<Vec<i32>>| {
+	///     let queue = queue.clone();
+	///     move |tx: &mut spinout::stm::Tx| {
+	///         let mut items = tx.read(&queue)?;
+	///         let Some(item) = items.pop() else { return retry() };
+	///         tx.write(&queue, items);
+	///         Ok(item)
+	///     }
+	/// };
+	/// let item = atomically(|tx| tx.or_else(pop(&queue1), pop(&queue2)));
+	/// assert_eq!(item, 2);
+	/// ```
+	pub fn or_else<U>(
+		&mut self,
+		first: impl FnOnce(&mut Tx) -> StmResult<U>,
+		second: impl FnOnce(&mut Tx) -> StmResult<U>,
+	) -> StmResult<U> {
+		let writes = self.writes.len();
+		match first(self) {
+			Err(StmError::Retry) => {
+				self.writes.truncate(writes);
+				second(self)
+			}
+			result => result,
+		}
+	}
+
+	/// Returns `true` if none of the `TVar`s read has changed since.
+	fn valid(&self) -> bool {
+		valid(&self.reads)
+	}
+
+	/// Try to make the writes visible. Returns `false` if a `TVar` read has changed
+	/// since, and the transaction has to run again.
+	fn commit(self) -> bool {
+		if self.writes.is_empty() {
+			// Every read was current when the last of them was checked, so they all
+			// show the values at that moment.
+			return self.valid();
+		}
+		let Tx { reads, mut writes } = self;
+		// Keep only the last value written to each `TVar`: sorting is stable, so
+		// after reversing, the last write of a `TVar` is the first of its run.
+		writes.reverse();
+		writes.sort_by_key(|(v, _)| v.addr());
+		writes.dedup_by_key(|(v, _)| v.addr());
+		let (written, mut values): (Vec<Box<dyn Var>>, Vec<Value>) = writes.into_iter().unzip();
+		let mut vars: Vec<&dyn Var> = reads.iter().map(|(v, _)| &**v)
+			.chain(written.iter().map(|v| &**v))
+			.collect();
+		// Lock all the `TVar`s in the order of their addresses, like `lock_all`.
+		vars.sort_unstable_by_key(|v| v.addr());
+		vars.dedup_by_key(|v| v.addr());
+		for var in &vars {
+			var.lock().lock();
+		}
+		let valid = valid(&reads);
+		if valid {
+			// The old values are swapped into `values`, and only dropped once the
+			// `TVar`s are unlocked, so that a panicking `Drop` can't keep them locked.
+			for (var, value) in written.iter().zip(&mut values) {
+				unsafe { var.swap(value) };
+				var.lock().version.fetch_add(1, Release);
+			}
+		}
+		for var in &vars {
+			var.lock().unlock();
+		}
+		if valid {
+			// Pairs with `wait`, so that either the waiter sees the new count or the
+			// commit sees the waiter.
+			COMMITS.fetch_add(1, SeqCst);
+			if RETRYING.load(SeqCst) > 0 {
+				futex_wake_all(&COMMITS);
+			}
+		}
+		drop(values);
+		valid
+	}
+
+	/// Wait until one of the `TVar`s read has changed. `COMMITS` changes with every
+	/// commit, so the reads are checked again after every wake up.
+	fn wait(&self) {
+		RETRYING.fetch_add(1, SeqCst);
+		loop {
+			let commits = COMMITS.load(SeqCst);
+			if !self.valid() {
+				break;
+			}
+			futex_wait(&COMMITS, commits, None);
+		}
+		RETRYING.fetch_sub(1, Relaxed);
+	}
+}
+
+/// Returns `true` if none of the `TVar`s read has changed since.
+fn valid(reads: &[(Box<dyn Var>, u64)]) -> bool {
+	reads.iter().all(|(var, version)| var.lock().version.load(Acquire) == *version)
+}
+
+/// Run a transaction. The closure reads and writes `TVar`s through the `Tx`, and its
+/// writes become visible all at once when it returns `Ok`. The closure is run again
+/// if another transaction changed a `TVar` it read, or if it returns
+/// `StmError::Retry`, in which case it runs again after such a change.
+///
+/// # Examples
+///
+/// ```
+/// use spinout::stm::{atomically, TVar};
+///
+/// let a = TVar::new(1);
+/// let b = TVar::new(2);
+/// let sum = atomically(|tx| Ok(tx.read(&a)? + tx.read(&b)?));
+/// assert_eq!(sum, 3);
+/// ```
+pub fn atomically<U>(mut f: impl FnMut(&mut Tx) -> StmResult<U>) -> U {
+	loop {
+		let mut tx = Tx::new();
+		match f(&mut tx) {
+			Ok(result) => {
+				if tx.commit() {
+					return result;
+				}
+			}
+			Err(StmError::Conflict) => {}
+			Err(StmError::Retry) => tx.wait(),
+		}
+	}
+}
@@ -0,0 +1,159 @@
+use super::*;
+use std::sync::atomic::AtomicU32;
+use std::hint::spin_loop;
+use crate::futex::{futex_wait, futex_wake_all};
+use crate::owner::Owner;
+
+pub struct TicketLock {
+	next_ticket: AtomicU32,
+	now_serving: AtomicU32,
+	owner: Owner,
+}
+
+impl TicketLock {
+
+	/// Create a new `TicketLock`. A ticket lock hands out tickets to the threads
+	/// that want to lock it and serves them in the order they arrived, so unlike
+	/// `SpinLock` a thread can't starve while others keep re-acquiring the lock.
+	///
+	/// Waiting threads spin for a while and then sleep on the futex of the ticket
+	/// being served. All of them are woken up when it changes, so with many
+	/// waiters `McsLock`, where every waiter sleeps on its own word, scales better.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::TicketLock;
+	///
+	/// let lock = TicketLock::new();
+	/// ```
+	#[inline]
+	pub const fn new() -> Self {
+		TicketLock {
+			next_ticket: AtomicU32::new(0),
+			now_serving: AtomicU32::new(0),
+			owner: Owner::new(),
+		}
+	}
+
+	/// Lock the `TicketLock`. This is a blocking operation. The calling thread takes
+	/// a ticket and waits until all the threads that arrived before it have released
+	/// the lock.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::TicketLock;
+	/// use std::sync::Arc;
+	///
+	/// let lock = Arc::new(TicketLock::new());
+	/// let lock2 = lock.clone();
+	///
+	/// let t = std::thread::spawn(move || {
+	///     lock2.lock();
+	///     // do something
+	///     lock2.unlock();
+	/// });
+	///
+	/// lock.lock();
+	/// // do something
+	/// lock.unlock();
+	/// t.join().unwrap();
+	/// ```
+	#[inline]
+	pub fn lock(&self) {
+		// Check before taking a ticket, as a ticket that is never served would
+		// leave the lock to no one once the holder unlocks it.
+		self.owner.check("TicketLock");
+		// SeqCst pairs with `unlock`, so that either the waiter sees its turn or the
+		// holder sees the waiter.
+		let ticket = self.next_ticket.fetch_add(1, SeqCst);
+		if self.now_serving.load(Acquire) != ticket {
+			self.lock_contended(ticket);
+		}
+		self.owner.locked();
+	}
+
+	#[cold]
+	fn lock_contended(&self, ticket: u32) {
+		let mut spin = 0u32;
+		loop {
+			let serving = self.now_serving.load(Acquire);
+			if serving == ticket {
+				return;
+			}
+			// Sleep once we've spun for a while, as the holder and the threads
+			// ahead of us in the queue may need our core to make progress.
+			if spin < 100 {
+				spin_loop();
+				spin += 1;
+			} else {
+				futex_wait(&self.now_serving, serving, None);
+			}
+		}
+	}
+
+	/// Try to lock the `TicketLock` without blocking. Only succeeds if the lock is
+	/// free and no other thread is waiting for it.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use spinout::TicketLock;
+	///
+	/// let lock = TicketLock::new();
+	/// assert!(lock.try_lock());
+	/// assert!(!lock.try_lock());
+	/// lock.unlock();
+	/// ```
+	#[inline]
+	pub fn try_lock(&self) -> bool {
+		let serving = self.now_serving.load(Relaxed);
+		let locked = self.next_ticket
+			.compare_exchange(serving, serving.wrapping_add(1), Acquire, Relaxed)
+			.is_ok();
+		if locked {
+			self.owner.locked();
+		}
+		locked
+	}
+
+	/// Unlock the `TicketLock`, handing it to the next thread in line.
+	#[inline]
+	pub fn unlock(&self) {
+		self.owner.unlocking();
+		// Only the holder writes `now_serving`, so this doesn't need to be atomic.
+		let serving = self.now_serving.load(Relaxed).wrapping_add(1);
+		self.now_serving.store(serving, SeqCst);
+		// Someone took a ticket after ours, and might be sleeping.
+		if self.next_ticket.load(SeqCst) != serving {
+			futex_wake_all(&self.now_serving);
+		}
+	}
+}
+
+impl Default for TicketLock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+unsafe impl RawLock for TicketLock {
+	#[allow(clippy::declare_interior_mutable_const)]
+	const INIT: Self = TicketLock::new();
+
+	#[inline]
+	fn lock(&self) {
+		self.lock();
+	}
+
+	#[inline]
+	fn try_lock(&self) -> bool {
+		self.try_lock()
+	}
+
+	#[inline]
+	fn unlock(&self) {
+		self.unlock();
+	}
+}
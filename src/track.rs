@@ -0,0 +1,129 @@
+#[cfg(feature = "registry")]
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering::Relaxed};
+#[cfg(feature = "registry")]
+use std::time::Duration;
+#[cfg(feature = "registry")]
+use crate::fairness::WaitTimer;
+
+/// The owner and waiters of a named lock, for `registry::dump`. Tracking is only
+/// switched on for locks that are registered. Without the `registry` feature it
+/// has no fields and all of its methods do nothing, like `Stats`.
+#[cfg(feature = "registry")]
+pub(crate) struct Track {
+	enabled: AtomicBool,
+	/// The thread id of the exclusive holder, or 0.
+	owner: AtomicU32,
+	/// When the lock was acquired, plus one so that 0 means not held.
+	since: AtomicU64,
+	waiters: AtomicU32,
+}
+
+#[cfg(feature = "registry")]
+impl Track {
+	#[inline]
+	pub const fn new() -> Self {
+		Track {
+			enabled: AtomicBool::new(false),
+			owner: AtomicU32::new(0),
+			since: AtomicU64::new(0),
+			waiters: AtomicU32::new(0),
+		}
+	}
+
+	pub fn enable(&self) {
+		self.enabled.store(true, Relaxed);
+	}
+
+	/// The lock was acquired exclusively.
+	#[inline]
+	pub fn locked(&self) {
+		if self.enabled.load(Relaxed) {
+			self.owner.store(crate::registry::current_thread(), Relaxed);
+			self.since.store(WaitTimer::now() + 1, Relaxed);
+		}
+	}
+
+	/// The lock was acquired shared. The time is that of the first reader.
+	#[inline]
+	pub fn locked_shared(&self) {
+		if self.enabled.load(Relaxed) {
+			let _ = self.since.compare_exchange(0, WaitTimer::now() + 1, Relaxed, Relaxed);
+		}
+	}
+
+	/// The exclusive holder, or the last reader, is about to release the lock.
+	#[inline]
+	pub fn unlocking(&self) {
+		if self.enabled.load(Relaxed) {
+			self.owner.store(0, Relaxed);
+			self.since.store(0, Relaxed);
+		}
+	}
+
+	/// A thread found the lock held and starts waiting for it, until it gets the
+	/// lock or gives up and drops the returned guard.
+	#[inline]
+	pub fn waiting(&self) -> Waiting<'_> {
+		let enabled = self.enabled.load(Relaxed);
+		if enabled {
+			self.waiters.fetch_add(1, Relaxed);
+		}
+		Waiting(self, enabled)
+	}
+
+	pub fn owner(&self) -> Option<u32> {
+		Some(self.owner.load(Relaxed)).filter(|&tid| tid != 0)
+	}
+
+	pub fn waiters(&self) -> u32 {
+		self.waiters.load(Relaxed)
+	}
+
+	pub fn held_for(&self) -> Option<Duration> {
+		match self.since.load(Relaxed) {
+			0 => None,
+			since => Some(Duration::from_nanos((WaitTimer::now() + 1).saturating_sub(since))),
+		}
+	}
+}
+
+#[cfg(feature = "registry")]
+pub(crate) struct Waiting<'a>(&'a Track, bool);
+
+#[cfg(feature = "registry")]
+impl Drop for Waiting<'_> {
+	#[inline]
+	fn drop(&mut self) {
+		if self.1 {
+			self.0.waiters.fetch_sub(1, Relaxed);
+		}
+	}
+}
+
+#[cfg(not(feature = "registry"))]
+pub(crate) struct Track;
+
+#[cfg(not(feature = "registry"))]
+pub(crate) struct Waiting;
+
+#[cfg(not(feature = "registry"))]
+impl Track {
+	#[inline(always)]
+	pub const fn new() -> Self {
+		Track
+	}
+
+	#[inline(always)]
+	pub fn locked(&self) {}
+
+	#[inline(always)]
+	pub fn locked_shared(&self) {}
+
+	#[inline(always)]
+	pub fn unlocking(&self) {}
+
+	#[inline(always)]
+	pub fn waiting(&self) -> Waiting {
+		Waiting
+	}
+}